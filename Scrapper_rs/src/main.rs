@@ -1,168 +1,163 @@
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
-use encoding::{all::WINDOWS_1252, EncoderTrap};
-use encoding::{DecoderTrap, Encoding};
-use std::{
-    collections::VecDeque,
-    convert::TryInto,
-    fs::{self, File},
-    io::Seek,
-    path::PathBuf,
+use anyhow::{bail, Context, Result};
+use glob::{MatchOptions, Pattern};
+use packed::{
+    diff::{Listing, Status},
+    repack::{folder_files, Change, RepackPlan},
+    Header,
 };
-
-use std::{fs::create_dir_all, io::SeekFrom};
-use structopt::{StructOpt, paw};
 use std::{
-    io::{BufReader, BufWriter, Read, Write},
-    path::Path,
+    fs::File,
+    io::{self, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
 };
+use structopt::{paw, StructOpt};
 
 #[derive(StructOpt)]
 #[structopt(about = "Scrapland .packed packer and unpacker")]
 enum Args {
     /// Unpack .packed file
     Unpack {
-        destination_folder: PathBuf,
+        /// Folder to extract to, a subfolder is created for each archive
+        #[structopt(
+            short,
+            long,
+            required_unless_one = &["list", "stdout"],
+            conflicts_with_all = &["list", "stdout"]
+        )]
+        output: Option<PathBuf>,
+        #[structopt(required = true)]
         packed_files: Vec<PathBuf>,
+        /// Only extract entries matching one of these globs (e.g. "levels/*/map/map3d.emi")
+        #[structopt(short, long)]
+        include: Vec<Pattern>,
+        /// Skip entries matching one of these globs
+        #[structopt(short, long)]
+        exclude: Vec<Pattern>,
+        /// List matching entries instead of extracting them
+        #[structopt(short, long)]
+        list: bool,
+        /// Write the single matching entry to stdout
+        #[structopt(long, conflicts_with = "list")]
+        stdout: bool,
     },
     /// Repack .packed file
     Repack {
         input_folder: PathBuf,
-        destination_folder: PathBuf
-    }
+        destination_folder: PathBuf,
+    },
+    /// Repack a modified folder on top of an existing .packed file, keeping the layout
+    /// of unchanged files and only rewriting what changed
+    Update {
+        original: PathBuf,
+        input_folder: PathBuf,
+        destination: PathBuf,
+    },
+    /// Compare two .packed files (or extracted folders) and list changed entries
+    Diff {
+        old: PathBuf,
+        new: PathBuf,
+        /// Also list unchanged entries
+        #[structopt(short, long)]
+        all: bool,
+    },
 }
-#[derive(Debug, PartialEq, Eq)]
-struct FileEntry {
-    path: String,
-    offset: u32,
-    size: u32,
+
+struct Filter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+impl Filter {
+    const OPTIONS: MatchOptions = MatchOptions {
+        case_sensitive: false,
+        require_literal_separator: true,
+        require_literal_leading_dot: false,
+    };
+
+    fn matches(&self, path: &str) -> bool {
+        let path = path.replace('\\', "/");
+        let matches = |pattern: &Pattern| pattern.matches_with(&path, Self::OPTIONS);
+        (self.include.is_empty() || self.include.iter().any(matches))
+            && !self.exclude.iter().any(matches)
+    }
 }
+
 #[derive(Debug, PartialEq, Eq)]
 struct Packed {
     path: PathBuf,
     ext_path: Option<PathBuf>,
-    magic: [u8; 4],
-    version: [u8; 4],
-    files: Vec<FileEntry>,
+    header: Header,
 }
 
 impl Packed {
-    fn from_file(filename: &PathBuf) -> std::io::Result<Packed> {
+    fn from_file(filename: &Path) -> Result<Packed> {
         let mut fh = BufReader::new(File::open(filename)?);
-        let magic: [u8; 4] = [fh.read_u8()?, fh.read_u8()?, fh.read_u8()?, fh.read_u8()?];
-        let version: [u8; 4] = [fh.read_u8()?, fh.read_u8()?, fh.read_u8()?, fh.read_u8()?];
-        println!("Magic: {:?}", magic);
-        println!("Version: {:?}", version);
-        let num_files = fh.read_u32::<LittleEndian>()?;
-        println!("{} files", num_files);
-        let mut files: Vec<FileEntry> = Vec::new();
-        for _ in 0..num_files {
-            let name_len = fh.read_u32::<LittleEndian>()?;
-            let mut name = vec![0; name_len as usize];
-            fh.read_exact(&mut name)?;
-            let size = fh.read_u32::<LittleEndian>()?;
-            let offset = fh.read_u32::<LittleEndian>()?;
-            let path = WINDOWS_1252.decode(&name, DecoderTrap::Strict).unwrap();
-            files.push(FileEntry { path, offset, size });
-        }
+        let header = Header::read(&mut fh)?;
+        header
+            .validate(filename.metadata()?.len())
+            .with_context(|| filename.display().to_string())?;
         Ok(Packed {
             path: filename.to_owned(),
-            magic,
-            version,
-            files,
+            header,
             ext_path: None,
         })
     }
-    fn size(&self) -> (u64, u64) {
-        let mut header_size: u64 = 4 * 3; // Magic+Version+Num files
-        let mut data_size: u64 = 0;
-        for entry in &self.files {
-            header_size += 4 * 3; // Name length, Offset, Size
-            let path = WINDOWS_1252
-                .encode(&entry.path, EncoderTrap::Strict)
-                .unwrap();
-            let hs: u64 = path.len().try_into().unwrap();
-            header_size += hs;
-            data_size += entry.size as u64;
-        }
-        (header_size, data_size)
-    }
 
-    fn from_folder(folder: PathBuf) -> std::io::Result<Self> {
-        let mut queue = VecDeque::new();
-        queue.push_back(folder.clone());
-        let mut header = Packed {
+    fn from_folder(folder: PathBuf) -> Result<Self> {
+        let files = folder_files(&folder)?
+            .into_iter()
+            .map(|(path, _, size)| (path, size));
+        Ok(Packed {
             path: PathBuf::new(),
-            magic: [0x42, 0x46, 0x50, 0x4B], // BFPK
-            version: [0x00, 0x00, 0x00, 0x00],
-            files: vec![],
-            ext_path: Some(folder.clone()),
-        };
-        while let Some(dir) = queue.pop_front() {
-            for entry in fs::read_dir(dir)? {
-                let entry = entry?;
-                let path = entry.path();
-                if path.is_dir() {
-                    queue.push_back(path);
-                } else {
-                    let size = path.metadata().unwrap().len().try_into().unwrap();
-                    header.files.push(FileEntry {
-                        path: path
-                            .strip_prefix(folder.clone())
-                            .unwrap()
-                            .to_string_lossy()
-                            .replace("\\", "/"),
-                        offset: 0,
-                        size,
-                    });
-                }
-            }
-        }
-        let mut offset = header.size().0.try_into().unwrap();
-        for entry in header.files.iter_mut() {
-            entry.offset = offset;
-            offset += entry.size;
-        }
-        Ok(header)
+            header: Header::from_files(files)?,
+            ext_path: Some(folder),
+        })
     }
 
-    fn write(&self, out_path: &PathBuf) -> std::io::Result<()> {
+    fn write(&self, out_path: &Path) -> Result<()> {
         let base_path = self.ext_path.clone().unwrap();
         let mut outfile = BufWriter::new(File::create(out_path)?);
-        outfile.write_all(&self.magic)?;
-        outfile.write_all(&self.version)?;
-        outfile.write_u32::<LittleEndian>(self.files.len() as u32)?;
-        println!("Building header");
-        for entry in &self.files {
-            let path = WINDOWS_1252
-                .encode(&entry.path, EncoderTrap::Strict)
-                .unwrap();
-            outfile.write_u32::<LittleEndian>(path.len().try_into().unwrap())?;
-            outfile.write_all(&path)?;
-            outfile.write_u32::<LittleEndian>(entry.size)?;
-            outfile.write_u32::<LittleEndian>(entry.offset)?;
-        }
-        let total = self.files.len();
-        for (n, entry) in self.files.iter().enumerate() {
+        let total = self.header.files.len();
+        let mut n = 0;
+        self.header.write(&mut outfile, |entry| {
+            n += 1;
             println!(
                 "[{}/{}] Writing: {} (offset: {}, size: {})",
-                n + 1,
-                total,
-                entry.path,
-                entry.offset,
-                entry.size
+                n, total, entry.path, entry.offset, entry.size
             );
-            let mut fh = BufReader::new(File::open(base_path.join(&entry.path))?);
-            std::io::copy(&mut fh, &mut outfile)?;
-        }
+            Ok(BufReader::new(File::open(base_path.join(&entry.path))?))
+        })?;
+        outfile.flush()?;
         Ok(())
     }
 
-    fn extract(&mut self, ext_folder: &PathBuf) -> std::io::Result<()> {
-        let total = self.files.len();
-        let ext_folder = ext_folder.join(&Path::new(&self.path).file_name().unwrap());
-        println!("Extracting to {}", ext_folder.to_string_lossy());
-        let mut fh = File::open(self.path.clone())?;
-        for (n, entry) in self.files.iter().enumerate() {
+    /// Drops all entries not matched by `filter`
+    fn filter(&mut self, filter: &Filter) {
+        self.header
+            .files
+            .retain(|entry| filter.matches(&entry.path));
+    }
+
+    /// Path and SHA-256 of every entry, `path` can be an archive or a folder
+    fn listing(path: &Path) -> Result<Listing> {
+        if path.is_dir() {
+            return Listing::from_folder(path);
+        }
+        let pkd = Packed::from_file(path)?;
+        let mut fh = BufReader::new(File::open(path)?);
+        Listing::from_archive(&pkd.header, &mut fh)
+    }
+
+    fn extract(&mut self, ext_folder: &Path) -> Result<()> {
+        let total = self.header.files.len();
+        let ext_folder = ext_folder.join(self.path.file_name().unwrap());
+        println!(
+            "Extracting {} files to {}",
+            total,
+            ext_folder.to_string_lossy()
+        );
+        let mut fh = BufReader::new(File::open(&self.path)?);
+        self.header.extract_all(&mut fh, &ext_folder, |n, entry| {
             println!(
                 "[{}/{}] Extracting: {} (offset: {}, size: {})",
                 n + 1,
@@ -171,32 +166,135 @@ impl Packed {
                 entry.offset,
                 entry.size
             );
-            fh.seek(SeekFrom::Start(entry.offset.try_into().unwrap()))?;
-            let mut data = vec![0; entry.size.try_into().unwrap()];
-            let path: PathBuf = PathBuf::from(&entry.path);
-            let file = Path::new(&ext_folder).join(path);
-            fh.read_exact(&mut data)?;
-            create_dir_all(file.parent().unwrap())?;
-            let mut fh = BufWriter::new(File::create(file)?);
-            fh.write_all(&data)?;
-        }
+        })?;
         self.ext_path = Some(ext_folder);
         Ok(())
     }
 }
 
 #[paw::main]
-fn main(args: Args) -> std::io::Result<()> {
+fn main(args: Args) -> Result<()> {
     match args {
-        Args::Unpack { packed_files, destination_folder } => {
+        Args::Unpack {
+            packed_files,
+            output,
+            include,
+            exclude,
+            list,
+            stdout,
+        } => {
+            let filter = Filter { include, exclude };
+            let mut archives = vec![];
             for packed_file in &packed_files {
-                let mut pkd = Packed::from_file(&packed_file)?;
-                pkd.extract(&destination_folder)?;
+                let mut pkd = Packed::from_file(packed_file)?;
+                pkd.filter(&filter);
+                archives.push(pkd);
+            }
+            if list {
+                let width = archives
+                    .iter()
+                    .flat_map(|pkd| &pkd.header.files)
+                    .map(|entry| entry.path.len())
+                    .max()
+                    .unwrap_or(0);
+                println!("{:<width$} {:>10} {:>10} Archive", "Path", "Size", "Offset");
+                for pkd in &archives {
+                    for entry in &pkd.header.files {
+                        println!(
+                            "{:<width$} {:>10} {:>10} {}",
+                            entry.path,
+                            entry.size,
+                            entry.offset,
+                            pkd.path.display()
+                        );
+                    }
+                }
+            } else if stdout {
+                // Archives loaded first take precedence, same as in game
+                let mut entries = archives
+                    .iter()
+                    .flat_map(|pkd| pkd.header.files.iter().map(move |entry| (pkd, entry)));
+                let Some((pkd, entry)) = entries.next() else {
+                    bail!("No entry matches");
+                };
+                if let Some((_, other)) =
+                    entries.find(|(_, other)| !other.path.eq_ignore_ascii_case(&entry.path))
+                {
+                    bail!(
+                        "Multiple entries match: {}, {}, ...",
+                        entry.path,
+                        other.path
+                    );
+                }
+                let mut fh = BufReader::new(File::open(&pkd.path)?);
+                let mut out = io::stdout().lock();
+                io::copy(&mut entry.open(&mut fh)?, &mut out)?;
+                out.flush()?;
+            } else if let Some(output) = output {
+                for mut pkd in archives {
+                    pkd.extract(&output)?;
+                }
             }
         }
-        Args::Repack { input_folder, destination_folder } => {
+        Args::Repack {
+            input_folder,
+            destination_folder,
+        } => {
             Packed::from_folder(input_folder)?.write(&destination_folder)?;
         }
+        Args::Update {
+            original,
+            input_folder,
+            destination,
+        } => {
+            if original.canonicalize()? == destination.canonicalize().unwrap_or_default() {
+                anyhow::bail!("Refusing to overwrite {}", original.display());
+            }
+            let header = Packed::from_file(&original)?.header;
+            let mut fh = BufReader::new(File::open(&original)?);
+            let plan = RepackPlan::new(&header, &mut fh, &input_folder)?;
+            for (path, change) in &plan.changes {
+                if *change != Change::Unchanged {
+                    println!("{:?}: {}", change, path);
+                }
+            }
+            let mut outfile = BufWriter::new(File::create(&destination)?);
+            plan.write(&mut fh, &mut outfile)?;
+            outfile.flush()?;
+        }
+        Args::Diff { old, new, all } => {
+            let diff = Packed::listing(&old)?.diff(&Packed::listing(&new)?);
+            let mut counts = [0usize; 5];
+            for entry in &diff {
+                counts[entry.status as usize] += 1;
+                let (old, new) = (entry.old.as_ref(), entry.new.as_ref());
+                match (entry.status, old, new) {
+                    (Status::Added, _, Some(new)) => {
+                        println!("+ {} ({} bytes, {})", new.path, new.size, new.sha256)
+                    }
+                    (Status::Removed, Some(old), _) => {
+                        println!("- {} ({} bytes, {})", old.path, old.size, old.sha256)
+                    }
+                    (Status::Resized, Some(old), Some(new)) => println!(
+                        "R {} ({} -> {} bytes, {} -> {})",
+                        new.path, old.size, new.size, old.sha256, new.sha256
+                    ),
+                    (Status::Changed, Some(old), Some(new)) => println!(
+                        "M {} ({} bytes, {} -> {})",
+                        new.path, new.size, old.sha256, new.sha256
+                    ),
+                    (Status::Unchanged, _, Some(new)) if all => {
+                        println!("= {} ({} bytes, {})", new.path, new.size, new.sha256)
+                    }
+                    _ => (),
+                }
+            }
+            let [unchanged, added, removed, resized, changed] = counts;
+            println!(
+                "{} added, {} removed, {} resized, {} changed, {} unchanged",
+                added, removed, resized, changed, unchanged
+            );
+        }
     }
     Ok(())
 }
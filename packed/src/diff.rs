@@ -0,0 +1,161 @@
+//! Comparison of archive (or extracted folder) contents
+use crate::{normalize_path, repack::folder_files, Header};
+use anyhow::Result;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek};
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FileInfo {
+    pub path: String,
+    pub size: u32,
+    /// Hex encoded SHA-256 of the data
+    pub sha256: String,
+}
+
+/// Contents of one side of a comparison, keyed by normalized path
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Listing(pub BTreeMap<String, FileInfo>);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Unchanged,
+    Added,
+    Removed,
+    /// Size differs (which implies different content)
+    Resized,
+    /// Same size, different content
+    Changed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Difference {
+    pub status: Status,
+    pub old: Option<FileInfo>,
+    pub new: Option<FileInfo>,
+}
+
+impl Difference {
+    pub fn path(&self) -> &str {
+        match (&self.new, &self.old) {
+            (Some(info), _) | (None, Some(info)) => &info.path,
+            (None, None) => "",
+        }
+    }
+}
+
+fn sha256<R: Read>(mut reader: R) -> Result<String> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut reader, &mut hasher)?;
+    let mut hash = String::with_capacity(64);
+    for byte in hasher.finalize() {
+        write!(hash, "{byte:02x}")?;
+    }
+    Ok(hash)
+}
+
+impl Listing {
+    pub fn from_archive<R: Read + Seek>(header: &Header, reader: &mut R) -> Result<Self> {
+        let mut files = BTreeMap::new();
+        for entry in &header.files {
+            // Same as the game, the first entry for a path wins
+            files
+                .entry(normalize_path(&entry.path))
+                .or_insert(FileInfo {
+                    path: entry.path.clone(),
+                    size: entry.size,
+                    sha256: sha256(entry.open(reader)?)?,
+                });
+        }
+        Ok(Self(files))
+    }
+
+    pub fn from_folder(folder: &Path) -> Result<Self> {
+        let mut files = BTreeMap::new();
+        for (name, path, size) in folder_files(folder)? {
+            let sha256 = sha256(BufReader::new(File::open(&path)?))?;
+            files.insert(
+                normalize_path(&name),
+                FileInfo {
+                    path: name,
+                    size,
+                    sha256,
+                },
+            );
+        }
+        Ok(Self(files))
+    }
+
+    /// Compares `self` (old) against `new`, sorted by normalized path
+    pub fn diff(&self, new: &Listing) -> Vec<Difference> {
+        let mut keys: Vec<&String> = self.0.keys().chain(new.0.keys()).collect();
+        keys.sort();
+        keys.dedup();
+        keys.into_iter()
+            .map(|key| {
+                let old = self.0.get(key);
+                let new = new.0.get(key);
+                let status = match (old, new) {
+                    (None, _) => Status::Added,
+                    (_, None) => Status::Removed,
+                    (Some(old), Some(new)) if old.size != new.size => Status::Resized,
+                    (Some(old), Some(new)) if old.sha256 != new.sha256 => Status::Changed,
+                    _ => Status::Unchanged,
+                };
+                Difference {
+                    status,
+                    old: old.cloned(),
+                    new: new.cloned(),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::archive;
+
+    fn listing(files: &[(&str, &[u8])]) -> Listing {
+        let (header, mut data) = archive(files);
+        Listing::from_archive(&header, &mut data).unwrap()
+    }
+
+    #[test]
+    fn diff() {
+        let old = listing(&[
+            ("a.txt", b"a"),
+            ("B.txt", b"bb"),
+            ("c.txt", b"c"),
+            ("d", b""),
+        ]);
+        let new = listing(&[
+            ("b.txt", b"bbb"),
+            ("a.txt", b"a"),
+            ("c.txt", b"C"),
+            ("e", b""),
+        ]);
+        let diff = old.diff(&new);
+        let status: Vec<(&str, Status)> = diff.iter().map(|d| (d.path(), d.status)).collect();
+        assert_eq!(
+            status,
+            [
+                ("a.txt", Status::Unchanged),
+                ("b.txt", Status::Resized),
+                ("c.txt", Status::Changed),
+                ("d", Status::Removed),
+                ("e", Status::Added),
+            ]
+        );
+        assert_eq!(
+            diff[3].old.as_ref().unwrap().sha256,
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+}
@@ -0,0 +1,209 @@
+//! Reader and writer for Scrapland `.packed` (BFPK) archives
+//!
+//! ```text
+//! magic: b"BFPK"
+//! version: u32 (always 0)
+//! num_files: u32
+//! files: [{path_len: u32, path: [u8; path_len] (Latin-1), size: u32, offset: u32}; num_files]
+//! data
+//! ```
+use anyhow::{anyhow, bail, Result};
+use binrw::prelude::*;
+use serde::Serialize;
+use std::io::{Read, Seek, SeekFrom, Take, Write};
+use std::path::Path;
+
+pub mod diff;
+#[cfg(feature = "vfs")]
+pub mod packed_vfs;
+pub mod repack;
+#[cfg(test)]
+mod test_util;
+mod tree;
+mod validate;
+
+pub use tree::DirectoryTree;
+pub use validate::{is_safe_path, Problem, ValidationError};
+
+/// Decodes Latin-1 (every byte maps to the code point of the same value)
+pub fn decode_latin1(data: &[u8]) -> String {
+    data.iter().copied().map(char::from).collect()
+}
+
+pub fn encode_latin1(string: &str) -> Result<Vec<u8>> {
+    string
+        .chars()
+        .map(|c| {
+            u8::try_from(c)
+                .map_err(|_| anyhow!("Character {c:?} in {string:?} can't be encoded as Latin-1"))
+        })
+        .collect()
+}
+
+/// Normalized lookup key for archive paths, the game treats them case-insensitively
+pub fn normalize_path(path: &str) -> String {
+    path.replace('\\', "/")
+        .trim_start_matches('/')
+        .to_ascii_lowercase()
+}
+
+#[binrw]
+#[brw(little)]
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    #[br(temp)]
+    #[bw(try_calc = u32::try_from(path.chars().count()))]
+    path_len: u32,
+    #[br(count = path_len, map = |data: Vec<u8>| decode_latin1(&data))]
+    #[bw(try_map = |path: &String| encode_latin1(path).map_err(|e| e.to_string()))]
+    pub path: String,
+    pub size: u32,
+    pub offset: u32,
+}
+
+impl Entry {
+    /// Seeks to the data of this entry and returns a reader limited to its size
+    pub fn open<'a, R: Read + Seek>(&self, reader: &'a mut R) -> Result<Take<&'a mut R>> {
+        reader.seek(SeekFrom::Start(self.offset.into()))?;
+        Ok(reader.take(self.size.into()))
+    }
+}
+
+#[binrw]
+#[brw(little, magic = b"BFPK")]
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Header {
+    #[br(temp, assert(version == 0, "Unsupported .packed version: {}", version))]
+    #[bw(calc = 0)]
+    version: u32,
+    #[br(temp)]
+    #[bw(try_calc = u32::try_from(files.len()))]
+    num_files: u32,
+    #[br(count = num_files)]
+    pub files: Vec<Entry>,
+}
+
+impl Header {
+    pub fn read<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        Ok(reader.read_le()?)
+    }
+
+    /// Lays out `files` (path and size) back to back after the header
+    pub fn from_files<I: IntoIterator<Item = (String, u32)>>(files: I) -> Result<Self> {
+        let mut header = Self {
+            files: files
+                .into_iter()
+                .map(|(path, size)| Entry {
+                    path,
+                    size,
+                    offset: 0,
+                })
+                .collect(),
+        };
+        let mut offset = header.size()?;
+        for entry in &mut header.files {
+            entry.offset = u32::try_from(offset)
+                .map_err(|_| anyhow!("Archive too large, {} starts at {offset}", entry.path))?;
+            offset += u64::from(entry.size);
+        }
+        if u32::try_from(offset).is_err() {
+            bail!("Archive too large: {offset} bytes");
+        }
+        Ok(header)
+    }
+
+    /// Size of the encoded header in bytes
+    pub fn size(&self) -> Result<u64> {
+        // Magic + version + number of files
+        let mut size = 4 * 3;
+        for entry in &self.files {
+            // Path length + path + size + offset
+            size += 4 * 3 + u64::try_from(encode_latin1(&entry.path)?.len())?;
+        }
+        Ok(size)
+    }
+
+    pub fn find(&self, path: &str) -> Option<&Entry> {
+        let path = normalize_path(path);
+        self.files
+            .iter()
+            .find(|entry| normalize_path(&entry.path) == path)
+    }
+
+    /// Writes the header followed by the data of each entry, `open` is called once
+    /// per entry in order and has to yield exactly `entry.size` bytes
+    pub fn write<W: Write + Seek, R: Read, F: FnMut(&Entry) -> Result<R>>(
+        &self,
+        writer: &mut W,
+        mut open: F,
+    ) -> Result<()> {
+        writer.write_le(self)?;
+        let mut pos = self.size()?;
+        for entry in &self.files {
+            if pos != u64::from(entry.offset) {
+                bail!(
+                    "{} is expected at offset {}, writer is at {pos}",
+                    entry.path,
+                    entry.offset
+                );
+            }
+            let written = std::io::copy(&mut open(entry)?.take(entry.size.into()), writer)?;
+            if written != u64::from(entry.size) {
+                bail!("{} is {} bytes, only got {written}", entry.path, entry.size);
+            }
+            pos += written;
+        }
+        Ok(())
+    }
+
+    /// Extracts all entries below `dest`, calling `progress` before each one
+    ///
+    /// The header is validated first, nothing is written for broken or malicious archives.
+    pub fn extract_all<R: Read + Seek, F: FnMut(usize, &Entry)>(
+        &self,
+        reader: &mut R,
+        dest: &Path,
+        mut progress: F,
+    ) -> Result<()> {
+        self.validate(reader.seek(SeekFrom::End(0))?)?;
+        for (n, entry) in self.files.iter().enumerate() {
+            progress(n, entry);
+            let path = dest.join(&entry.path);
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let mut fh = std::io::BufWriter::new(std::fs::File::create(&path)?);
+            std::io::copy(&mut entry.open(reader)?, &mut fh)?;
+            fh.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::archive;
+    use std::io::Cursor;
+
+    #[test]
+    fn roundtrip() {
+        let (header, out) = archive(&[("data/Caf\u{e9}.txt", b"abc"), ("x.ini", b"[a]")]);
+        let data = out.into_inner();
+        // name is stored as Latin-1, one byte per character
+        assert_eq!(&data[12..16], &13u32.to_le_bytes());
+        assert_eq!(data[12 + 4 + 8], 0xe9);
+
+        let mut reader = Cursor::new(data);
+        let parsed = Header::read(&mut reader).unwrap();
+        assert_eq!(parsed, header);
+        let entry = parsed.find("DATA\\caf\u{e9}.TXT").unwrap();
+        let mut buf = String::new();
+        entry
+            .open(&mut reader)
+            .unwrap()
+            .read_to_string(&mut buf)
+            .unwrap();
+        assert_eq!(buf, "abc");
+    }
+}
@@ -0,0 +1,484 @@
+//! [`vfs::FileSystem`] over memory mapped archives with an optional writable overlay
+use crate::{normalize_path, DirectoryTree, Header};
+use anyhow::{bail, Result};
+use memmap2::Mmap;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fs;
+use std::io::{BufReader, BufWriter, Cursor, Read, Seek, Write};
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use vfs::{error::VfsErrorKind, FileSystem};
+use vfs::{VfsMetadata, VfsPath, VfsResult};
+
+#[derive(Debug)]
+struct PackedFile {
+    _fh: fs::File,
+    mm: Arc<Mmap>,
+    path: PathBuf,
+}
+
+#[derive(Debug)]
+pub struct MultiPack {
+    files: Vec<PackedFile>,
+    pub tree: DirectoryTree,
+    overlay: Option<Overlay>,
+}
+
+/// Writable layer shadowing the packed archives, like loose files do in the game
+#[derive(Debug)]
+struct Overlay {
+    layer: VfsPath,
+    /// Normalized paths removed from the archives
+    removed: RwLock<BTreeSet<String>>,
+}
+
+impl MultiPack {
+    pub fn load_all<P: AsRef<Path>>(files: &[P]) -> Result<Self> {
+        let mut tree = DirectoryTree::default();
+        let mut packed_files = vec![];
+        for (file_index, file) in files.iter().enumerate() {
+            let mut fh = BufReader::new(fs::File::open(file.as_ref())?);
+            let header = Header::read(&mut fh)?;
+            tree.merge(&header.files, file_index);
+            let fh = fh.into_inner();
+            packed_files.push(PackedFile {
+                mm: Arc::new(unsafe { Mmap::map(&fh)? }),
+                path: file.as_ref().to_owned(),
+                _fh: fh,
+            });
+        }
+        Ok(Self {
+            tree,
+            files: packed_files,
+            overlay: None,
+        })
+    }
+
+    /// Makes the archive writable, all modifications go to `layer`
+    ///
+    /// `layer` can be a `MemoryFS` or a `PhysicalFS` directory, existing files in it
+    /// override the archive contents (matched case-insensitively).
+    pub fn with_overlay(mut self, layer: VfsPath) -> Self {
+        self.overlay = Some(Overlay {
+            layer,
+            removed: RwLock::default(),
+        });
+        self
+    }
+
+    fn writable(&self) -> VfsResult<&Overlay> {
+        self.overlay
+            .as_ref()
+            .ok_or_else(|| VfsErrorKind::NotSupported.into())
+    }
+
+    fn is_removed(&self, path: &str) -> bool {
+        let Some(overlay) = &self.overlay else {
+            return false;
+        };
+        let Ok(removed) = overlay.removed.read() else {
+            return false;
+        };
+        let mut prefix = String::new();
+        for part in path.split('/') {
+            if !prefix.is_empty() {
+                prefix.push('/');
+            }
+            prefix.push_str(part);
+            if removed.contains(&prefix) {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn set_removed(&self, path: &str, is_removed: bool) -> VfsResult<()> {
+        let overlay = self.writable()?;
+        let mut removed = overlay
+            .removed
+            .write()
+            .map_err(|e| VfsErrorKind::Other(format!("{e}")))?;
+        if is_removed {
+            removed.insert(path.to_owned());
+        } else {
+            removed.remove(path);
+        }
+        Ok(())
+    }
+
+    fn packed_entry(&self, path: &str) -> Option<&DirectoryTree> {
+        if self.is_removed(path) {
+            return None;
+        }
+        self.tree.get(path)
+    }
+
+    /// Looks up a normalized path in the overlay, returns the deepest existing
+    /// entry and the path components that don't exist yet
+    fn resolve_overlay<'p>(&self, path: &'p str) -> VfsResult<Option<(VfsPath, Vec<&'p str>)>> {
+        let Some(overlay) = &self.overlay else {
+            return Ok(None);
+        };
+        let mut current = overlay.layer.clone();
+        let mut parts: VecDeque<&str> = path.split('/').filter(|p| !p.is_empty()).collect();
+        while let Some(&part) = parts.front() {
+            if !current.is_dir()? {
+                break;
+            }
+            let Some(entry) = current
+                .read_dir()?
+                .find(|entry| entry.filename().to_ascii_lowercase() == part)
+            else {
+                break;
+            };
+            current = entry;
+            parts.pop_front();
+        }
+        Ok(Some((current, parts.into())))
+    }
+
+    fn overlay_entry(&self, path: &str) -> VfsResult<Option<VfsPath>> {
+        Ok(self
+            .resolve_overlay(path)?
+            .and_then(|(entry, rest)| rest.is_empty().then_some(entry)))
+    }
+
+    /// Components of `path` with the casing of the archive entries they name, if any
+    fn display_names(&self, path: &str) -> Vec<String> {
+        let mut tree = Some(&self.tree);
+        path.replace('\\', "/")
+            .split('/')
+            .filter(|part| !part.is_empty())
+            .map(|part| {
+                let found = match tree {
+                    Some(DirectoryTree::Directory { entries }) => {
+                        entries.get(&part.to_ascii_lowercase())
+                    }
+                    _ => None,
+                };
+                tree = found.map(|(_, child)| child);
+                found.map_or(part, |(name, _)| name.as_str()).to_owned()
+            })
+            .collect()
+    }
+
+    /// Creates the parent directories of `path` in the overlay and returns the path to write to
+    ///
+    /// New components are named like the archive entries they shadow, or as given in `path`.
+    fn overlay_create(&self, path: &str) -> VfsResult<VfsPath> {
+        let key = normalize_path(path);
+        let Some((mut entry, rest)) = self.resolve_overlay(&key)? else {
+            return Err(VfsErrorKind::NotSupported.into());
+        };
+        let parent = key.rsplit_once('/').map(|(parent, _)| parent).unwrap_or("");
+        if !self.is_dir(parent)? {
+            return Err(VfsErrorKind::FileNotFound.into());
+        }
+        let names = self.display_names(path);
+        for part in &names[names.len() - rest.len()..] {
+            entry = entry.join(part)?;
+        }
+        entry.parent().create_dir_all()?;
+        Ok(entry)
+    }
+
+    fn is_dir(&self, path: &str) -> VfsResult<bool> {
+        if let Some(entry) = self.overlay_entry(path)? {
+            return entry.is_dir();
+        }
+        Ok(matches!(
+            self.packed_entry(path),
+            Some(DirectoryTree::Directory { .. })
+        ))
+    }
+
+    pub fn add<P: AsRef<Path>>(&mut self, file: &P) -> Result<()> {
+        let file = file.as_ref();
+        for packed in &self.files {
+            if packed.path == file {
+                bail!("File already loaded!");
+            }
+        }
+        let mut fh = BufReader::new(fs::File::open(file)?);
+        let header = Header::read(&mut fh)?;
+        self.tree.merge(&header.files, self.files.len());
+        let fh = fh.into_inner();
+        self.files.push(PackedFile {
+            mm: Arc::new(unsafe { Mmap::map(&fh)? }),
+            path: file.to_owned(),
+            _fh: fh,
+        });
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct FileHandle {
+    _mm: Arc<Mmap>,
+    cursor: Cursor<Arc<[u8]>>,
+}
+
+impl Seek for FileHandle {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        self.cursor.seek(pos)
+    }
+}
+
+impl Read for FileHandle {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.cursor.read(buf)
+    }
+}
+
+fn join(path: &str, name: &str) -> String {
+    if path.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{path}/{name}")
+    }
+}
+
+impl FileSystem for MultiPack {
+    fn read_dir(&self, path: &str) -> VfsResult<Box<dyn Iterator<Item = String> + Send>> {
+        let path = normalize_path(path);
+        // normalized name -> name as stored, archive names take precedence
+        let mut names = BTreeMap::new();
+        let mut found = false;
+        match self.packed_entry(&path) {
+            Some(DirectoryTree::File { .. }) => return Err(VfsErrorKind::NotSupported.into()),
+            Some(tree @ DirectoryTree::Directory { .. }) => {
+                found = true;
+                for (name, _) in tree.children() {
+                    let key = name.to_ascii_lowercase();
+                    if !self.is_removed(&join(&path, &key)) {
+                        names.insert(key, name.to_owned());
+                    }
+                }
+            }
+            None => (),
+        }
+        if let Some(entry) = self.overlay_entry(&path)? {
+            if entry.is_dir()? {
+                found = true;
+                for entry in entry.read_dir()? {
+                    let name = entry.filename();
+                    names.entry(name.to_ascii_lowercase()).or_insert(name);
+                }
+            } else if !found {
+                return Err(VfsErrorKind::NotSupported.into());
+            }
+        }
+        if !found {
+            return Err(VfsErrorKind::FileNotFound.into());
+        }
+        Ok(Box::new(names.into_values()))
+    }
+
+    fn create_dir(&self, path: &str) -> VfsResult<()> {
+        let key = normalize_path(path);
+        if self.exists(&key)? {
+            return Err(VfsErrorKind::DirectoryExists.into());
+        }
+        self.overlay_create(path)?.create_dir()?;
+        self.set_removed(&key, false)
+    }
+
+    fn open_file(&self, path: &str) -> VfsResult<Box<dyn vfs::SeekAndRead + Send>> {
+        let path = normalize_path(path);
+        if let Some(entry) = self.overlay_entry(&path)? {
+            return entry.open_file();
+        }
+        match self.packed_entry(&path) {
+            Some(&DirectoryTree::File {
+                size,
+                offset,
+                file_index,
+            }) => {
+                let Some(file) = self.files.get(file_index) else {
+                    return Err(VfsErrorKind::FileNotFound.into());
+                };
+                let mm = Arc::clone(&file.mm);
+                let start = offset as usize;
+                let Some(data) = mm.get(start..start + size as usize) else {
+                    return Err(VfsErrorKind::Other("Entry out of bounds".into()).into());
+                };
+                Ok(Box::new(FileHandle {
+                    cursor: Cursor::new(Arc::from(data)),
+                    _mm: mm,
+                }))
+            }
+            Some(DirectoryTree::Directory { .. }) => Err(VfsErrorKind::NotSupported.into()),
+            None => Err(VfsErrorKind::FileNotFound.into()),
+        }
+    }
+
+    fn create_file(&self, path: &str) -> VfsResult<Box<dyn Write + Send>> {
+        let key = normalize_path(path);
+        let fh = match self.overlay_entry(&key)? {
+            Some(entry) => entry.create_file()?,
+            None => self.overlay_create(path)?.create_file()?,
+        };
+        self.set_removed(&key, false)?;
+        Ok(fh)
+    }
+
+    fn append_file(&self, path: &str) -> VfsResult<Box<dyn Write + Send>> {
+        let path = normalize_path(path);
+        if let Some(entry) = self.overlay_entry(&path)? {
+            return entry.append_file();
+        }
+        let mut data = self.open_file(&path)?;
+        let mut fh = self.create_file(&path)?;
+        std::io::copy(&mut data, &mut fh)?;
+        Ok(fh)
+    }
+
+    fn metadata(&self, path: &str) -> VfsResult<VfsMetadata> {
+        let path = normalize_path(path);
+        if let Some(entry) = self.overlay_entry(&path)? {
+            return entry.metadata();
+        }
+        Ok(match self.packed_entry(&path) {
+            Some(DirectoryTree::File { size, .. }) => VfsMetadata {
+                file_type: vfs::VfsFileType::File,
+                len: (*size).into(),
+            },
+            Some(DirectoryTree::Directory { entries: _ }) => VfsMetadata {
+                file_type: vfs::VfsFileType::Directory,
+                len: 0,
+            },
+            None => return Err(VfsErrorKind::FileNotFound.into()),
+        })
+    }
+
+    fn exists(&self, path: &str) -> VfsResult<bool> {
+        let path = normalize_path(path);
+        Ok(self.overlay_entry(&path)?.is_some() || self.packed_entry(&path).is_some())
+    }
+
+    fn remove_file(&self, path: &str) -> VfsResult<()> {
+        let path = normalize_path(path);
+        self.writable()?;
+        if self.metadata(&path)?.file_type != vfs::VfsFileType::File {
+            return Err(VfsErrorKind::NotSupported.into());
+        }
+        if let Some(entry) = self.overlay_entry(&path)? {
+            entry.remove_file()?;
+        }
+        if self.packed_entry(&path).is_some() {
+            self.set_removed(&path, true)?;
+        }
+        Ok(())
+    }
+
+    fn remove_dir(&self, path: &str) -> VfsResult<()> {
+        let path = normalize_path(path);
+        self.writable()?;
+        if self.read_dir(&path)?.next().is_some() {
+            return Err(VfsErrorKind::Other("Directory to remove is not empty".into()).into());
+        }
+        if let Some(entry) = self.overlay_entry(&path)? {
+            entry.remove_dir()?;
+        }
+        if self.packed_entry(&path).is_some() {
+            self.set_removed(&path, true)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes the files below `root` into a new .packed archive at `out_path`
+pub fn write_packed<P: AsRef<Path>>(root: &VfsPath, out_path: P) -> Result<()> {
+    let mut files = vec![];
+    for path in root.walk_dir()? {
+        let path = path?;
+        if path.is_file()? {
+            files.push(path);
+        }
+    }
+    files.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+    let mut entries = vec![];
+    for file in &files {
+        let path = file.as_str().trim_start_matches('/').to_owned();
+        entries.push((path, u32::try_from(file.metadata()?.len)?));
+    }
+    let header = Header::from_files(entries)?;
+    let mut fh = BufWriter::new(fs::File::create(out_path.as_ref())?);
+    let mut files = files.iter();
+    header.write(&mut fh, |entry| match files.next() {
+        Some(file) => Ok(file.open_file()?),
+        None => bail!("Missing data for {}", entry.path),
+    })?;
+    fh.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::TempDir;
+    use vfs::MemoryFS;
+
+    fn write(root: &VfsPath, path: &str, data: &[u8]) {
+        root.join(path)
+            .unwrap()
+            .create_file()
+            .unwrap()
+            .write_all(data)
+            .unwrap();
+    }
+
+    fn read(root: &VfsPath, path: &str) -> String {
+        root.join(path).unwrap().read_to_string().unwrap()
+    }
+
+    #[test]
+    fn overlay_commit() {
+        let dir = TempDir::new("overlay");
+        let base = dir.join("base.packed");
+        let modded = dir.join("modded.packed");
+
+        let files: &[&Path] = &[];
+        let root: VfsPath = MultiPack::load_all(files)
+            .unwrap()
+            .with_overlay(MemoryFS::new().into())
+            .into();
+        root.join("data/models").unwrap().create_dir_all().unwrap();
+        write(&root, "data/models/Ship.ini", b"ship");
+        write(&root, "data/keep.txt", b"keep");
+        write_packed(&root, &base).unwrap();
+
+        let root: VfsPath = MultiPack::load_all(&[&base])
+            .unwrap()
+            .with_overlay(MemoryFS::new().into())
+            .into();
+        assert_eq!(read(&root, "DATA/Models/ship.INI"), "ship");
+        write(&root, "data/models/ship.ini", b"modded");
+        root.join("data/keep.txt").unwrap().remove_file().unwrap();
+        assert!(!root.join("data/keep.txt").unwrap().exists().unwrap());
+        let entries: Vec<String> = root
+            .join("data")
+            .unwrap()
+            .read_dir()
+            .unwrap()
+            .map(|p| p.filename())
+            .collect();
+        assert_eq!(entries, vec!["models"]);
+        write(&root, "data/models/New.TXT", b"new");
+        write_packed(&root, &modded).unwrap();
+
+        let root: VfsPath = MultiPack::load_all(&[&modded]).unwrap().into();
+        assert_eq!(read(&root, "data/models/ship.ini"), "modded");
+        let entries: Vec<String> = root
+            .join("data/models")
+            .unwrap()
+            .read_dir()
+            .unwrap()
+            .map(|p| p.filename())
+            .collect();
+        assert_eq!(entries, vec!["New.TXT", "Ship.ini"]);
+        assert!(!root.join("data/keep.txt").unwrap().exists().unwrap());
+        assert!(root.join("data/new.txt").unwrap().create_file().is_err());
+    }
+}
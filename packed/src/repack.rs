@@ -0,0 +1,261 @@
+//! Incremental repacking of an existing archive from a modified folder
+//!
+//! Untouched entries keep their position in the header and their data offset (shifted
+//! as a whole if the header grows), changed entries are rewritten in place when they
+//! fit and appended otherwise, new entries are appended sorted by path. Space freed by
+//! removed or relocated entries keeps its original bytes so binary diffs stay small.
+use crate::{normalize_path, Entry, Header};
+use anyhow::{anyhow, Result};
+use binrw::prelude::*;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Change {
+    Unchanged,
+    /// Rewritten at its original offset
+    InPlace,
+    /// Grew and was moved to the end of the archive
+    Relocated,
+    Added,
+    Removed,
+}
+
+#[derive(Debug)]
+enum Source {
+    Original,
+    Folder(PathBuf),
+}
+
+#[derive(Debug)]
+pub struct RepackPlan {
+    pub header: Header,
+    /// Every path of the original archive and the folder with what happens to it
+    pub changes: Vec<(String, Change)>,
+    sources: Vec<Source>,
+    /// Original data region (start, end) and where it ends up in the new archive
+    original_data: (u64, u64),
+    shift: u64,
+}
+
+/// Lists all files below `folder` sorted by their archive path
+pub fn folder_files(folder: &Path) -> Result<Vec<(String, PathBuf, u32)>> {
+    let mut files = vec![];
+    let mut queue = vec![folder.to_owned()];
+    while let Some(dir) = queue.pop() {
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                queue.push(path);
+                continue;
+            }
+            let name = path
+                .strip_prefix(folder)?
+                .to_string_lossy()
+                .replace('\\', "/");
+            let size = u32::try_from(path.metadata()?.len())?;
+            files.push((name, path, size));
+        }
+    }
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(files)
+}
+
+fn same_content<R: Read + Seek>(reader: &mut R, entry: &Entry, path: &Path) -> Result<bool> {
+    let mut original = entry.open(reader)?;
+    let mut modified = BufReader::new(File::open(path)?);
+    let mut buf_1 = [0u8; 0x1000];
+    let mut buf_2 = [0u8; 0x1000];
+    loop {
+        let n = original.read(&mut buf_1)?;
+        if n == 0 {
+            return Ok(modified.read(&mut buf_2[..1])? == 0);
+        }
+        modified.read_exact(&mut buf_2[..n])?;
+        if buf_1[..n] != buf_2[..n] {
+            return Ok(false);
+        }
+    }
+}
+
+impl RepackPlan {
+    pub fn new<R: Read + Seek>(original: &Header, reader: &mut R, folder: &Path) -> Result<Self> {
+        let mut modified: BTreeMap<String, (String, PathBuf, u32)> = folder_files(folder)?
+            .into_iter()
+            .map(|file| (normalize_path(&file.0), file))
+            .collect();
+        let mut files = vec![];
+        let mut sources = vec![];
+        let mut changes = vec![];
+        let mut relocated = HashSet::new();
+        for entry in &original.files {
+            let Some((_, path, size)) = modified.remove(&normalize_path(&entry.path)) else {
+                changes.push((entry.path.clone(), Change::Removed));
+                continue;
+            };
+            let change = if size == entry.size && same_content(reader, entry, &path)? {
+                sources.push(Source::Original);
+                Change::Unchanged
+            } else {
+                sources.push(Source::Folder(path));
+                if size <= entry.size {
+                    Change::InPlace
+                } else {
+                    relocated.insert(files.len());
+                    Change::Relocated
+                }
+            };
+            changes.push((entry.path.clone(), change));
+            files.push(Entry {
+                path: entry.path.clone(),
+                size,
+                offset: entry.offset,
+            });
+        }
+        let mut added = HashSet::new();
+        for (name, path, size) in modified.into_values() {
+            changes.push((name.clone(), Change::Added));
+            added.insert(files.len());
+            sources.push(Source::Folder(path));
+            files.push(Entry {
+                path: name,
+                size,
+                offset: 0,
+            });
+        }
+        let mut header = Header { files };
+        let original_size = original.size()?;
+        // empty entries can carry any offset (often 0), they don't own any data
+        let stored = || original.files.iter().filter(|entry| entry.size != 0);
+        let data_start = stored()
+            .map(|entry| u64::from(entry.offset))
+            .fold(original_size, u64::min);
+        let data_end = stored()
+            .map(|entry| u64::from(entry.offset) + u64::from(entry.size))
+            .max()
+            .unwrap_or(data_start);
+        let shift = header.size()?.saturating_sub(data_start);
+        let mut end = data_end + shift;
+        for (n, entry) in header.files.iter_mut().enumerate() {
+            entry.offset = if relocated.contains(&n) || added.contains(&n) {
+                let offset = end;
+                end += u64::from(entry.size);
+                u32::try_from(offset)
+            } else {
+                u32::try_from(u64::from(entry.offset) + shift)
+            }
+            .map_err(|_| anyhow!("Archive too large, {} doesn't fit", entry.path))?;
+        }
+        u32::try_from(end).map_err(|_| anyhow!("Archive too large: {end} bytes"))?;
+        Ok(Self {
+            header,
+            changes,
+            sources,
+            original_data: (data_start, data_end),
+            shift,
+        })
+    }
+
+    pub fn write<R: Read + Seek, W: Write + Seek>(
+        &self,
+        reader: &mut R,
+        writer: &mut W,
+    ) -> Result<()> {
+        let (data_start, data_end) = self.original_data;
+        writer.write_le(&self.header)?;
+        let header_end = self.header.size()?;
+        std::io::copy(
+            &mut std::io::repeat(0).take((data_start + self.shift).saturating_sub(header_end)),
+            writer,
+        )?;
+        reader.seek(SeekFrom::Start(data_start))?;
+        let copied = std::io::copy(&mut reader.take(data_end - data_start), writer)?;
+        if copied != data_end - data_start {
+            return Err(anyhow!("Original archive is truncated"));
+        }
+        let mut entries: Vec<(&Entry, &Source)> =
+            self.header.files.iter().zip(&self.sources).collect();
+        entries.sort_by_key(|(entry, _)| entry.offset);
+        for (entry, source) in entries {
+            let Source::Folder(path) = source else {
+                continue;
+            };
+            writer.seek(SeekFrom::Start(entry.offset.into()))?;
+            let written = std::io::copy(&mut File::open(path)?.take(entry.size.into()), writer)?;
+            if written != u64::from(entry.size) {
+                return Err(anyhow!("{} changed while repacking", path.display()));
+            }
+        }
+        writer.seek(SeekFrom::End(0))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{archive, TempDir};
+    use std::io::Cursor;
+
+    #[test]
+    fn incremental() {
+        let dir = TempDir::new("repack");
+        let (header, mut original) =
+            archive(&[("b.txt", b"bbbb"), ("a.txt", b"aaaa"), ("c.txt", b"cc")]);
+
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("b.txt"), b"bbbb").unwrap();
+        std::fs::write(dir.join("a.txt"), b"AA").unwrap();
+        std::fs::write(dir.join("c.txt"), b"CCCCCC").unwrap();
+        std::fs::write(dir.join("sub/d.txt"), b"d").unwrap();
+
+        let plan = RepackPlan::new(&header, &mut original, &dir).unwrap();
+        let changes: Vec<Change> = plan.changes.iter().map(|(_, c)| *c).collect();
+        assert_eq!(
+            changes,
+            [
+                Change::Unchanged,
+                Change::InPlace,
+                Change::Relocated,
+                Change::Added
+            ]
+        );
+        let mut out = Cursor::new(vec![]);
+        plan.write(&mut original, &mut out).unwrap();
+
+        let mut out = Cursor::new(out.into_inner());
+        let repacked = Header::read(&mut out).unwrap();
+        let names: Vec<&str> = repacked.files.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(names, ["b.txt", "a.txt", "c.txt", "sub/d.txt"]);
+        // the header grew by one entry, everything moves by the same amount
+        let shift = repacked.files[0].offset - header.files[0].offset;
+        assert_eq!(shift, 4 * 3 + 9);
+        assert_eq!(repacked.files[1].offset, header.files[1].offset + shift);
+        for (entry, expected) in repacked
+            .files
+            .iter()
+            .zip([&b"bbbb"[..], b"AA", b"CCCCCC", b"d"])
+        {
+            let mut buf = vec![];
+            entry.open(&mut out).unwrap().read_to_end(&mut buf).unwrap();
+            assert_eq!(buf, expected);
+        }
+    }
+
+    #[test]
+    fn empty_entry() {
+        let dir = TempDir::new("repack_empty");
+        let (mut header, mut original) = archive(&[("a.txt", b"aaaa"), ("empty.txt", b"")]);
+        header.files[1].offset = 0;
+
+        std::fs::write(dir.join("a.txt"), b"aaaa").unwrap();
+        std::fs::write(dir.join("empty.txt"), b"").unwrap();
+        let plan = RepackPlan::new(&header, &mut original, &dir).unwrap();
+        assert_eq!(plan.original_data.0, u64::from(header.files[0].offset));
+        assert_eq!(plan.shift, 0);
+    }
+}
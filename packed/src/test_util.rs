@@ -0,0 +1,44 @@
+//! Fixtures shared by the unit tests
+use crate::Header;
+use std::io::Cursor;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+/// Archive with `files` in order and its header
+pub(crate) fn archive(files: &[(&str, &[u8])]) -> (Header, Cursor<Vec<u8>>) {
+    let header =
+        Header::from_files(files.iter().map(|(p, d)| (p.to_string(), d.len() as u32))).unwrap();
+    let mut out = Cursor::new(vec![]);
+    let mut data = files.iter().map(|(_, d)| *d);
+    header
+        .write(&mut out, |_| Ok(data.next().unwrap()))
+        .unwrap();
+    (header, out)
+}
+
+/// Empty directory in the system temp directory, removed on drop so failed tests don't
+/// leave it behind
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    pub(crate) fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("packed_{name}_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,136 @@
+use crate::{normalize_path, Entry};
+use serde::{Serialize, Serializer};
+use std::collections::BTreeMap;
+
+/// Merged directory view over the entries of one or more archives
+///
+/// Children are keyed by their normalized (lower case) name and keep the name as it was
+/// first seen in an archive for display, the first archive providing a path wins.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DirectoryTree {
+    File {
+        size: u32,
+        offset: u32,
+        file_index: usize,
+    },
+    Directory {
+        #[serde(serialize_with = "serialize_entries")]
+        entries: BTreeMap<String, (String, DirectoryTree)>,
+    },
+}
+
+/// Serializes children by their display name
+fn serialize_entries<S: Serializer>(
+    entries: &BTreeMap<String, (String, DirectoryTree)>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(entries.values().map(|(name, tree)| (name, tree)))
+}
+
+impl Default for DirectoryTree {
+    fn default() -> Self {
+        Self::Directory {
+            entries: Default::default(),
+        }
+    }
+}
+
+impl DirectoryTree {
+    /// Adds the entries of archive number `file_index`
+    ///
+    /// Entries whose parent path is already taken by a file are skipped.
+    pub fn merge(&mut self, files: &[Entry], file_index: usize) {
+        for file in files {
+            let path = file.path.replace('\\', "/");
+            let parts: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+            let Some((filename, parents)) = parts.split_last() else {
+                continue;
+            };
+            let mut folder = &mut *self;
+            for part in parents {
+                let DirectoryTree::Directory { entries } = folder else {
+                    break;
+                };
+                folder = &mut entries
+                    .entry(part.to_ascii_lowercase())
+                    .or_insert_with(|| (part.to_string(), DirectoryTree::default()))
+                    .1;
+            }
+            let DirectoryTree::Directory { entries } = folder else {
+                continue;
+            };
+            entries
+                .entry(filename.to_ascii_lowercase())
+                .or_insert_with(|| {
+                    (
+                        filename.to_string(),
+                        DirectoryTree::File {
+                            size: file.size,
+                            offset: file.offset,
+                            file_index,
+                        },
+                    )
+                });
+        }
+    }
+
+    pub fn get(&self, path: &str) -> Option<&Self> {
+        let path = normalize_path(path);
+        let mut tree = self;
+        for part in path.split('/').filter(|part| !part.is_empty()) {
+            let DirectoryTree::Directory { entries } = tree else {
+                return None;
+            };
+            tree = &entries.get(part)?.1;
+        }
+        Some(tree)
+    }
+
+    /// Display names and nodes of the children of a directory, in normalized name order
+    pub fn children(&self) -> impl Iterator<Item = (&str, &DirectoryTree)> {
+        let entries = match self {
+            DirectoryTree::Directory { entries } => Some(entries),
+            DirectoryTree::File { .. } => None,
+        };
+        entries
+            .into_iter()
+            .flat_map(|entries| entries.values())
+            .map(|(name, tree)| (name.as_str(), tree))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn keeps_case() {
+        let entry = |path: &str, offset| Entry {
+            path: path.to_owned(),
+            size: 1,
+            offset,
+        };
+        let mut tree = DirectoryTree::default();
+        tree.merge(&[entry("Data\\Models\\Ship.SM3", 1)], 0);
+        tree.merge(
+            &[entry("data/models/ship.sm3", 2), entry("data/b.ini", 3)],
+            1,
+        );
+        let names: Vec<&str> = tree
+            .get("DATA")
+            .unwrap()
+            .children()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(names, ["b.ini", "Models"]);
+        assert_eq!(
+            tree.get("data/MODELS/ship.sm3"),
+            Some(&DirectoryTree::File {
+                size: 1,
+                offset: 1,
+                file_index: 0
+            })
+        );
+    }
+}
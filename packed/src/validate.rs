@@ -0,0 +1,203 @@
+//! Sanity checks for archives from untrusted sources
+use crate::{normalize_path, Entry, Header};
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// Absolute path, drive prefix or `..` component
+    UnsafePath { path: String },
+    /// Same path (ignoring case) stored more than once
+    Duplicate { path: String },
+    /// Data starts inside the header
+    OverlapsHeader {
+        path: String,
+        offset: u32,
+        header_size: u64,
+    },
+    /// Data extends past the end of the archive
+    OutOfRange {
+        path: String,
+        offset: u32,
+        size: u32,
+        archive_size: u64,
+    },
+    /// Data shares bytes with another entry
+    Overlap { path: String, other: String },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::UnsafePath { path } => write!(f, "{path:?}: unsafe path"),
+            Problem::Duplicate { path } => write!(f, "{path:?}: stored more than once"),
+            Problem::OverlapsHeader {
+                path,
+                offset,
+                header_size,
+            } => write!(
+                f,
+                "{path:?}: offset {offset} is inside the header ({header_size} bytes)"
+            ),
+            Problem::OutOfRange {
+                path,
+                offset,
+                size,
+                archive_size,
+            } => write!(
+                f,
+                "{path:?}: {size} bytes at offset {offset} exceed the archive size ({archive_size} bytes)"
+            ),
+            Problem::Overlap { path, other } => write!(f, "{path:?}: overlaps {other:?}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    pub problems: Vec<Problem>,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid archive, {} problem(s)", self.problems.len())?;
+        for problem in &self.problems {
+            write!(f, "\n  {problem}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// Checks that `path` stays inside the folder it gets extracted to
+pub fn is_safe_path(path: &str) -> bool {
+    !path.is_empty()
+        && !path.starts_with(['/', '\\'])
+        && !path.contains(':')
+        && path.split(['/', '\\']).all(|part| part != "..")
+}
+
+impl Header {
+    /// Collects every problem instead of stopping at the first one
+    pub fn validate(&self, archive_size: u64) -> Result<(), ValidationError> {
+        let mut problems = vec![];
+        let header_size = self.size().unwrap_or(u64::MAX);
+        let mut seen = HashMap::new();
+        for entry in &self.files {
+            if !is_safe_path(&entry.path) {
+                problems.push(Problem::UnsafePath {
+                    path: entry.path.clone(),
+                });
+            }
+            let count = seen.entry(normalize_path(&entry.path)).or_insert(0);
+            *count += 1;
+            if *count == 2 {
+                problems.push(Problem::Duplicate {
+                    path: entry.path.clone(),
+                });
+            }
+            if entry.size == 0 {
+                continue;
+            }
+            if u64::from(entry.offset) < header_size {
+                problems.push(Problem::OverlapsHeader {
+                    path: entry.path.clone(),
+                    offset: entry.offset,
+                    header_size,
+                });
+            }
+            if u64::from(entry.offset) + u64::from(entry.size) > archive_size {
+                problems.push(Problem::OutOfRange {
+                    path: entry.path.clone(),
+                    offset: entry.offset,
+                    size: entry.size,
+                    archive_size,
+                });
+            }
+        }
+        let mut entries: Vec<&Entry> = self.files.iter().filter(|e| e.size != 0).collect();
+        entries.sort_by_key(|entry| entry.offset);
+        // Track the entry reaching furthest so far, this also catches entries
+        // nested inside an earlier, larger one
+        let mut furthest: Option<&Entry> = None;
+        for entry in entries {
+            if let Some(prev) = furthest {
+                let prev_end = u64::from(prev.offset) + u64::from(prev.size);
+                if u64::from(entry.offset) < prev_end {
+                    problems.push(Problem::Overlap {
+                        path: entry.path.clone(),
+                        other: prev.path.clone(),
+                    });
+                }
+                if u64::from(entry.offset) + u64::from(entry.size) <= prev_end {
+                    continue;
+                }
+            }
+            furthest = Some(entry);
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationError { problems })
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(path: &str, offset: u32, size: u32) -> Entry {
+        Entry {
+            path: path.into(),
+            size,
+            offset,
+        }
+    }
+
+    #[test]
+    fn problems() {
+        let header = Header {
+            files: vec![
+                entry("../evil.dll", 200, 10),
+                entry("C:/evil.dll", 210, 10),
+                entry("data/a.txt", 0, 10),
+                entry("data/b.txt", 215, 10),
+                entry("DATA\\A.TXT", 300, 100),
+            ],
+        };
+        let err = header.validate(350).unwrap_err();
+        assert_eq!(
+            err.problems,
+            [
+                Problem::UnsafePath {
+                    path: "../evil.dll".into()
+                },
+                Problem::UnsafePath {
+                    path: "C:/evil.dll".into()
+                },
+                Problem::OverlapsHeader {
+                    path: "data/a.txt".into(),
+                    offset: 0,
+                    header_size: header.size().unwrap()
+                },
+                Problem::Duplicate {
+                    path: "DATA\\A.TXT".into()
+                },
+                Problem::OutOfRange {
+                    path: "DATA\\A.TXT".into(),
+                    offset: 300,
+                    size: 100,
+                    archive_size: 350
+                },
+                Problem::Overlap {
+                    path: "data/b.txt".into(),
+                    other: "C:/evil.dll".into()
+                },
+            ]
+        );
+        let valid = Header::from_files([("a/b.txt".into(), 3), ("c".into(), 0)]).unwrap();
+        assert_eq!(valid.validate(valid.size().unwrap() + 3), Ok(()));
+    }
+}
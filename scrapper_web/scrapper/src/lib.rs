@@ -1,6 +1,4 @@
-use binrw::{binread, BinReaderExt};
-use serde::Serialize;
-use std::collections::BTreeMap;
+use packed::{DirectoryTree, Header};
 use std::io::{Read, Seek, SeekFrom};
 use wasm_bindgen::prelude::*;
 use wasm_bindgen_file_reader::WebSysFile;
@@ -8,42 +6,6 @@ use web_sys::{Blob, File};
 
 type JsResult<T> = Result<T,JsValue>;
 
-#[binread]
-#[derive(Serialize, Debug)]
-struct ScrapFile {
-    #[br(temp)]
-    name_len: u32,
-    #[br(count = name_len)]
-    #[br(map = |s: Vec<u8>| String::from_utf8_lossy(&s).to_string())]
-    path: String,
-    size: u32,
-    offset: u32,
-}
-
-#[binread]
-#[br(magic = b"BFPK", little)]
-#[derive(Serialize, Debug)]
-struct PackedHeader {
-    version: u32,
-    #[br(temp)]
-    num_files: u32,
-    #[br(count= num_files)]
-    files: Vec<ScrapFile>,
-}
-
-#[derive(Serialize, Debug)]
-#[serde(tag = "type", rename_all = "snake_case")]
-enum DirectoryTree {
-    File {
-        size: u32,
-        offset: u32,
-        file_index: u8,
-    },
-    Directory {
-        entries: BTreeMap<String, DirectoryTree>,
-    },
-}
-
 #[wasm_bindgen(inspectable)]
 pub struct MultiPack {
     files: Vec<(String,WebSysFile)>,
@@ -72,8 +34,8 @@ impl MultiPack {
         for (file_index, file) in files.into_iter().enumerate() {
             let file_name = file.name();
             let mut fh = WebSysFile::new(file);
-            let header = fh.read_le::<PackedHeader>().unwrap();
-            tree.merge(&header.files, file_index.try_into().unwrap());
+            let header = Header::read(&mut fh).unwrap();
+            tree.merge(&header.files, file_index);
             web_files.push((file_name,fh));
         }
         Self {
@@ -90,64 +52,22 @@ impl MultiPack {
     #[wasm_bindgen]
     pub fn download(
         &mut self,
-        file_index: u8,
+        file_index: usize,
         offset: u32,
         size: u32,
     ) -> Result<JsValue, JsValue> {
-        let Some((_,file)) = self.files.get_mut(file_index as usize) else {
+        let Some((_,file)) = self.files.get_mut(file_index) else {
             return Err("File not found".into());
         };
         let mut buffer = vec![0u8; size as usize];
         file.seek(SeekFrom::Start(offset as u64))
             .map_err(|e| format!("Failed to seek file: {e}"))?;
-        file.read(&mut buffer)
+        file.read_exact(&mut buffer)
             .map_err(|e| format!("Failed to read from file: {e}"))?;
         Ok(blob_url(&buffer)?.into())
     }
 }
 
-impl Default for DirectoryTree {
-    fn default() -> Self {
-        Self::Directory {
-            entries: Default::default(),
-        }
-    }
-}
-
-impl DirectoryTree {
-    fn add_child(&mut self, name: &str, node: Self) -> &mut Self {
-        match self {
-            Self::File { .. } => panic!("Can't add child to file!"),
-            Self::Directory {
-                entries
-            } => entries.entry(name.to_owned()).or_insert(node),
-        }
-    }
-
-    fn merge(&mut self, files: &[ScrapFile], file_index: u8) {
-        for file in files {
-            let mut folder = &mut *self;
-            let path: Vec<_> = file.path.split('/').collect();
-            if let Some((filename, path)) = path.as_slice().split_last() {
-                for part in path {
-                    let DirectoryTree::Directory { entries } = folder else {
-                            unreachable!();
-                        };
-                    folder = entries.entry(part.to_string()).or_default();
-                }
-                folder.add_child(
-                    filename,
-                    DirectoryTree::File {
-                        size: file.size,
-                        offset: file.offset,
-                        file_index,
-                    },
-                );
-            }
-        }
-    }
-}
-
 #[wasm_bindgen(start)]
 pub fn main() -> Result<(), JsValue> {
     console_error_panic_hook::set_once();
@@ -0,0 +1,382 @@
+//! AI node graphs (.pth), see `file_formats/ai_path.md`
+//!
+//! ```text
+//! num_nodes: u32
+//! nodes: [[f32; dims]; num_nodes]
+//! num_edges: u32 (only in the documented layout)
+//! edges: [{num_points: u32, points: [[f32; dims]; num_points]}; num_edges or num_nodes]
+//! ```
+//!
+//! The documentation and `render_ai_path.py` disagree on `num_edges` and nothing in the
+//! file tells 2D and 3D graphs apart, so every combination is tried and the one that
+//! consumes the whole file and only references existing nodes wins. Edges are polylines,
+//! consecutive points are connected in both directions. Edge points that are only close
+//! to their node are kept as read, so writing reproduces the file.
+use anyhow::{bail, Result};
+use binrw::prelude::*;
+use binrw::Endian;
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::io::{Read, Seek, Write};
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct Graph {
+    /// Number of components per position (2 or 3)
+    pub(crate) dims: u8,
+    /// File stores `num_edges` before the edges instead of one edge per node
+    pub(crate) edge_count: bool,
+    pub(crate) nodes: Vec<Vec<f32>>,
+    /// Polylines as node indices
+    pub(crate) edges: Vec<Vec<u32>>,
+    /// Edge points that differ from the node they were matched to, written instead of
+    /// the node position
+    pub(crate) snapped: Vec<SnappedPoint>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct SnappedPoint {
+    pub(crate) edge: u32,
+    /// Index into the edge
+    pub(crate) point: u32,
+    /// Position as stored in the file
+    pub(crate) pos: Vec<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct GraphPath {
+    pub(crate) nodes: Vec<u32>,
+    /// Length along the graph, not including the way to and from the nearest nodes
+    pub(crate) length: f32,
+}
+
+fn read_u32(data: &[u8], pos: &mut usize) -> Option<u32> {
+    let bytes = data.get(*pos..*pos + 4)?;
+    *pos += 4;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+fn read_point(data: &[u8], pos: &mut usize, dims: u8) -> Option<Vec<f32>> {
+    (0..dims)
+        .map(|_| read_u32(data, pos).map(f32::from_bits))
+        .collect()
+}
+
+/// Largest distance between an edge point and the node it refers to, relative to the
+/// distance of the point from the origin (but at least 1) since level coordinates reach
+/// 1e5 where `f32` steps are around 0.01
+const EDGE_EPSILON: f32 = 1e-5;
+
+fn distance(a: &[f32], b: &[f32]) -> f32 {
+    a.iter()
+        .zip(b)
+        .map(|(a, b)| (a - b) * (a - b))
+        .sum::<f32>()
+        .sqrt()
+}
+
+/// Index of the node closest to `pos` and its distance
+fn nearest(nodes: &[Vec<f32>], pos: &[f32]) -> Option<(u32, f32)> {
+    nodes
+        .iter()
+        .map(|node| distance(node, pos))
+        .zip(0..)
+        .min_by(|(a, _), (b, _)| a.total_cmp(b))
+        .map(|(dist, n)| (n, dist))
+}
+
+impl Graph {
+    fn parse(data: &[u8], dims: u8, edge_count: bool) -> Option<Self> {
+        let mut pos = 0;
+        let num_nodes = read_u32(data, &mut pos)?;
+        // Bounds every count by the data left to avoid huge allocations on bad guesses
+        let max_items = data.len() / 4;
+        if num_nodes as usize > max_items {
+            return None;
+        }
+        let nodes: Vec<Vec<f32>> = (0..num_nodes)
+            .map(|_| read_point(data, &mut pos, dims))
+            .collect::<Option<_>>()?;
+        // Edges store copies of the node positions, exact matches are looked up bitwise and
+        // anything else snaps to the nearest node within `EDGE_EPSILON`
+        let index: HashMap<Vec<u32>, u32> = nodes
+            .iter()
+            .zip(0..)
+            .map(|(node, n)| (node.iter().map(|v| v.to_bits()).collect(), n))
+            .collect();
+        let num_edges = if edge_count {
+            read_u32(data, &mut pos)?
+        } else {
+            num_nodes
+        };
+        if num_edges as usize > max_items {
+            return None;
+        }
+        let mut edges = Vec::with_capacity(num_edges as usize);
+        let mut snapped = vec![];
+        for edge_idx in 0..num_edges {
+            let num_points = read_u32(data, &mut pos)?;
+            if num_points as usize > max_items {
+                return None;
+            }
+            let edge: Vec<u32> = (0..num_points)
+                .map(|point_idx| {
+                    let point = read_point(data, &mut pos, dims)?;
+                    let key: Vec<u32> = point.iter().map(|v| v.to_bits()).collect();
+                    if let Some(&n) = index.get(&key) {
+                        return Some(n);
+                    }
+                    let tolerance = EDGE_EPSILON * distance(&point, &[0.0; 3]).max(1.0);
+                    let (n, _) = nearest(&nodes, &point).filter(|&(_, dist)| dist <= tolerance)?;
+                    snapped.push(SnappedPoint {
+                        edge: edge_idx,
+                        point: point_idx,
+                        pos: point,
+                    });
+                    Some(n)
+                })
+                .collect::<Option<_>>()?;
+            edges.push(edge);
+        }
+        (pos == data.len()).then_some(Self {
+            dims,
+            edge_count,
+            nodes,
+            edges,
+            snapped,
+        })
+    }
+
+    fn neighbours(&self) -> Vec<Vec<u32>> {
+        let mut ret = vec![vec![]; self.nodes.len()];
+        for edge in &self.edges {
+            for pair in edge.windows(2) {
+                let (a, b) = (pair[0], pair[1]);
+                if a != b {
+                    ret[a as usize].push(b);
+                    ret[b as usize].push(a);
+                }
+            }
+        }
+        ret
+    }
+
+    /// Node closest to `pos`, which needs `dims` components
+    pub(crate) fn nearest_node(&self, pos: &[f32]) -> Result<Option<u32>> {
+        if pos.len() != usize::from(self.dims) {
+            bail!(
+                "Expected a position with {} components, got {}",
+                self.dims,
+                pos.len()
+            );
+        }
+        Ok(nearest(&self.nodes, pos).map(|(n, _)| n))
+    }
+
+    /// A* between the nodes closest to `start` and `end`
+    pub(crate) fn shortest_path(&self, start: &[f32], end: &[f32]) -> Result<Option<GraphPath>> {
+        #[derive(PartialEq)]
+        struct Entry(f32, u32);
+        impl Eq for Entry {}
+        impl Ord for Entry {
+            fn cmp(&self, other: &Self) -> Ordering {
+                // BinaryHeap is a max-heap, lowest estimate first
+                other.0.total_cmp(&self.0).then(other.1.cmp(&self.1))
+            }
+        }
+        impl PartialOrd for Entry {
+            fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+
+        let (Some(start), Some(goal)) = (self.nearest_node(start)?, self.nearest_node(end)?) else {
+            return Ok(None);
+        };
+        let neighbours = self.neighbours();
+        let pos = |n: u32| &self.nodes[n as usize];
+        let mut cost = vec![f32::INFINITY; self.nodes.len()];
+        let mut prev = vec![None; self.nodes.len()];
+        let mut queue = BinaryHeap::new();
+        cost[start as usize] = 0.0;
+        queue.push(Entry(distance(pos(start), pos(goal)), start));
+        while let Some(Entry(_, node)) = queue.pop() {
+            if node == goal {
+                let mut nodes = vec![goal];
+                while let Some(n) = prev[nodes[nodes.len() - 1] as usize] {
+                    nodes.push(n);
+                }
+                nodes.reverse();
+                return Ok(Some(GraphPath {
+                    nodes,
+                    length: cost[goal as usize],
+                }));
+            }
+            for &next in &neighbours[node as usize] {
+                let new_cost = cost[node as usize] + distance(pos(node), pos(next));
+                if new_cost < cost[next as usize] {
+                    cost[next as usize] = new_cost;
+                    prev[next as usize] = Some(node);
+                    queue.push(Entry(new_cost + distance(pos(next), pos(goal)), next));
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl BinRead for Graph {
+    type Args<'a> = ();
+
+    fn read_options<R: Read + Seek>(
+        reader: &mut R,
+        _: Endian,
+        _: Self::Args<'_>,
+    ) -> BinResult<Self> {
+        let pos = reader.stream_position()?;
+        let mut data = vec![];
+        reader.read_to_end(&mut data)?;
+        [(3, false), (3, true), (2, false), (2, true)]
+            .into_iter()
+            .find_map(|(dims, edge_count)| Self::parse(&data, dims, edge_count))
+            .ok_or_else(|| binrw::Error::AssertFail {
+                pos,
+                message: "No known AI path layout matches".to_owned(),
+            })
+    }
+}
+
+impl BinWrite for Graph {
+    type Args<'a> = ();
+
+    fn write_options<W: Write + Seek>(
+        &self,
+        writer: &mut W,
+        endian: Endian,
+        _: Self::Args<'_>,
+    ) -> BinResult<()> {
+        let len = |n: usize| {
+            u32::try_from(n).map_err(|_| binrw::Error::AssertFail {
+                pos: 0,
+                message: format!("Too many entries: {n}"),
+            })
+        };
+        len(self.nodes.len())?.write_options(writer, endian, ())?;
+        for node in &self.nodes {
+            node.write_options(writer, endian, ())?;
+        }
+        if self.edge_count {
+            len(self.edges.len())?.write_options(writer, endian, ())?;
+        }
+        let snapped: HashMap<(u32, u32), &Vec<f32>> = self
+            .snapped
+            .iter()
+            .map(|point| ((point.edge, point.point), &point.pos))
+            .collect();
+        for (edge, edge_idx) in self.edges.iter().zip(0..) {
+            len(edge.len())?.write_options(writer, endian, ())?;
+            for (&n, point_idx) in edge.iter().zip(0..) {
+                snapped
+                    .get(&(edge_idx, point_idx))
+                    .copied()
+                    .unwrap_or(&self.nodes[n as usize])
+                    .write_options(writer, endian, ())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use binrw::io::Cursor;
+
+    #[test]
+    fn parse_and_route() {
+        // 2D square with a diagonal shortcut from 0 to 2 through 4
+        let nodes = [
+            [0.0f32, 0.0],
+            [10.0, 0.0],
+            [10.0, 10.0],
+            [0.0, 10.0],
+            [5.0, 5.0],
+        ];
+        let edges: [&[usize]; 3] = [&[0, 1, 2], &[2, 3, 0], &[0, 4, 2]];
+        let mut data = vec![];
+        data.extend((nodes.len() as u32).to_le_bytes());
+        nodes
+            .iter()
+            .flatten()
+            .for_each(|v| data.extend(v.to_le_bytes()));
+        data.extend((edges.len() as u32).to_le_bytes());
+        for edge in edges {
+            data.extend((edge.len() as u32).to_le_bytes());
+            edge.iter()
+                .flat_map(|&n| nodes[n])
+                .for_each(|v| data.extend(v.to_le_bytes()));
+        }
+
+        let graph: Graph = Cursor::new(&data).read_le().unwrap();
+        assert_eq!((graph.dims, graph.edge_count), (2, true));
+        assert_eq!(graph.edges[2], [0, 4, 2]);
+        let mut out = Cursor::new(vec![]);
+        out.write_le(&graph).unwrap();
+        assert_eq!(out.into_inner(), data);
+
+        let path = graph
+            .shortest_path(&[1.0, -1.0], &[9.0, 11.0])
+            .unwrap()
+            .unwrap();
+        assert_eq!(path.nodes, [0, 4, 2]);
+        assert!((path.length - 200f32.sqrt()).abs() < 1e-4);
+        assert_eq!(
+            graph
+                .shortest_path(&[0.0, 0.0], &[0.0, 0.0])
+                .unwrap()
+                .unwrap()
+                .length,
+            0.0
+        );
+        assert!(graph.nearest_node(&[1.0, 2.0, 3.0]).is_err());
+        assert!(graph.shortest_path(&[1.0], &[9.0, 11.0]).is_err());
+    }
+
+    #[test]
+    fn inexact_edge_points() {
+        let nodes = [[0.0f32, 0.0, 0.0], [10.0, 0.0, 0.0], [40000.0, 0.0, 0.0]];
+        let file = |edge: &[[f32; 3]]| {
+            let mut data = vec![];
+            data.extend((nodes.len() as u32).to_le_bytes());
+            nodes
+                .iter()
+                .flatten()
+                .for_each(|v| data.extend(v.to_le_bytes()));
+            // one edge per node, only the first one has points
+            for edge in [edge, &[], &[]] {
+                data.extend((edge.len() as u32).to_le_bytes());
+                edge.iter()
+                    .flatten()
+                    .for_each(|v| data.extend(v.to_le_bytes()));
+            }
+            data
+        };
+        // tolerance grows with the distance from the origin
+        let data = file(&[
+            [0.000001, 0.0, 0.0],
+            [10.0, -0.00005, 0.0],
+            [40000.0, 0.25, 0.0],
+        ]);
+        let graph: Graph = Cursor::new(&data).read_le().unwrap();
+        assert_eq!((graph.dims, graph.edge_count), (3, false));
+        assert_eq!(graph.edges, [vec![0, 1, 2], vec![], vec![]]);
+        assert_eq!(graph.snapped.len(), 3);
+        assert_eq!(graph.snapped[2].pos, [40000.0, 0.25, 0.0]);
+        let mut out = Cursor::new(vec![]);
+        out.write_le(&graph).unwrap();
+        assert_eq!(out.into_inner(), data);
+
+        let data = file(&[[0.001, 0.0, 0.0]]);
+        assert!(Cursor::new(&data).read_le::<Graph>().is_err());
+    }
+}
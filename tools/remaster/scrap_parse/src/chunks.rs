@@ -0,0 +1,346 @@
+//! Lazy walker over the chunk tree of a file, for exploring formats without a full parser
+//!
+//! A chunk is a 4 byte magic followed by a `u32` body size. Bodies mix plain fields with
+//! nested chunks, so a body is scanned for headers: any plausible magic is accepted at the
+//! start of a range or right after another chunk, in between only known magics are, the
+//! bytes around them are returned as opaque ranges. Nothing here fails, bad headers end the
+//! current range with an error instead.
+use crate::{
+    AMC, ANI, CAM, CM3, CMSH, DUM, EMI, EVA, INI, LFVF, LUZ, MAP, MAT, MD3D, MST, NABK, NAM, PORT,
+    QUAD, SCN, SM3, SPR3, SUEL,
+};
+use anyhow::{anyhow, Result};
+use binrw::io::Cursor;
+use binrw::BinReaderExt;
+use serde::Serialize;
+
+/// Magics of the chunks in [`crate`] and what they contain
+const KNOWN: &[(&[u8; 4], &str)] = &[
+    (b"AMC\0", "Collision"),
+    (b"ANI\0", "Animation"),
+    (b"CAM\0", "Camera"),
+    (b"CM3\0", "Animated model"),
+    (b"CMSH", "Collision mesh"),
+    (b"DUM\0", "Dummies"),
+    (b"EMI\0", "Level geometry"),
+    (b"EVA\0", "Animation events"),
+    (b"INI\0", "Properties"),
+    (b"LFVF", "Vertex data"),
+    (b"LUZ\0", "Light"),
+    (b"MAP\0", "Texture map"),
+    (b"MAT\0", "Material"),
+    (b"MD3D", "Mesh"),
+    (b"MST\0", "Sprite table"),
+    (b"NABK", "Node animation data"),
+    (b"NAM\0", "Node animation"),
+    (b"PORT", "Portal"),
+    (b"QUAD", "Collision quadtree"),
+    (b"SCN\0", "Scene"),
+    (b"SM3\0", "Model"),
+    (b"SPR3", "Sprite"),
+    (b"SUEL", "Floor"),
+    (b"TRI\0", "Triangle data"),
+];
+
+fn plausible_magic(magic: &[u8]) -> bool {
+    let len = magic.iter().position(|&c| c == 0).unwrap_or(magic.len());
+    len >= 3
+        && magic[..len]
+            .iter()
+            .all(|&c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == b'_')
+        && magic[len..].iter().all(|&c| c == 0)
+}
+
+fn parse<T>(data: &[u8]) -> Result<serde_json::Value>
+where
+    T: for<'a> binrw::BinRead<Args<'a> = ()> + Serialize,
+{
+    let value: T = Cursor::new(data).read_le()?;
+    Ok(serde_json::to_value(value)?)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Chunk<'a> {
+    pub(crate) magic: [u8; 4],
+    /// Offset of the header in the walked data
+    pub(crate) offset: usize,
+    /// Header and body
+    raw: &'a [u8],
+}
+
+impl<'a> Chunk<'a> {
+    pub(crate) fn magic_str(&self) -> String {
+        self.magic
+            .iter()
+            .take_while(|&&c| c != 0)
+            .map(|&c| c as char)
+            .collect()
+    }
+
+    pub(crate) fn description(&self) -> Option<&'static str> {
+        KNOWN
+            .iter()
+            .find(|(magic, _)| **magic == self.magic)
+            .map(|(_, desc)| *desc)
+    }
+
+    pub(crate) fn body(&self) -> &'a [u8] {
+        &self.raw[8..]
+    }
+
+    /// Nested chunks and the bytes between them
+    pub(crate) fn children(&self) -> Walker<'a> {
+        Walker {
+            data: self.body(),
+            base: self.offset + 8,
+            pos: 0,
+            at_boundary: true,
+        }
+    }
+
+    /// Parses the chunk with its parser from [`crate`], `None` for unknown magics and
+    /// chunks that can only be read as part of their parent
+    pub(crate) fn parse(&self) -> Option<Result<serde_json::Value>> {
+        Some(match &self.magic {
+            b"AMC\0" => parse::<AMC>(self.raw),
+            b"ANI\0" => parse::<ANI>(self.raw),
+            b"CAM\0" => parse::<CAM>(self.raw),
+            b"CM3\0" => parse::<CM3>(self.raw),
+            b"CMSH" => parse::<CMSH>(self.raw),
+            b"DUM\0" => parse::<DUM>(self.raw),
+            b"EMI\0" => parse::<EMI>(self.raw),
+            b"EVA\0" => parse::<EVA>(self.raw),
+            b"INI\0" => parse::<INI>(self.raw),
+            b"LFVF" => parse::<LFVF>(self.raw),
+            b"LUZ\0" => parse::<LUZ>(self.raw),
+            b"MAP\0" => parse::<MAP>(self.raw),
+            b"MAT\0" => parse::<MAT>(self.raw),
+            b"MD3D" => parse::<MD3D>(self.raw),
+            b"MST\0" => parse::<MST>(self.raw),
+            b"NABK" => parse::<NABK>(self.raw),
+            b"NAM\0" => parse::<NAM>(self.raw),
+            b"PORT" => parse::<PORT>(self.raw),
+            b"QUAD" => parse::<QUAD>(self.raw),
+            b"SCN\0" => parse::<SCN>(self.raw),
+            b"SM3\0" => parse::<SM3>(self.raw),
+            b"SPR3" => parse::<SPR3>(self.raw),
+            b"SUEL" => parse::<SUEL>(self.raw),
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum Node<'a> {
+    Chunk(Chunk<'a>),
+    /// Bytes that aren't a chunk, with the reason the walk stopped if it did
+    Bytes {
+        offset: usize,
+        data: &'a [u8],
+        error: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Walker<'a> {
+    data: &'a [u8],
+    /// Offset of `data` in the walked file
+    base: usize,
+    pos: usize,
+    /// Any plausible magic is accepted at `pos`
+    at_boundary: bool,
+}
+
+/// Walks the chunks of a whole file
+pub(crate) fn walk(data: &[u8]) -> Walker<'_> {
+    Walker {
+        data,
+        base: 0,
+        pos: 0,
+        at_boundary: true,
+    }
+}
+
+impl<'a> Walker<'a> {
+    fn header(&self, pos: usize, any_magic: bool) -> Option<Result<Chunk<'a>>> {
+        let header = self.data.get(pos..pos + 8)?;
+        let magic: [u8; 4] = header[..4].try_into().ok()?;
+        let known = KNOWN.iter().any(|(known, _)| **known == magic);
+        if !(known || any_magic && plausible_magic(&magic)) {
+            return None;
+        }
+        let size = u32::from_le_bytes(header[4..].try_into().ok()?) as usize;
+        let left = self.data.len() - pos - 8;
+        if size > left {
+            // a known magic at a boundary is most likely a truncated chunk, anything
+            // else is probably just data that happens to look like a header
+            return (known && any_magic).then(|| {
+                Err(anyhow!(
+                    "{} chunk at 0x{:x} claims {size} bytes, only {left} left",
+                    String::from_utf8_lossy(&magic).trim_end_matches('\0'),
+                    self.base + pos
+                ))
+            });
+        }
+        Some(Ok(Chunk {
+            magic,
+            offset: self.base + pos,
+            raw: &self.data[pos..pos + 8 + size],
+        }))
+    }
+}
+
+impl<'a> Iterator for Walker<'a> {
+    type Item = Node<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = self.pos;
+        if start >= self.data.len() {
+            return None;
+        }
+        let mut pos = start;
+        let mut any_magic = self.at_boundary;
+        while pos < self.data.len() {
+            match self.header(pos, any_magic) {
+                Some(Ok(chunk)) if pos == start => {
+                    self.pos = pos + chunk.raw.len();
+                    self.at_boundary = true;
+                    return Some(Node::Chunk(chunk));
+                }
+                Some(Ok(_)) => {
+                    self.pos = pos;
+                    self.at_boundary = true;
+                    break;
+                }
+                Some(Err(e)) => {
+                    self.pos = self.data.len();
+                    return Some(Node::Bytes {
+                        offset: self.base + start,
+                        data: &self.data[start..],
+                        error: Some(e.to_string()),
+                    });
+                }
+                None => {
+                    pos += 1;
+                    any_magic = false;
+                }
+            }
+        }
+        if pos >= self.data.len() {
+            self.pos = pos;
+        }
+        Some(Node::Bytes {
+            offset: self.base + start,
+            data: &self.data[start..self.pos],
+            error: None,
+        })
+    }
+}
+
+/// Eagerly walked tree for dumping
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum TreeNode {
+    Chunk {
+        magic: String,
+        description: Option<&'static str>,
+        offset: usize,
+        size: usize,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        data: Option<serde_json::Value>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+        children: Vec<TreeNode>,
+    },
+    Bytes {
+        offset: usize,
+        size: usize,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+}
+
+/// Walks `nodes` down to `max_depth` levels, with `parse` known chunks are parsed into
+/// `data` and the error of the ones that fail is kept
+pub(crate) fn tree(nodes: Walker, max_depth: Option<usize>, parse: bool) -> Vec<TreeNode> {
+    nodes
+        .map(|node| match node {
+            Node::Chunk(chunk) => {
+                let (data, error) = match chunk.parse().filter(|_| parse) {
+                    Some(Ok(data)) => (Some(data), None),
+                    Some(Err(e)) => (None, Some(e.to_string())),
+                    None => (None, None),
+                };
+                let children = match max_depth {
+                    Some(0) => vec![],
+                    _ => tree(chunk.children(), max_depth.map(|d| d - 1), parse),
+                };
+                TreeNode::Chunk {
+                    magic: chunk.magic_str(),
+                    description: chunk.description(),
+                    offset: chunk.offset,
+                    size: chunk.body().len(),
+                    data,
+                    error,
+                    children,
+                }
+            }
+            Node::Bytes {
+                offset,
+                data,
+                error,
+            } => TreeNode::Bytes {
+                offset,
+                size: data.len(),
+                error,
+            },
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::chunk;
+
+    #[test]
+    fn walk_nested() {
+        let mut ini = 0u32.to_le_bytes().to_vec(); // num_sections
+        ini = chunk(b"INI\0", &ini);
+        let mut body = 7u32.to_le_bytes().to_vec(); // plain field before the nested chunk
+        body.extend(&ini);
+        body.extend(chunk(b"XYZ\0", b"abc"));
+        let mut data = chunk(b"MODX", &body);
+        data.extend(chunk(b"DUM\0", &[0; 4])[..10].to_vec());
+
+        let nodes: Vec<_> = walk(&data).collect();
+        let [Node::Chunk(root), Node::Bytes { offset, error, .. }] = &nodes[..] else {
+            panic!("unexpected nodes {nodes:?}");
+        };
+        assert_eq!(root.magic_str(), "MODX");
+        assert_eq!(root.description(), None);
+        assert_eq!(*offset, 8 + body.len());
+        assert!(error.as_ref().unwrap().contains("DUM chunk"));
+
+        let children: Vec<_> = root
+            .children()
+            .map(|node| match node {
+                Node::Chunk(chunk) => (chunk.magic_str(), chunk.offset),
+                Node::Bytes { offset, .. } => ("".to_owned(), offset),
+            })
+            .collect();
+        assert_eq!(
+            children,
+            [
+                ("".to_owned(), 8),
+                ("INI".to_owned(), 12),
+                ("XYZ".to_owned(), 12 + ini.len())
+            ]
+        );
+
+        let tree = tree(walk(&data), None, true);
+        let json = serde_json::to_value(&tree).unwrap();
+        assert_eq!(json[0]["children"][1]["data"], serde_json::json!([]));
+        assert_eq!(json[0]["children"][2]["children"][0]["size"], 3);
+    }
+}
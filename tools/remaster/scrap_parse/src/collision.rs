@@ -0,0 +1,414 @@
+//! Spatial queries against level collision geometry (AMC)
+//!
+//! Triangles come from every CMSH chunk, the QUAD tree is used as bounding volume
+//! hierarchy. A QUAD references faces through `mesh` (index into the CMSH chunks in file
+//! order, `cmsh` followed by both entries of each `sector_col`) and `table` (face indices
+//! into that mesh). That mapping is inferred, so node bounds are computed from the
+//! referenced triangles instead of trusting `f_4`, and triangles not referenced by any
+//! QUAD are kept in an extra root node. Results are exact either way, a wrong guess
+//! only costs speed (see [`Collision::unindexed`]).
+use crate::{AMC, CMSH, QUAD};
+use serde::Serialize;
+
+type Vec3 = [f32; 3];
+
+fn sub(a: Vec3, b: Vec3) -> Vec3 {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn dot(a: Vec3, b: Vec3) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: Vec3, b: Vec3) -> Vec3 {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub(crate) struct Aabb {
+    pub(crate) min: Vec3,
+    pub(crate) max: Vec3,
+}
+
+impl Aabb {
+    const EMPTY: Self = Self {
+        min: [f32::INFINITY; 3],
+        max: [f32::NEG_INFINITY; 3],
+    };
+
+    pub(crate) fn new(a: Vec3, b: Vec3) -> Self {
+        let mut ret = Self::EMPTY;
+        ret.add_point(a);
+        ret.add_point(b);
+        ret
+    }
+
+    fn add_point(&mut self, p: Vec3) {
+        for ((min, max), v) in self.min.iter_mut().zip(&mut self.max).zip(p) {
+            *min = min.min(v);
+            *max = max.max(v);
+        }
+    }
+
+    fn add(&mut self, other: &Self) {
+        self.add_point(other.min);
+        self.add_point(other.max);
+    }
+
+    fn intersects(&self, other: &Self) -> bool {
+        (0..3).all(|n| self.min[n] <= other.max[n] && other.min[n] <= self.max[n])
+    }
+
+    fn contains(&self, p: Vec3) -> bool {
+        (0..3).all(|n| self.min[n] <= p[n] && p[n] <= self.max[n])
+    }
+
+    /// Distance along the ray at which it enters the box (slab test)
+    fn ray(&self, origin: Vec3, inv_dir: Vec3, max_dist: f32) -> Option<f32> {
+        let (mut t_min, mut t_max) = (0.0f32, max_dist);
+        for n in 0..3 {
+            let t_1 = (self.min[n] - origin[n]) * inv_dir[n];
+            let t_2 = (self.max[n] - origin[n]) * inv_dir[n];
+            // NaN (0 * inf, ray in the slab plane) leaves the interval unchanged
+            t_min = t_min.max(t_1.min(t_2));
+            t_max = t_max.min(t_1.max(t_2));
+        }
+        (t_min <= t_max).then_some(t_min)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Triangle {
+    /// Index into [`Collision::meshes`]
+    pub(crate) mesh: usize,
+    /// Index into the faces of the CMSH
+    pub(crate) face: usize,
+    pub(crate) verts: [Vec3; 3],
+}
+
+impl Triangle {
+    fn bbox(&self) -> Aabb {
+        let mut ret = Aabb::EMPTY;
+        self.verts.iter().for_each(|&v| ret.add_point(v));
+        ret
+    }
+
+    /// Separating axis test (Akenine-Möller)
+    fn intersects(&self, bbox: &Aabb) -> bool {
+        let center: Vec3 = std::array::from_fn(|n| (bbox.min[n] + bbox.max[n]) / 2.0);
+        let half: Vec3 = std::array::from_fn(|n| (bbox.max[n] - bbox.min[n]) / 2.0);
+        let v = self.verts.map(|p| sub(p, center));
+        let edges = [sub(v[1], v[0]), sub(v[2], v[1]), sub(v[0], v[2])];
+        let separated = |axis: Vec3| {
+            let proj = v.map(|p| dot(p, axis));
+            let r = dot(half, axis.map(f32::abs));
+            proj.iter().copied().fold(f32::INFINITY, f32::min) > r
+                || proj.iter().copied().fold(f32::NEG_INFINITY, f32::max) < -r
+        };
+        let box_axes = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+        if box_axes.iter().any(|&axis| separated(axis)) {
+            return false;
+        }
+        if separated(cross(edges[0], edges[1])) {
+            return false;
+        }
+        !edges
+            .iter()
+            .any(|&edge| box_axes.iter().any(|&axis| separated(cross(axis, edge))))
+    }
+
+    /// Double sided Möller-Trumbore intersection
+    fn ray(&self, origin: Vec3, dir: Vec3) -> Option<f32> {
+        let [a, b, c] = self.verts;
+        let (e_1, e_2) = (sub(b, a), sub(c, a));
+        let p = cross(dir, e_2);
+        let det = dot(e_1, p);
+        if det.abs() < f32::EPSILON {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+        let s = sub(origin, a);
+        let u = dot(s, p) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = cross(s, e_1);
+        let v = dot(dir, q) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let t = dot(e_2, q) * inv_det;
+        (t >= 0.0).then_some(t)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct MeshInfo {
+    pub(crate) name: String,
+    pub(crate) sector: u16,
+    /// `None` for the two global meshes, else the index into `sector_col`
+    pub(crate) sector_index: Option<usize>,
+    pub(crate) bbox: Aabb,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct RayHit<'a> {
+    pub(crate) distance: f32,
+    pub(crate) point: Vec3,
+    pub(crate) triangle: &'a Triangle,
+}
+
+#[derive(Debug)]
+struct Node {
+    bbox: Aabb,
+    triangles: Vec<usize>,
+    children: Vec<usize>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Collision {
+    meshes: Vec<MeshInfo>,
+    triangles: Vec<Triangle>,
+    nodes: Vec<Node>,
+    roots: Vec<usize>,
+    unindexed: usize,
+}
+
+impl Collision {
+    pub(crate) fn new(amc: &AMC) -> Self {
+        let cmsh: Vec<(Option<usize>, &CMSH)> = amc
+            .cmsh
+            .iter()
+            .map(|cmsh| (None, cmsh))
+            .chain(
+                amc.sector_col
+                    .iter()
+                    .enumerate()
+                    .flat_map(|(n, pair)| pair.iter().map(move |cmsh| (Some(n), cmsh))),
+            )
+            .collect();
+        let mut ret = Self {
+            meshes: vec![],
+            triangles: vec![],
+            nodes: vec![],
+            roots: vec![],
+            unindexed: 0,
+        };
+        // (mesh, face) -> triangle, faces referencing missing vertices are dropped
+        let mut lookup: Vec<Vec<Option<usize>>> = vec![];
+        for (mesh, (sector_index, cmsh)) in cmsh.into_iter().enumerate() {
+            let [a, b] = cmsh.bbox_1;
+            ret.meshes.push(MeshInfo {
+                name: cmsh.name.string.clone(),
+                sector: cmsh.sector,
+                sector_index,
+                bbox: Aabb::new(a, b),
+            });
+            let verts = &cmsh.verts.data;
+            let faces = cmsh.faces.data.iter().enumerate().map(|(face, data)| {
+                let [a, b, c] = data.indices().map(|idx| verts.get(idx as usize).copied());
+                ret.triangles.push(Triangle {
+                    mesh,
+                    face,
+                    verts: [a?, b?, c?],
+                });
+                Some(ret.triangles.len() - 1)
+            });
+            lookup.push(faces.collect());
+        }
+        let mut referenced = vec![false; ret.triangles.len()];
+        for quad in &amc.quads {
+            let root = ret.add_quad(quad, &lookup, &mut referenced);
+            ret.roots.push(root);
+        }
+        let rest: Vec<usize> = (0..ret.triangles.len())
+            .filter(|&idx| !referenced[idx])
+            .collect();
+        ret.unindexed = rest.len();
+        if !rest.is_empty() {
+            let root = ret.add_node(rest, vec![]);
+            ret.roots.push(root);
+        }
+        ret
+    }
+
+    fn add_node(&mut self, triangles: Vec<usize>, children: Vec<usize>) -> usize {
+        let mut bbox = Aabb::EMPTY;
+        for &tri in &triangles {
+            bbox.add(&self.triangles[tri].bbox());
+        }
+        for &child in &children {
+            bbox.add(&self.nodes[child].bbox);
+        }
+        self.nodes.push(Node {
+            bbox,
+            triangles,
+            children,
+        });
+        self.nodes.len() - 1
+    }
+
+    fn add_quad(
+        &mut self,
+        quad: &QUAD,
+        lookup: &[Vec<Option<usize>>],
+        referenced: &mut [bool],
+    ) -> usize {
+        let faces = lookup.get(quad.mesh as usize);
+        let triangles: Vec<usize> = quad
+            .table
+            .data
+            .iter()
+            .filter_map(|&face| *faces?.get(usize::from(face))?)
+            .collect();
+        for &tri in &triangles {
+            referenced[tri] = true;
+        }
+        let children = quad
+            .children
+            .iter()
+            .map(|child| self.add_quad(child, lookup, referenced))
+            .collect();
+        self.add_node(triangles, children)
+    }
+
+    pub(crate) fn meshes(&self) -> &[MeshInfo] {
+        &self.meshes
+    }
+
+    /// Number of triangles not referenced by the QUAD tree
+    pub(crate) fn unindexed(&self) -> usize {
+        self.unindexed
+    }
+
+    /// Collision meshes whose bounding box contains `point`
+    pub(crate) fn sectors_at(&self, point: Vec3) -> Vec<&MeshInfo> {
+        self.meshes
+            .iter()
+            .filter(|mesh| mesh.bbox.contains(point))
+            .collect()
+    }
+
+    /// All triangles touching `bbox`, a zero sized box can be used to query a point
+    pub(crate) fn query_aabb(&self, bbox: &Aabb) -> Vec<&Triangle> {
+        let mut ret = vec![];
+        let mut stack = self.roots.clone();
+        while let Some(node) = stack.pop() {
+            let node = &self.nodes[node];
+            if !node.bbox.intersects(bbox) {
+                continue;
+            }
+            ret.extend(
+                node.triangles
+                    .iter()
+                    .map(|&tri| &self.triangles[tri])
+                    .filter(|tri| tri.intersects(bbox)),
+            );
+            stack.extend(&node.children);
+        }
+        ret.sort_by_key(|tri| (tri.mesh, tri.face));
+        ret.dedup_by_key(|tri| (tri.mesh, tri.face));
+        ret
+    }
+
+    /// Closest triangle hit by the ray, `dir` doesn't need to be normalized
+    pub(crate) fn raycast(&self, origin: Vec3, dir: Vec3, max_dist: f32) -> Option<RayHit<'_>> {
+        let len = dot(dir, dir).sqrt();
+        if len == 0.0 {
+            return None;
+        }
+        let dir = dir.map(|v| v / len);
+        let inv_dir = dir.map(|v| 1.0 / v);
+        let mut best: Option<(f32, &Triangle)> = None;
+        let mut stack = self.roots.clone();
+        while let Some(node) = stack.pop() {
+            let node = &self.nodes[node];
+            let limit = best.map_or(max_dist, |(t, _)| t);
+            if node.bbox.ray(origin, inv_dir, limit).is_none() {
+                continue;
+            }
+            for &tri in &node.triangles {
+                let tri = &self.triangles[tri];
+                if let Some(t) = tri.ray(origin, dir) {
+                    if t <= best.map_or(max_dist, |(t, _)| t) {
+                        best = Some((t, tri));
+                    }
+                }
+            }
+            stack.extend(&node.children);
+        }
+        best.map(|(distance, triangle)| RayHit {
+            distance,
+            point: std::array::from_fn(|n| origin[n] + dir[n] * distance),
+            triangle,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn queries() {
+        // Two triangles forming the unit square at y=0 and one wall at x=5
+        let triangles = vec![
+            Triangle {
+                mesh: 0,
+                face: 0,
+                verts: [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 0.0, 1.0]],
+            },
+            Triangle {
+                mesh: 0,
+                face: 1,
+                verts: [[0.0, 0.0, 0.0], [1.0, 0.0, 1.0], [0.0, 0.0, 1.0]],
+            },
+            Triangle {
+                mesh: 1,
+                face: 0,
+                verts: [[5.0, 0.0, 0.0], [5.0, 2.0, 0.0], [5.0, 0.0, 2.0]],
+            },
+        ];
+        let mut collision = Collision {
+            meshes: vec![],
+            triangles,
+            nodes: vec![],
+            roots: vec![],
+            unindexed: 0,
+        };
+        let floor = collision.add_node(vec![0, 1], vec![]);
+        let root = collision.add_node(vec![2], vec![floor]);
+        collision.roots.push(root);
+
+        let hits = collision.query_aabb(&Aabb::new([0.2, -0.1, 0.7], [0.3, 0.1, 0.8]));
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].face, 1);
+        // point on the shared diagonal touches both
+        assert_eq!(
+            collision
+                .query_aabb(&Aabb::new([0.5; 3], [0.5, 0.0, 0.5]))
+                .len(),
+            2
+        );
+        assert!(collision
+            .query_aabb(&Aabb::new([2.0, 0.0, 0.0], [3.0, 1.0, 1.0]))
+            .is_empty());
+
+        let hit = collision
+            .raycast([0.25, 10.0, 0.5], [0.0, -2.0, 0.0], f32::INFINITY)
+            .unwrap();
+        assert_eq!(hit.distance, 10.0);
+        assert_eq!(hit.point, [0.25, 0.0, 0.5]);
+        let hit = collision
+            .raycast([0.5, 0.5, 0.5], [1.0, 0.0, 0.0], f32::INFINITY)
+            .unwrap();
+        assert_eq!((hit.triangle.mesh, hit.distance), (1, 4.5));
+        assert!(collision
+            .raycast([0.5, 0.5, 0.5], [1.0, 0.0, 0.0], 4.0)
+            .is_none());
+    }
+}
@@ -0,0 +1,172 @@
+use crate::parse_data;
+use anyhow::Result;
+use binrw::io::Cursor;
+use binrw::BinWriterExt;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::Read;
+use vfs::VfsPath;
+
+/// Extensions of the chunked formats handled by [`crate::Data`]
+pub(crate) const CHUNKED_EXTENSIONS: &[&str] = &["sm3", "cm3", "dum", "amc", "emi", "mst"];
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub(crate) enum FileStatus {
+    Parsed {
+        /// Bytes left over after the parser finished
+        trailing_bytes: u64,
+        /// Writing the parsed data back reproduces the parsed bytes exactly
+        roundtrip: bool,
+    },
+    Failed {
+        error: String,
+    },
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct FileResult {
+    pub(crate) path: String,
+    pub(crate) extension: String,
+    #[serde(flatten)]
+    pub(crate) status: FileStatus,
+}
+
+#[derive(Debug, Default, Serialize, PartialEq, Eq)]
+pub(crate) struct ExtensionStats {
+    pub(crate) files: usize,
+    pub(crate) parsed: usize,
+    pub(crate) failed: usize,
+    pub(crate) with_trailing_bytes: usize,
+    pub(crate) trailing_bytes: u64,
+    pub(crate) roundtrip_mismatch: usize,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct CoverageReport {
+    pub(crate) extensions: BTreeMap<String, ExtensionStats>,
+    pub(crate) files: Vec<FileResult>,
+}
+
+impl CoverageReport {
+    fn add(&mut self, result: FileResult) {
+        let stats = self.extensions.entry(result.extension.clone()).or_default();
+        stats.files += 1;
+        match &result.status {
+            FileStatus::Parsed {
+                trailing_bytes,
+                roundtrip,
+            } => {
+                stats.parsed += 1;
+                if *trailing_bytes != 0 {
+                    stats.with_trailing_bytes += 1;
+                    stats.trailing_bytes += trailing_bytes;
+                }
+                if !roundtrip {
+                    stats.roundtrip_mismatch += 1;
+                }
+            }
+            FileStatus::Failed { .. } => stats.failed += 1,
+        }
+        self.files.push(result);
+    }
+}
+
+fn check_data(path: &VfsPath, data: &[u8]) -> Result<FileStatus> {
+    let parsed = parse_data(path, data)?;
+    let mut out = Cursor::new(Vec::with_capacity(data.len()));
+    out.write_le(&parsed.data)?;
+    let consumed = usize::try_from(parsed.end)?;
+    Ok(FileStatus::Parsed {
+        trailing_bytes: parsed.trailing_bytes,
+        roundtrip: out.get_ref().as_slice() == &data[..consumed],
+    })
+}
+
+pub(crate) fn check_file(path: &VfsPath) -> FileResult {
+    let extension = path.extension().unwrap_or_default().to_ascii_lowercase();
+    let mut data = vec![];
+    let status = path
+        .open_file()
+        .map_err(anyhow::Error::from)
+        .and_then(|mut fh| Ok(fh.read_to_end(&mut data)?))
+        .and_then(|_| check_data(path, &data))
+        .unwrap_or_else(|e| FileStatus::Failed {
+            error: format!("{e}"),
+        });
+    FileResult {
+        path: path.as_str().to_owned(),
+        extension,
+        status,
+    }
+}
+
+/// Parses and re-writes every chunked file below `root`
+pub(crate) fn run(root: &VfsPath) -> Result<CoverageReport> {
+    let mut report = CoverageReport::default();
+    for path in root.walk_dir()? {
+        let path = path?;
+        let is_chunked = path
+            .extension()
+            .map(|ext| CHUNKED_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+            .unwrap_or(false);
+        if !is_chunked || !path.is_file()? {
+            continue;
+        }
+        report.add(check_file(&path));
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::chunk;
+    use std::io::Write;
+    use vfs::MemoryFS;
+
+    fn dum(version: u32) -> Vec<u8> {
+        let mut body = vec![];
+        for v in [version, 1, 0, 4] {
+            body.extend(v.to_le_bytes());
+        }
+        body.extend(b"DM_\0");
+        body.extend([0u8; 24]);
+        body.extend(0u32.to_le_bytes());
+        chunk(b"DUM\0", &body)
+    }
+
+    fn fixtures() -> VfsPath {
+        let root = VfsPath::new(MemoryFS::new());
+        let mut trailing = dum(1);
+        trailing.extend([0xff; 3]);
+        for (path, data) in [
+            ("levels/test/map/map3d.dum", dum(1)),
+            ("levels/test/map/moredummies.dum", trailing),
+            ("levels/test/map/broken.DUM", dum(2)),
+            ("levels/test/map/map3d.ini", b"[model]\n".to_vec()),
+        ] {
+            let path = root.join(path).unwrap();
+            path.parent().create_dir_all().unwrap();
+            path.create_file().unwrap().write_all(&data).unwrap();
+        }
+        root
+    }
+
+    #[test]
+    fn report() {
+        let report = run(&fixtures()).unwrap();
+        assert_eq!(report.files.len(), 3);
+        assert_eq!(
+            report.extensions["dum"],
+            ExtensionStats {
+                files: 3,
+                parsed: 2,
+                failed: 1,
+                with_trailing_bytes: 1,
+                trailing_bytes: 3,
+                roundtrip_mismatch: 0,
+            }
+        );
+    }
+}
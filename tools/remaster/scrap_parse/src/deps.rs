@@ -0,0 +1,408 @@
+//! Asset dependency graph over a whole install
+//!
+//! Edges point from the user to the used asset, nodes are VFS paths except for dummies,
+//! which are named `<level path>#<dummy name>`. Models are resolved against the
+//! `map3d.ini` next to them like [`crate::Level`] does.
+use crate::ini::IniFile;
+use crate::{link_dummies, load_ini, parse_file, resolve_dep, texture, Data, DUM};
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use vfs::VfsPath;
+
+const MODEL_EXTENSIONS: &[&str] = &["sm3", "cm3", "emi"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum NodeKind {
+    Level,
+    Model,
+    Texture,
+    Dummy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum EdgeKind {
+    /// Model → texture used by one of its materials
+    Texture,
+    /// Texture → its separate alpha map
+    Alpha,
+    /// Level → model in its `map/` directory
+    Model,
+    /// Level → dummy from `map3d.dum` or `moredummies.ini`
+    Dummy,
+    /// Dummy → model named in its properties
+    DummyModel,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub(crate) struct Edge {
+    pub(crate) from: String,
+    pub(crate) to: String,
+    pub(crate) kind: EdgeKind,
+}
+
+/// Reference that doesn't match any file
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub(crate) struct Unresolved {
+    pub(crate) from: String,
+    pub(crate) reference: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct AssetGraph {
+    nodes: BTreeMap<String, NodeKind>,
+    edges: BTreeSet<Edge>,
+    unresolved: BTreeSet<Unresolved>,
+    /// Files that failed to parse
+    errors: BTreeMap<String, String>,
+}
+
+fn has_extension(path: &str, exts: &[&str]) -> bool {
+    path.rsplit_once('.')
+        .is_some_and(|(_, ext)| exts.iter().any(|e| e.eq_ignore_ascii_case(ext)))
+}
+
+/// Model file names in the values of `key=value` lines
+fn model_references<'a, I: IntoIterator<Item = &'a str>>(values: I) -> Vec<String> {
+    values
+        .into_iter()
+        .map(|value| value.trim().trim_matches(|c| c == '"' || c == '\0'))
+        .filter(|value| has_extension(value, &["sm3", "cm3"]))
+        .map(|value| value.replace('\\', "/"))
+        .collect()
+}
+
+impl AssetGraph {
+    /// Parses every model, level and dummy file below `root`, files that fail to parse
+    /// are recorded in `errors` and skipped
+    pub(crate) fn build(root: &VfsPath) -> Result<Self> {
+        let mut graph = Self::default();
+        for path in root.walk_dir()? {
+            let path = path?;
+            if !path.is_file()? {
+                continue;
+            }
+            let name = path.as_str();
+            if texture::is_texture(name) {
+                graph.add_node(name, NodeKind::Texture);
+                if let Some(alpha) = texture::alpha_path(&path) {
+                    graph.add_node(alpha.as_str(), NodeKind::Texture);
+                    graph.add_edge(name, alpha.as_str(), EdgeKind::Alpha);
+                }
+            } else if has_extension(name, MODEL_EXTENSIONS) {
+                graph.add_node(name, NodeKind::Model);
+                match parse_file(&path) {
+                    Ok(file) => {
+                        let base = path.parent();
+                        let config = load_ini(&base.join("map3d.ini")?);
+                        graph.add_model(name, file.data.dependencies(), &base, &config);
+                    }
+                    Err(e) => {
+                        graph.errors.insert(name.to_owned(), e.to_string());
+                    }
+                }
+            }
+            let is_level = path.filename().eq_ignore_ascii_case("map3d.emi")
+                && path.parent().filename().eq_ignore_ascii_case("map");
+            if is_level {
+                graph.add_level(&path.parent().parent())?;
+            }
+        }
+        Ok(graph)
+    }
+
+    fn add_node(&mut self, name: &str, kind: NodeKind) {
+        self.nodes.entry(name.to_owned()).or_insert(kind);
+    }
+
+    fn add_edge(&mut self, from: &str, to: &str, kind: EdgeKind) {
+        self.edges.insert(Edge {
+            from: from.to_owned(),
+            to: to.to_owned(),
+            kind,
+        });
+    }
+
+    fn add_unresolved(&mut self, from: &str, reference: &str) {
+        self.unresolved.insert(Unresolved {
+            from: from.to_owned(),
+            reference: reference.to_owned(),
+        });
+    }
+
+    fn add_model(&mut self, model: &str, deps: Vec<String>, base: &VfsPath, config: &IniFile) {
+        for dep in deps {
+            match resolve_dep(&dep, base, config) {
+                Some(path) => {
+                    self.add_node(path.as_str(), NodeKind::Texture);
+                    self.add_edge(model, path.as_str(), EdgeKind::Texture);
+                }
+                None => self.add_unresolved(model, &dep),
+            }
+        }
+    }
+
+    fn add_level(&mut self, level: &VfsPath) -> Result<()> {
+        let name = level.as_str();
+        let map_path = level.join("map")?;
+        self.add_node(name, NodeKind::Level);
+        for entry in map_path.read_dir()? {
+            if has_extension(entry.as_str(), MODEL_EXTENSIONS) && entry.is_file()? {
+                self.add_node(entry.as_str(), NodeKind::Model);
+                self.add_edge(name, entry.as_str(), EdgeKind::Model);
+            }
+        }
+
+        let dum_path = map_path.join("map3d.dum")?;
+        let empty = || DUM {
+            version: 1,
+            dummies: vec![],
+        };
+        let dum = match dum_path
+            .exists()?
+            .then(|| parse_file(&dum_path).map(|file| file.data))
+        {
+            Some(Ok(Data::DUM(dum))) => dum,
+            Some(Ok(_)) => {
+                self.errors
+                    .insert(dum_path.as_str().to_owned(), "Not a DUM file".to_owned());
+                empty()
+            }
+            Some(Err(e)) => {
+                self.errors
+                    .insert(dum_path.as_str().to_owned(), e.to_string());
+                empty()
+            }
+            None => empty(),
+        };
+        let moredummies = load_ini(&map_path.join("moredummies.ini")?);
+        for dummy in link_dummies(&dum, &moredummies) {
+            let id = format!("{name}#{}", dummy.name);
+            self.add_node(&id, NodeKind::Dummy);
+            self.add_edge(name, &id, EdgeKind::Dummy);
+            let info = dummy
+                .dum
+                .and_then(|n| dum.dummies[n].info.value.as_ref())
+                .map(|ini| ini.ini())
+                .unwrap_or_default();
+            let info = info.entries().map(|(_, key, value)| value.unwrap_or(key));
+            let section = dummy
+                .moredummies
+                .as_deref()
+                .into_iter()
+                .flat_map(|section| moredummies.section(section))
+                .map(|(key, value)| value.unwrap_or(key));
+            for reference in model_references(info.chain(section)) {
+                let path = [level.root(), level.clone(), map_path.clone()]
+                    .iter()
+                    .filter_map(|base| base.join(&reference).ok())
+                    .find(|path| path.is_file().unwrap_or(false));
+                match path {
+                    Some(path) => {
+                        self.add_node(path.as_str(), NodeKind::Model);
+                        self.add_edge(&id, path.as_str(), EdgeKind::DummyModel);
+                    }
+                    None => self.add_unresolved(&id, &reference),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn find_node(&self, path: &str) -> Option<&str> {
+        let path = format!("/{}", path.trim_start_matches('/'));
+        self.nodes
+            .get_key_value(&path)
+            .or_else(|| {
+                self.nodes
+                    .iter()
+                    .find(|(node, _)| node.eq_ignore_ascii_case(&path))
+            })
+            .map(|(node, _)| node.as_str())
+    }
+
+    /// Edges pointing to `path`, matched case insensitively
+    pub(crate) fn users_of(&self, path: &str) -> Vec<&Edge> {
+        let Some(path) = self.find_node(path) else {
+            return vec![];
+        };
+        self.edges.iter().filter(|edge| edge.to == path).collect()
+    }
+
+    /// Everything that directly or indirectly uses `path`
+    pub(crate) fn affected_by(&self, path: &str) -> BTreeSet<&str> {
+        let mut ret = BTreeSet::new();
+        let mut todo: Vec<&str> = self.find_node(path).into_iter().collect();
+        while let Some(node) = todo.pop() {
+            for edge in self.edges.iter().filter(|edge| edge.to == node) {
+                if ret.insert(edge.from.as_str()) {
+                    todo.push(&edge.from);
+                }
+            }
+        }
+        ret
+    }
+
+    /// Models and textures nothing refers to, alpha maps count as used if their texture is.
+    /// Assets only referenced by scripts or code show up here as well.
+    pub(crate) fn orphans(&self) -> Vec<&str> {
+        let is_asset = |node: &str| {
+            matches!(
+                self.nodes.get(node),
+                Some(NodeKind::Model | NodeKind::Texture)
+            )
+        };
+        let unused = |node: &str| !self.edges.iter().any(|edge| edge.to == node);
+        self.nodes
+            .keys()
+            .map(String::as_str)
+            .filter(|&node| is_asset(node))
+            .filter(|&node| {
+                self.edges
+                    .iter()
+                    .filter(|edge| edge.to == node)
+                    .all(|edge| edge.kind == EdgeKind::Alpha && unused(&edge.from))
+            })
+            .collect()
+    }
+
+    pub(crate) fn unresolved(&self) -> impl Iterator<Item = &Unresolved> {
+        self.unresolved.iter()
+    }
+
+    pub(crate) fn errors(&self) -> &BTreeMap<String, String> {
+        &self.errors
+    }
+
+    pub(crate) fn to_json(&self, pretty: bool) -> Result<String> {
+        Ok(match pretty {
+            true => serde_json::to_string_pretty(self)?,
+            false => serde_json::to_string(self)?,
+        })
+    }
+
+    /// Graphviz graph, unresolved references are drawn as red dashed edges
+    pub(crate) fn to_dot(&self) -> String {
+        let mut dot = "digraph assets {\n    rankdir=LR;\n".to_owned();
+        for (node, kind) in &self.nodes {
+            let shape = match kind {
+                NodeKind::Level => "folder",
+                NodeKind::Model => "box",
+                NodeKind::Texture => "note",
+                NodeKind::Dummy => "ellipse",
+            };
+            writeln!(dot, "    {node:?} [shape={shape}];").unwrap();
+        }
+        for edge in &self.edges {
+            let (from, to) = (&edge.from, &edge.to);
+            let label = serde_json::to_value(edge.kind).unwrap_or_default();
+            writeln!(dot, "    {from:?} -> {to:?} [label={label}];").unwrap();
+        }
+        for Unresolved { from, reference } in &self.unresolved {
+            writeln!(
+                dot,
+                "    {reference:?} [shape=plaintext, fontcolor=red];\n    {from:?} -> {reference:?} [style=dashed, color=red];"
+            )
+            .unwrap();
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::chunk;
+    use std::io::Write;
+    use vfs::MemoryFS;
+
+    fn dum() -> Vec<u8> {
+        let info = b"Model=models/props/barrel.sm3\0";
+        let mut ini = 1u32.to_le_bytes().to_vec(); // num_sections
+        ini.extend(1u32.to_le_bytes()); // num_lines
+        ini.extend(u32::try_from(info.len()).unwrap().to_le_bytes());
+        ini.extend(info);
+        let mut body = vec![];
+        body.extend(1u32.to_le_bytes()); // version
+        body.extend(1u32.to_le_bytes()); // num_dummies
+        body.extend(0u32.to_le_bytes()); // has_next
+        body.extend(7u32.to_le_bytes());
+        body.extend(b"DM_Box\0");
+        body.extend([0u8; 24]); // pos, rot
+        body.extend(1u32.to_le_bytes()); // info
+        body.extend(chunk(b"INI\0", &ini));
+        chunk(b"DUM\0", &body)
+    }
+
+    #[test]
+    fn graph() {
+        let root = VfsPath::new(MemoryFS::new());
+        for (path, data) in [
+            ("levels/town/map/map3d.emi", b"broken".to_vec()),
+            ("levels/town/map/map3d.dum", dum()),
+            (
+                "levels/town/map/moredummies.ini",
+                b"[DM_Car]\nmodel=models/car.sm3\n".to_vec(),
+            ),
+            ("models/props/barrel.sm3", b"broken".to_vec()),
+            ("models/props/dds/barrel.dds", vec![]),
+            ("models/props/dds/barrel.alpha.dds", vec![]),
+            ("models/props/dds/unused.dds", vec![]),
+            ("models/props/dds/unused.alpha.dds", vec![]),
+        ] {
+            let path = root.join(path).unwrap();
+            path.parent().create_dir_all().unwrap();
+            path.create_file().unwrap().write_all(&data).unwrap();
+        }
+        let mut graph = AssetGraph::build(&root).unwrap();
+        let barrel = root.join("models/props/barrel.sm3").unwrap();
+        graph.add_model(
+            barrel.as_str(),
+            vec!["barrel.tga".to_owned(), "missing.tga".to_owned()],
+            &barrel.parent(),
+            &IniFile::default(),
+        );
+
+        assert_eq!(graph.errors().len(), 2);
+        let users: Vec<_> = graph
+            .users_of("Models/Props/Barrel.sm3")
+            .into_iter()
+            .map(|edge| (edge.from.as_str(), edge.kind))
+            .collect();
+        assert_eq!(users, [("/levels/town#DM_Box", EdgeKind::DummyModel)]);
+        assert_eq!(
+            graph.affected_by("models/props/dds/barrel.alpha.dds"),
+            BTreeSet::from([
+                "/levels/town",
+                "/levels/town#DM_Box",
+                "/models/props/barrel.sm3",
+                "/models/props/dds/barrel.dds",
+            ])
+        );
+        assert_eq!(
+            graph.orphans(),
+            [
+                "/models/props/dds/unused.alpha.dds",
+                "/models/props/dds/unused.dds",
+            ]
+        );
+        let unresolved: Vec<_> = graph
+            .unresolved()
+            .map(|u| (u.from.as_str(), u.reference.as_str()))
+            .collect();
+        assert_eq!(
+            unresolved,
+            [
+                ("/levels/town#DM_Car", "models/car.sm3"),
+                ("/models/props/barrel.sm3", "missing.tga"),
+            ]
+        );
+        assert!(graph
+            .to_dot()
+            .contains("\"/levels/town\" -> \"/levels/town/map/map3d.emi\" [label=\"model\"];"));
+    }
+}
@@ -0,0 +1,274 @@
+//! Parse errors located in the chunk tree of the file they happened in
+use crate::chunks::{self, Node};
+use rhexdump::rhexdumps;
+use serde::Serialize;
+use std::fmt;
+
+/// Bytes shown before and after the error offset
+const HEXDUMP_CONTEXT: u64 = 0x20;
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct ParseError {
+    pub(crate) file: String,
+    pub(crate) message: String,
+    /// Chunks containing `offset`, outermost first, with the array entries read through
+    /// [`crate::indexed`] in between, e.g. `["SM3", "SCN", "nodes[17]", "MD3D", "LFVF"]`
+    pub(crate) chunk_path: Vec<String>,
+    /// Absolute offset of the field that failed
+    pub(crate) offset: Option<u64>,
+    /// What binrw was reading, outermost first
+    pub(crate) context: Vec<String>,
+    pub(crate) hexdump: Option<String>,
+}
+
+/// Context added to errors in array entries by [`crate::indexed`]
+#[derive(Debug)]
+pub(crate) struct EntryIndex {
+    pub(crate) index: usize,
+    /// Absolute offset of the entry
+    pub(crate) offset: u64,
+}
+
+impl fmt::Display for EntryIndex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "While parsing entry {} at 0x{:x}",
+            self.index, self.offset
+        )
+    }
+}
+
+/// Array entry an error happened in
+struct Entry {
+    /// Array field, from the binrw frame before the index
+    field: Option<String>,
+    index: usize,
+    offset: u64,
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let field = self.field.as_deref().unwrap_or_default();
+        write!(f, "{field}[{}]", self.index)
+    }
+}
+
+fn error_pos(err: &binrw::Error) -> Option<u64> {
+    match err {
+        binrw::Error::BadMagic { pos, .. }
+        | binrw::Error::AssertFail { pos, .. }
+        | binrw::Error::Custom { pos, .. }
+        | binrw::Error::NoVariantMatch { pos } => Some(*pos),
+        binrw::Error::EnumErrors { variant_errors, .. } => variant_errors
+            .iter()
+            .filter_map(|(_, err)| error_pos(err))
+            .max(),
+        binrw::Error::Backtrace(bt) => error_pos(&bt.error),
+        _ => None,
+    }
+}
+
+/// Follows backtraces and, for enums, the variant that got furthest, collecting frames
+/// and the array entries they pass through, outermost first
+fn root_cause<'a>(
+    err: &'a binrw::Error,
+    context: &mut Vec<String>,
+    entries: &mut Vec<Entry>,
+) -> &'a binrw::Error {
+    match err {
+        binrw::Error::Backtrace(bt) => {
+            for frame in bt.frames.iter().rev() {
+                let message = match frame {
+                    binrw::error::BacktraceFrame::Full { message, .. }
+                    | binrw::error::BacktraceFrame::Message(message) => message.to_string(),
+                    binrw::error::BacktraceFrame::Custom(err) => {
+                        if let Some(entry) = err.downcast_ref::<EntryIndex>() {
+                            // binrw names the field being read in its own frame
+                            let field = context.last().and_then(|message| {
+                                let rest = message.strip_prefix("While parsing field '")?;
+                                rest.split_once('\'').map(|(field, _)| field.to_owned())
+                            });
+                            entries.push(Entry {
+                                field,
+                                index: entry.index,
+                                offset: entry.offset,
+                            });
+                        }
+                        err.to_string()
+                    }
+                };
+                context.push(message);
+            }
+            root_cause(&bt.error, context, entries)
+        }
+        binrw::Error::EnumErrors { variant_errors, .. } => {
+            let furthest = variant_errors
+                .iter()
+                .filter(|(_, err)| !matches!(err.root_cause(), binrw::Error::BadMagic { .. }))
+                .max_by_key(|(_, err)| error_pos(err));
+            match furthest {
+                Some((variant, err)) => {
+                    context.push(format!("While parsing variant {variant}"));
+                    root_cause(err, context, entries)
+                }
+                None => err,
+            }
+        }
+        err => err,
+    }
+}
+
+/// Chunks containing `offset` with each entry placed before the first chunk starting in it
+fn chunk_path(data: &[u8], offset: u64, entries: &[Entry]) -> Vec<String> {
+    let Ok(offset) = usize::try_from(offset) else {
+        return vec![];
+    };
+    let mut path = vec![];
+    let mut entries = entries.iter().peekable();
+    let mut nodes = chunks::walk(data);
+    loop {
+        let chunk = nodes.find_map(|node| {
+            match node {
+                Node::Chunk(chunk) => Some(chunk),
+                Node::Bytes { .. } => None,
+            }
+            .filter(|chunk| (chunk.offset..chunk.offset + 8 + chunk.body().len()).contains(&offset))
+        });
+        let Some(chunk) = chunk else {
+            break;
+        };
+        while let Some(entry) = entries.next_if(|entry| entry.offset <= chunk.offset as u64) {
+            path.push(entry.to_string());
+        }
+        path.push(chunk.magic_str());
+        nodes = chunk.children();
+    }
+    path.extend(entries.map(Entry::to_string));
+    path
+}
+
+impl ParseError {
+    pub(crate) fn new(file: &str, err: &binrw::Error, data: &[u8]) -> Box<Self> {
+        let mut context = vec![];
+        let mut entries = vec![];
+        let cause = root_cause(err, &mut context, &mut entries);
+        let offset = error_pos(cause).or_else(|| {
+            // only running out of data has no position
+            cause.is_eof().then_some(data.len() as u64)
+        });
+        let message = match cause {
+            binrw::Error::AssertFail { message, .. } => message.clone(),
+            binrw::Error::Custom { err, .. } => err.to_string(),
+            binrw::Error::BadMagic { found, .. } => format!("Bad magic {found:?}"),
+            binrw::Error::EnumErrors { .. } => "No variant matched".to_owned(),
+            err => err.to_string(),
+        };
+        let hexdump = offset.map(|offset| {
+            let start = offset.saturating_sub(HEXDUMP_CONTEXT) & !0xf;
+            let end = (offset + HEXDUMP_CONTEXT).min(data.len() as u64);
+            let window = data.get(start as usize..end as usize).unwrap_or_default();
+            rhexdumps!(window, start)
+        });
+        Box::new(ParseError {
+            file: file.to_owned(),
+            message,
+            chunk_path: offset
+                .map(|o| chunk_path(data, o, &entries))
+                .unwrap_or_default(),
+            offset,
+            context,
+            hexdump,
+        })
+    }
+
+    /// Errors without a position in the file, e.g. when opening it
+    pub(crate) fn io(file: &str, err: impl fmt::Display) -> Box<Self> {
+        Box::new(ParseError {
+            file: file.to_owned(),
+            message: err.to_string(),
+            chunk_path: vec![],
+            offset: None,
+            context: vec![],
+            hexdump: None,
+        })
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.file)?;
+        if !self.chunk_path.is_empty() {
+            write!(f, " in {}", self.chunk_path.join("/"))?;
+        }
+        if let Some(offset) = self.offset {
+            write!(f, " at 0x{offset:x}")?;
+        }
+        write!(f, ": {}", self.message)?;
+        for context in &self.context {
+            write!(f, "\n  {context}")?;
+        }
+        if let Some(hexdump) = &self.hexdump {
+            write!(f, "\n{hexdump}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::chunk;
+    use crate::Data;
+    use binrw::io::Cursor;
+    use binrw::BinReaderExt;
+
+    fn cmsh(version: u32) -> Vec<u8> {
+        let mut body = vec![];
+        body.extend(version.to_le_bytes());
+        body.extend(0x34u32.to_le_bytes()); // collide_mesh_size
+        body.extend(0u32.to_le_bytes()); // name
+        body.extend([0u8; 8 + 24]); // unk_1 .. unk_4, bbox_1
+        body.extend([0, 0, 0, 0, 12, 0, 0, 0]); // verts
+        body.extend([0, 0, 0, 0, 0x1c, 0, 0, 0]); // faces
+        chunk(b"CMSH", &body)
+    }
+
+    #[test]
+    fn located() {
+        let amc = |sectors: &[Vec<u8>]| {
+            let mut body = vec![];
+            body.extend(100u32.to_le_bytes()); // version
+            body.extend(0u32.to_le_bytes()); // version_code
+            body.extend([0u8; 24 + 4 + 24 + 12]); // bbox_1, scale, bbox_2, unk
+            body.extend(cmsh(2));
+            let offset = 8 + body.len() as u64;
+            body.extend(if sectors.is_empty() { cmsh(3) } else { cmsh(2) });
+            body.extend(u32::try_from(sectors.len()).unwrap().to_le_bytes());
+            sectors.iter().for_each(|sector| body.extend(sector));
+            (chunk(b"AMC\0", &body), offset)
+        };
+        let parse = |data: &[u8]| {
+            let err = Cursor::new(data).read_le::<Data>().unwrap_err();
+            ParseError::new("map3d.amc", &err, data)
+        };
+
+        let (data, offset) = amc(&[]);
+        let err = parse(&data);
+        assert_eq!(err.message, "Invalid CMSH version");
+        assert_eq!(err.chunk_path, ["AMC", "cmsh[1]", "CMSH"]);
+        assert!((offset..offset + 16).contains(&err.offset.unwrap()));
+        assert!(err.context.iter().any(|c| c.contains("cmsh")));
+        assert!(err
+            .to_string()
+            .starts_with("map3d.amc in AMC/cmsh[1]/CMSH at 0x"));
+
+        let sector = [cmsh(2), cmsh(2)].concat();
+        let broken = [cmsh(2), cmsh(3)].concat();
+        let (data, _) = amc(&[sector.clone(), sector, broken]);
+        let err = parse(&data);
+        assert_eq!(err.chunk_path, ["AMC", "sector_col[2]", "CMSH"]);
+    }
+}
@@ -0,0 +1,847 @@
+use crate::space::{convert_dir, convert_rot, to_gltf_pos};
+use crate::{texture, with_extension, Data, Level, AMC, CMSH, EMI, LFVF, MAT, MD3D, SCN};
+use anyhow::{bail, Result};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::Write;
+use std::path::Path;
+use vfs::VfsPath;
+
+const FLOAT: u32 = 5126;
+const UNSIGNED_BYTE: u32 = 5121;
+const UNSIGNED_SHORT: u32 = 5123;
+const UNSIGNED_INT: u32 = 5125;
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+#[derive(Debug, Serialize)]
+struct Asset {
+    version: &'static str,
+    generator: &'static str,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Scene {
+    nodes: Vec<usize>,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Node {
+    name: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    children: Vec<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mesh: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    translation: Option<[f32; 3]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rotation: Option<[f32; 4]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scale: Option<[f32; 3]>,
+}
+
+#[derive(Debug, Serialize)]
+struct Mesh {
+    name: String,
+    primitives: Vec<Primitive>,
+}
+
+#[derive(Debug, Serialize)]
+struct Primitive {
+    attributes: HashMap<&'static str, usize>,
+    indices: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    material: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TextureInfo {
+    index: usize,
+    tex_coord: usize,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PbrMetallicRoughness {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    base_color_texture: Option<TextureInfo>,
+    metallic_factor: f32,
+    roughness_factor: f32,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Material {
+    name: String,
+    pbr_metallic_roughness: PbrMetallicRoughness,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    normal_texture: Option<TextureInfo>,
+    /// Level lightmap, glTF has no dedicated slot for baked lighting
+    #[serde(skip_serializing_if = "Option::is_none")]
+    occlusion_texture: Option<TextureInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    emissive_texture: Option<TextureInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    emissive_factor: Option<[f32; 3]>,
+    double_sided: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct Texture {
+    source: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct Image {
+    uri: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Accessor {
+    buffer_view: usize,
+    component_type: u32,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    normalized: bool,
+    count: usize,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    min: Option<Vec<f32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max: Option<Vec<f32>>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BufferView {
+    buffer: usize,
+    byte_offset: usize,
+    byte_length: usize,
+    target: u32,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Buffer {
+    uri: String,
+    byte_length: usize,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Gltf {
+    asset: Asset,
+    scene: usize,
+    scenes: Vec<Scene>,
+    nodes: Vec<Node>,
+    meshes: Vec<Mesh>,
+    materials: Vec<Material>,
+    textures: Vec<Texture>,
+    images: Vec<Image>,
+    accessors: Vec<Accessor>,
+    buffer_views: Vec<BufferView>,
+    buffers: Vec<Buffer>,
+}
+
+/// Percent-encodes everything but unreserved characters and path separators
+fn uri_encode(path: &str) -> String {
+    let mut ret = String::new();
+    for b in path.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~/".contains(&b) {
+            ret.push(char::from(b));
+        } else {
+            ret.push_str(&format!("%{b:02X}"));
+        }
+    }
+    ret
+}
+
+/// Builds a glTF 2.0 document from parsed scenes and level geometry
+///
+/// Texture paths are looked up in `dependencies` (as built by [`Level::load`]),
+/// converted to PNG and referenced relative to the output file, see [`Exporter::write`].
+pub(crate) struct Exporter<'a> {
+    gltf: Gltf,
+    bin: Vec<u8>,
+    dependencies: &'a HashMap<String, String>,
+    /// Texture index by resolved path, each file gets one texture and image
+    texture_ids: HashMap<String, usize>,
+    /// Dependencies used by materials, by resolved path
+    textures: BTreeMap<String, String>,
+    unresolved: BTreeSet<String>,
+}
+
+impl<'a> Exporter<'a> {
+    pub(crate) fn new(dependencies: &'a HashMap<String, String>) -> Self {
+        Self {
+            gltf: Gltf {
+                asset: Asset {
+                    version: "2.0",
+                    generator: "scrap_parse",
+                },
+                scene: 0,
+                scenes: vec![Scene::default()],
+                nodes: vec![],
+                meshes: vec![],
+                materials: vec![],
+                textures: vec![],
+                images: vec![],
+                accessors: vec![],
+                buffer_views: vec![],
+                buffers: vec![],
+            },
+            bin: vec![],
+            dependencies,
+            texture_ids: HashMap::new(),
+            textures: BTreeMap::new(),
+            unresolved: BTreeSet::new(),
+        }
+    }
+
+    fn add_node(&mut self, node: Node) -> usize {
+        self.gltf.nodes.push(node);
+        self.gltf.nodes.len() - 1
+    }
+
+    fn add_root(&mut self, name: &str) -> usize {
+        let root = self.add_node(Node {
+            name: name.to_owned(),
+            ..Default::default()
+        });
+        self.gltf.scenes[0].nodes.push(root);
+        root
+    }
+
+    fn add_view(&mut self, data: &[u8], target: u32) -> usize {
+        self.bin.resize(self.bin.len().next_multiple_of(4), 0);
+        self.gltf.buffer_views.push(BufferView {
+            buffer: 0,
+            byte_offset: self.bin.len(),
+            byte_length: data.len(),
+            target,
+        });
+        self.bin.extend(data);
+        self.gltf.buffer_views.len() - 1
+    }
+
+    fn add_accessor(&mut self, accessor: Accessor) -> usize {
+        self.gltf.accessors.push(accessor);
+        self.gltf.accessors.len() - 1
+    }
+
+    fn add_floats<const N: usize>(&mut self, values: &[[f32; N]], bounds: bool) -> usize {
+        let kind = match N {
+            2 => "VEC2",
+            3 => "VEC3",
+            4 => "VEC4",
+            _ => unreachable!(),
+        };
+        let data: Vec<u8> = values
+            .iter()
+            .flatten()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let (min, max) = if bounds {
+            let mut min = vec![f32::INFINITY; N];
+            let mut max = vec![f32::NEG_INFINITY; N];
+            for value in values {
+                for (n, &v) in value.iter().enumerate() {
+                    min[n] = min[n].min(v);
+                    max[n] = max[n].max(v);
+                }
+            }
+            (Some(min), Some(max))
+        } else {
+            (None, None)
+        };
+        let buffer_view = self.add_view(&data, ARRAY_BUFFER);
+        self.add_accessor(Accessor {
+            buffer_view,
+            component_type: FLOAT,
+            normalized: false,
+            count: values.len(),
+            kind,
+            min,
+            max,
+        })
+    }
+
+    fn add_texture(&mut self, dep: &str, tex_coord: usize) -> Option<TextureInfo> {
+        let Some(path) = self.dependencies.get(dep) else {
+            self.unresolved.insert(dep.to_owned());
+            return None;
+        };
+        let index = match self.texture_ids.get(path) {
+            Some(&idx) => idx,
+            None => {
+                // same location texture::export_png writes to
+                let png = with_extension(path.trim_start_matches('/'), "png");
+                self.gltf.images.push(Image {
+                    uri: uri_encode(&png),
+                });
+                self.gltf.textures.push(Texture {
+                    source: self.gltf.images.len() - 1,
+                });
+                let idx = self.gltf.textures.len() - 1;
+                self.texture_ids.insert(path.clone(), idx);
+                self.textures.insert(path.clone(), dep.to_owned());
+                idx
+            }
+        };
+        Some(TextureInfo { index, tex_coord })
+    }
+
+    /// Adds a material, `lightmap` is sampled with the second set of texture coordinates
+    fn add_material(&mut self, name: String, mat: &MAT, lightmap: Option<&str>) -> usize {
+        let maps = &mat.maps;
+        let base_color_texture = maps
+            .base
+            .value
+            .as_ref()
+            .and_then(|map| self.add_texture(&map.texture.string, 0));
+        let normal_texture = maps
+            .bump
+            .value
+            .as_ref()
+            .and_then(|map| self.add_texture(&map.texture.string, 0));
+        let emissive_texture = maps
+            .glow
+            .value
+            .as_ref()
+            .and_then(|map| self.add_texture(&map.texture.string, 0));
+        let occlusion_texture = lightmap.and_then(|lightmap| self.add_texture(lightmap, 1));
+        let emissive_factor = emissive_texture.as_ref().map(|_| [1.0; 3]);
+        self.gltf.materials.push(Material {
+            name,
+            pbr_metallic_roughness: PbrMetallicRoughness {
+                base_color_texture,
+                metallic_factor: 0.0,
+                roughness_factor: 1.0,
+            },
+            normal_texture,
+            occlusion_texture,
+            emissive_texture,
+            emissive_factor,
+            double_sided: false,
+        });
+        self.gltf.materials.len() - 1
+    }
+
+    /// Adds a mesh with one primitive per material, `materials` holds the material of each
+    /// triangle
+    fn add_mesh(
+        &mut self,
+        name: &str,
+        tris: &[[u16; 3]],
+        verts: &LFVF,
+        materials: impl Fn(usize) -> Option<usize>,
+    ) -> Option<usize> {
+        let verts = &verts.inner.as_ref()?.data;
+        let mut groups: BTreeMap<Option<usize>, Vec<[u16; 3]>> = BTreeMap::new();
+        for (n, &[a, b, c]) in tris.iter().enumerate() {
+            if [a, b, c].iter().all(|&idx| usize::from(idx) < verts.len()) {
+                // flipping the Z axis flips the winding order too
+                groups.entry(materials(n)).or_default().push([a, c, b]);
+            }
+        }
+        if groups.is_empty() {
+            return None;
+        }
+        let mut attributes = HashMap::new();
+        let pos: Vec<[f32; 3]> = verts.iter().map(|v| to_gltf_pos(v.xyz)).collect();
+        attributes.insert("POSITION", self.add_floats(&pos, true));
+        let normals: Option<Vec<[f32; 3]>> =
+            verts.iter().map(|v| v.normal.map(convert_dir)).collect();
+        if let Some(normals) = normals {
+            attributes.insert("NORMAL", self.add_floats(&normals, false));
+        }
+        let colors: Option<Vec<u8>> = verts
+            .iter()
+            // D3DCOLOR is BGRA in memory
+            .map(|v| v.diffuse.as_ref().map(|c| [c.b, c.g, c.r, c.a]))
+            .collect::<Option<Vec<_>>>()
+            .map(|colors| colors.into_iter().flatten().collect());
+        if let Some(colors) = colors {
+            let buffer_view = self.add_view(&colors, ARRAY_BUFFER);
+            let accessor = self.add_accessor(Accessor {
+                buffer_view,
+                component_type: UNSIGNED_BYTE,
+                normalized: true,
+                count: verts.len(),
+                kind: "VEC4",
+                min: None,
+                max: None,
+            });
+            attributes.insert("COLOR_0", accessor);
+        }
+        for (attr, n) in [("TEXCOORD_0", 0), ("TEXCOORD_1", 1)] {
+            let uvs: Option<Vec<[f32; 2]>> = verts
+                .iter()
+                .map(|v| {
+                    let uv = [&v.tex_1, &v.tex_2][n].as_ref()?;
+                    Some([*uv.0.first()?, *uv.0.get(1)?])
+                })
+                .collect();
+            if let Some(uvs) = uvs {
+                attributes.insert(attr, self.add_floats(&uvs, false));
+            }
+        }
+        let mut primitives = vec![];
+        for (material, tris) in groups {
+            let data: Vec<u8> = tris
+                .iter()
+                .flatten()
+                .flat_map(|v| v.to_le_bytes())
+                .collect();
+            let buffer_view = self.add_view(&data, ELEMENT_ARRAY_BUFFER);
+            let indices = self.add_accessor(Accessor {
+                buffer_view,
+                component_type: UNSIGNED_SHORT,
+                normalized: false,
+                count: tris.len() * 3,
+                kind: "SCALAR",
+                min: None,
+                max: None,
+            });
+            primitives.push(Primitive {
+                attributes: attributes.clone(),
+                indices,
+                material,
+            });
+        }
+        self.gltf.meshes.push(Mesh {
+            name: name.to_owned(),
+            primitives,
+        });
+        Some(self.gltf.meshes.len() - 1)
+    }
+
+    fn add_collision_mesh(&mut self, cmsh: &CMSH) -> Option<usize> {
+        let verts = &cmsh.verts.data;
+        let indices: Vec<u32> = cmsh
+            .faces
+            .data
+            .iter()
+            .map(|face| face.indices())
+            .filter(|tri| tri.iter().all(|&idx| (idx as usize) < verts.len()))
+            .flat_map(|[a, b, c]| [a, c, b])
+            .collect();
+        if indices.is_empty() {
+            return None;
+        }
+        let pos: Vec<[f32; 3]> = verts.iter().copied().map(to_gltf_pos).collect();
+        let mut attributes = HashMap::new();
+        attributes.insert("POSITION", self.add_floats(&pos, true));
+        let data: Vec<u8> = indices.iter().flat_map(|v| v.to_le_bytes()).collect();
+        let buffer_view = self.add_view(&data, ELEMENT_ARRAY_BUFFER);
+        let indices = self.add_accessor(Accessor {
+            buffer_view,
+            component_type: UNSIGNED_INT,
+            normalized: false,
+            count: indices.len(),
+            kind: "SCALAR",
+            min: None,
+            max: None,
+        });
+        self.gltf.meshes.push(Mesh {
+            name: cmsh.name.string.clone(),
+            primitives: vec![Primitive {
+                attributes,
+                indices,
+                material: None,
+            }],
+        });
+        Some(self.gltf.meshes.len() - 1)
+    }
+
+    fn add_md3d(&mut self, md3d: &MD3D, materials: &[usize]) -> Vec<usize> {
+        let mut ret = vec![];
+        let mut current = Some(md3d);
+        while let Some(md3d) = current {
+            let name = &md3d.name.string;
+            let face_materials = md3d.face_materials();
+            let material = |n: usize| match &face_materials {
+                Some(ids) => materials.get(usize::try_from(ids[n]).ok()?).copied(),
+                // without per face ids only a single material is unambiguous
+                None => match materials {
+                    &[material] => Some(material),
+                    _ => None,
+                },
+            };
+            if let Some(mesh) = self.add_mesh(name, &md3d.tris.tris, &md3d.verts, material) {
+                ret.push(self.add_node(Node {
+                    name: name.clone(),
+                    mesh: Some(mesh),
+                    ..Default::default()
+                }));
+            }
+            current = md3d.child.as_deref();
+        }
+        ret
+    }
+
+    /// Adds the node hierarchy of a SM3/CM3 scene below a new root node
+    pub(crate) fn add_scene(&mut self, name: &str, scene: &SCN) {
+        let root = self.add_root(name);
+        let materials: Vec<usize> = scene
+            .mat
+            .iter()
+            .enumerate()
+            .map(|(n, mat)| {
+                let name = mat
+                    .name
+                    .as_ref()
+                    .map(|name| name.string.clone())
+                    .unwrap_or_else(|| format!("{name}:MAT:{n}"));
+                self.add_material(name, mat, None)
+            })
+            .collect();
+        let mut by_name = HashMap::new();
+        let mut indices = vec![];
+        for node in &scene.nodes {
+            let children = match node.content.value.as_ref() {
+                Some(crate::NodeData::D3DMesh(md3d)) => self.add_md3d(md3d, &materials),
+                _ => vec![],
+            };
+            let idx = self.add_node(Node {
+                name: node.name.string.clone(),
+                children,
+                translation: Some(to_gltf_pos(node.pos_offset)),
+                rotation: Some(convert_rot(node.rotation)),
+                scale: Some([node.scale; 3]),
+                ..Default::default()
+            });
+            by_name.insert(node.name.string.as_str(), idx);
+            indices.push(idx);
+        }
+        for (node, &idx) in scene.nodes.iter().zip(&indices) {
+            let parent = by_name
+                .get(node.parent.string.as_str())
+                .copied()
+                .filter(|&parent| parent != idx)
+                .unwrap_or(root);
+            self.gltf.nodes[parent].children.push(idx);
+        }
+    }
+
+    /// Adds the level geometry of an EMI file below a new root node
+    pub(crate) fn add_emi(&mut self, name: &str, emi: &EMI) {
+        let root = self.add_root(name);
+        // the first of the two lightmaps (primary and secondary, see `R_ForceLMap`)
+        let lightmaps: HashMap<u32, &str> = emi
+            .maps
+            .iter()
+            .filter_map(|map| {
+                let (lightmap, _, _) = map.data.as_ref()?;
+                (!lightmap.string.is_empty()).then_some((map.key, lightmap.string.as_str()))
+            })
+            .collect();
+        let mut materials = HashMap::new();
+        for tri in &emi.tri {
+            let data = &tri.data;
+            let key = (data.mat_key, data.map_key);
+            let material = match materials.get(&key) {
+                Some(&material) => Some(material),
+                None => emi
+                    .materials
+                    .iter()
+                    .find(|(key, _)| *key == data.mat_key)
+                    .map(|(_, mat)| {
+                        let name = match &mat.name {
+                            Some(name) => format!("{}|{:08X}", name.string, data.map_key),
+                            None => format!("MAT:{:08X}|{:08X}", data.mat_key, data.map_key),
+                        };
+                        let lightmap = lightmaps.get(&data.map_key).copied();
+                        let material = self.add_material(name, mat, lightmap);
+                        materials.insert(key, material);
+                        material
+                    }),
+            };
+            for (n, verts) in [&data.verts_1, &data.verts_2].into_iter().enumerate() {
+                let name = format!("{}_{}", tri.name.string, n + 1);
+                let Some(mesh) = self.add_mesh(&name, &data.tris, verts, |_| material) else {
+                    continue;
+                };
+                let node = self.add_node(Node {
+                    name,
+                    mesh: Some(mesh),
+                    ..Default::default()
+                });
+                self.gltf.nodes[root].children.push(node);
+            }
+        }
+    }
+
+    /// Adds the collision meshes of an AMC file below a new root node
+    pub(crate) fn add_amc(&mut self, name: &str, amc: &AMC) {
+        let root = self.add_root(name);
+        for cmsh in amc.cmsh.iter().chain(amc.sector_col.iter().flatten()) {
+            let Some(mesh) = self.add_collision_mesh(cmsh) else {
+                continue;
+            };
+            let node = self.add_node(Node {
+                name: cmsh.name.string.clone(),
+                mesh: Some(mesh),
+                ..Default::default()
+            });
+            self.gltf.nodes[root].children.push(node);
+        }
+    }
+
+    pub(crate) fn add_data(&mut self, name: &str, data: &Data) -> Result<()> {
+        match data {
+            Data::SM3(sm3) => self.add_scene(name, &sm3.scene),
+            Data::CM3(cm3) => self.add_scene(name, &cm3.scene),
+            Data::EMI(emi) => self.add_emi(name, emi),
+            Data::AMC(amc) => self.add_amc(name, amc),
+            Data::DUM(_) | Data::MST(_) | Data::PTH(_) | Data::SAV(_) => {
+                bail!("{name} contains no geometry")
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn add_level(&mut self, level: &Level) {
+        self.add_emi("map3d.emi", &level.emi);
+        for (sm3, name) in level.sm3.iter().zip(["map3d.sm3", "map3d_2.sm3"]) {
+            if let Some(sm3) = sm3 {
+                self.add_scene(name, &sm3.scene);
+            }
+        }
+    }
+
+    /// Writes `out_path` (.gltf), the binary buffer next to it (.bin) and converts all
+    /// referenced textures from the filesystem of `root` to PNG files in the output
+    /// directory, returns the textures that weren't found in `dependencies` and the ones
+    /// that failed to decode
+    pub(crate) fn write(
+        mut self,
+        root: &VfsPath,
+        out_path: &Path,
+    ) -> Result<(BTreeSet<String>, texture::DecodeFailures)> {
+        let out_dir = out_path.parent().unwrap_or(Path::new("."));
+        let bin_path = out_path.with_extension("bin");
+        let Some(bin_name) = bin_path.file_name().and_then(|name| name.to_str()) else {
+            bail!("Invalid output path: {}", out_path.display());
+        };
+        self.gltf.buffers.push(Buffer {
+            uri: uri_encode(bin_name),
+            byte_length: self.bin.len(),
+        });
+        fs_err::write(&bin_path, &self.bin)?;
+        let textures: HashMap<String, String> = self
+            .textures
+            .into_iter()
+            .map(|(path, dep)| (dep, path))
+            .collect();
+        let (_, failed) = texture::export_png(&textures, &root.root(), out_dir)?;
+        let mut fh = std::io::BufWriter::new(fs_err::File::create(out_path)?);
+        serde_json::to_writer_pretty(&mut fh, &self.gltf)?;
+        fh.flush()?;
+        Ok((self.unresolved, failed))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        EMI_Textures, LFVFInner, Optional, PascalString, Textures, TriV104, Vertex, FVF, MAP, RGBA,
+        TRI,
+    };
+
+    fn name(s: &str) -> PascalString {
+        PascalString {
+            string: s.to_owned(),
+            padding: vec![0],
+        }
+    }
+
+    fn vertex(xyz: [f32; 3]) -> Vertex {
+        Vertex {
+            xyz,
+            normal: Some([0.0, 0.0, 1.0]),
+            point_size: None,
+            diffuse: Some(RGBA {
+                r: 255,
+                g: 0,
+                b: 0,
+                a: 255,
+            }),
+            specular: None,
+            tex_1: None,
+            tex_2: None,
+            tex_3: None,
+            tex_4: None,
+            tex_5: None,
+            tex_6: None,
+            tex_7: None,
+            tex_8: None,
+        }
+    }
+
+    fn triangle() -> LFVF {
+        LFVF {
+            version: 1,
+            fmt_id: 3,
+            inner: Some(LFVFInner {
+                vert_fmt: FVF::try_from(0x1c2).unwrap(),
+                vert_size: 0x1c,
+                data: vec![
+                    vertex([0.0, 0.0, 0.0]),
+                    vertex([1000.0, 0.0, 0.0]),
+                    vertex([0.0, 2000.0, 1000.0]),
+                ],
+            }),
+        }
+    }
+
+    fn tri(name_: &str, mat_key: u32, map_key: u32, tris: Vec<[u16; 3]>) -> TRI {
+        TRI {
+            flags: 0,
+            name: name(name_),
+            sector_num: 0,
+            data: TriV104 {
+                sector_name: Some(name("sector")),
+                mat_key,
+                map_key,
+                tris,
+                verts_1: triangle(),
+                verts_2: LFVF {
+                    version: 1,
+                    fmt_id: 0,
+                    inner: None,
+                },
+            },
+        }
+    }
+
+    fn map(texture: &str) -> Optional<MAP> {
+        Optional {
+            value: Some(MAP {
+                version: 2,
+                texture: name(texture),
+                unk_1: [0; 7],
+                unk_bbox: [[0.0; 2]; 2],
+                unk_2: 0.0,
+                unk_3: None,
+            }),
+        }
+    }
+
+    #[test]
+    fn emi_export() {
+        let emi = EMI {
+            version: 105,
+            materials: vec![],
+            maps: vec![],
+            // second triangle references a missing vertex and gets dropped
+            tri: vec![tri("floor", 1, 1, vec![[0, 1, 2], [0, 1, 3]])],
+        };
+        let deps = HashMap::new();
+        let mut exporter = Exporter::new(&deps);
+        exporter.add_emi("map3d.emi", &emi);
+        let gltf = &exporter.gltf;
+        assert_eq!(gltf.meshes.len(), 1);
+        assert_eq!(gltf.nodes[0].children, vec![1]);
+        assert_eq!(gltf.nodes[1].name, "floor_1");
+        let prim = &gltf.meshes[0].primitives[0];
+        assert_eq!(prim.material, None);
+        let pos = &gltf.accessors[prim.attributes["POSITION"]];
+        assert_eq!(pos.min, Some(vec![0.0, 0.0, -1.0]));
+        assert_eq!(pos.max, Some(vec![1.0, 2.0, 0.0]));
+        let indices = &gltf.accessors[prim.indices];
+        assert_eq!(indices.count, 3);
+        let view = &gltf.buffer_views[indices.buffer_view];
+        let data = &exporter.bin[view.byte_offset..view.byte_offset + view.byte_length];
+        assert_eq!(data, [0, 0, 2, 0, 1, 0]);
+        let colors = &gltf.accessors[prim.attributes["COLOR_0"]];
+        let view = &gltf.buffer_views[colors.buffer_view];
+        assert_eq!(exporter.bin[view.byte_offset..][..4], [0, 0, 255, 255]);
+        assert!(!prim.attributes.contains_key("TEXCOORD_0"));
+
+        // one primitive per material, sharing the vertex attributes
+        let mesh = exporter
+            .add_mesh(
+                "split",
+                &[[0, 1, 2], [2, 1, 0]],
+                &emi.tri[0].data.verts_1,
+                |n| Some(n + 3),
+            )
+            .unwrap();
+        let prims = &exporter.gltf.meshes[mesh].primitives;
+        assert_eq!(prims.len(), 2);
+        assert_eq!(prims[0].material, Some(3));
+        assert_eq!(prims[1].material, Some(4));
+        assert_eq!(prims[0].attributes, prims[1].attributes);
+    }
+
+    #[test]
+    fn emi_lightmaps() {
+        let black = || RGBA {
+            r: 0,
+            g: 0,
+            b: 0,
+            a: 0,
+        };
+        let mat = MAT {
+            version: 1,
+            name: None,
+            unk_f: std::array::from_fn(|_| black()),
+            unk_data: std::array::from_fn(|_| black()),
+            maps: Textures {
+                base: map("floor.bmp"),
+                metallic: Optional { value: None },
+                reflections: Optional { value: None },
+                bump: Optional { value: None },
+                glow: Optional { value: None },
+            },
+        };
+        let emi = EMI {
+            version: 105,
+            materials: vec![(1, mat)],
+            maps: vec![EMI_Textures {
+                key: 7,
+                data: Some((name("lm_1.bmp"), 0, name("lm_2.bmp"))),
+            }],
+            tri: vec![
+                tri("lit", 1, 7, vec![[0, 1, 2]]),
+                tri("lit_2", 1, 7, vec![[0, 1, 2]]),
+                tri("unlit", 1, 8, vec![[0, 1, 2]]),
+            ],
+        };
+        let deps: HashMap<String, String> = ["floor.bmp", "lm_1.bmp"]
+            .into_iter()
+            .map(|dep| (dep.to_owned(), format!("/levels/test/{dep}")))
+            .collect();
+        let mut exporter = Exporter::new(&deps);
+        exporter.add_emi("map3d.emi", &emi);
+        let gltf = &exporter.gltf;
+        // one material per (material, lightmap) pair, each file is a single texture
+        assert_eq!(gltf.materials.len(), 2);
+        assert_eq!(gltf.textures.len(), 2);
+        assert_eq!(gltf.images.len(), 2);
+        let lit = &gltf.materials[0];
+        let lightmap = lit.occlusion_texture.as_ref().unwrap();
+        assert_eq!((lightmap.index, lightmap.tex_coord), (1, 1));
+        assert_eq!(gltf.images[1].uri, "levels/test/lm_1.png");
+        let unlit = &gltf.materials[1];
+        assert!(unlit.occlusion_texture.is_none());
+        let base = |mat: &Material| {
+            mat.pbr_metallic_roughness
+                .base_color_texture
+                .as_ref()
+                .unwrap()
+                .index
+        };
+        assert_eq!(base(lit), base(unlit));
+        let mesh_materials: Vec<_> = gltf
+            .meshes
+            .iter()
+            .map(|mesh| mesh.primitives[0].material)
+            .collect();
+        assert_eq!(mesh_materials, [Some(0), Some(0), Some(1)]);
+    }
+}
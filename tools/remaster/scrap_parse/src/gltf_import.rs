@@ -0,0 +1,837 @@
+use crate::space::{convert_dir, convert_rot, from_gltf_pos};
+use crate::{
+    vertex_format_from_id, vertex_size_from_id, with_extension, LFVFInner, MD3D_Tris, NodeData,
+    Optional, PascalString, RawTable, TexCoords, Textures, Vertex, LFVF, MAP, MAT, MD3D, RGBA, SCN,
+    SM3,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::Utc;
+use serde::Deserialize;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::Path;
+
+const GLB_MAGIC: &[u8; 4] = b"glTF";
+const GLB_JSON: u32 = 0x4e4f534a;
+const GLB_BIN: u32 = 0x004e4942;
+
+/// LFVF format ids (and their FVF) used for imported meshes, indexed by (has_diffuse, has_lightmap_uv)
+const FORMATS: [[(u32, u32); 2]; 2] = [[(1, 0x112), (2, 0x212)], [(7, 0x152), (5, 0x252)]];
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Document {
+    scene: Option<usize>,
+    #[serde(default)]
+    scenes: Vec<Scene>,
+    #[serde(default)]
+    nodes: Vec<Node>,
+    #[serde(default)]
+    meshes: Vec<Mesh>,
+    #[serde(default)]
+    materials: Vec<Material>,
+    #[serde(default)]
+    textures: Vec<Texture>,
+    #[serde(default)]
+    images: Vec<Image>,
+    #[serde(default)]
+    accessors: Vec<Accessor>,
+    #[serde(default)]
+    buffer_views: Vec<BufferView>,
+    #[serde(default)]
+    buffers: Vec<Buffer>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Scene {
+    #[serde(default)]
+    nodes: Vec<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Node {
+    name: Option<String>,
+    #[serde(default)]
+    children: Vec<usize>,
+    mesh: Option<usize>,
+    translation: Option<[f32; 3]>,
+    rotation: Option<[f32; 4]>,
+    scale: Option<[f32; 3]>,
+    matrix: Option<[f32; 16]>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Mesh {
+    name: Option<String>,
+    primitives: Vec<Primitive>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Primitive {
+    attributes: HashMap<String, usize>,
+    indices: Option<usize>,
+    mode: Option<u32>,
+    material: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TextureRef {
+    index: usize,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PbrMetallicRoughness {
+    base_color_texture: Option<TextureRef>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Material {
+    name: Option<String>,
+    pbr_metallic_roughness: Option<PbrMetallicRoughness>,
+    normal_texture: Option<TextureRef>,
+    emissive_texture: Option<TextureRef>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Texture {
+    source: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Image {
+    uri: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Accessor {
+    buffer_view: Option<usize>,
+    #[serde(default)]
+    byte_offset: usize,
+    component_type: u32,
+    #[serde(default)]
+    normalized: bool,
+    count: usize,
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BufferView {
+    buffer: usize,
+    #[serde(default)]
+    byte_offset: usize,
+    byte_length: usize,
+    byte_stride: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Buffer {
+    uri: Option<String>,
+}
+
+fn percent_decode(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut ret = vec![];
+    let mut n = 0;
+    while n < bytes.len() {
+        let hex = bytes
+            .get(n + 1..n + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[n], hex) {
+            (b'%', Some(b)) => {
+                ret.push(b);
+                n += 3;
+            }
+            (b, _) => {
+                ret.push(b);
+                n += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&ret).into_owned()
+}
+
+fn pascal_string(string: &str) -> PascalString {
+    PascalString {
+        string: string.to_owned(),
+        padding: vec![0],
+    }
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Result<u32> {
+    let bytes = data
+        .get(offset..offset + 4)
+        .ok_or_else(|| anyhow!("Unexpected end of GLB data"))?;
+    Ok(u32::from_le_bytes(bytes.try_into()?))
+}
+
+fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn normalize(v: [f32; 3]) -> [f32; 3] {
+    let len = v.iter().map(|v| v * v).sum::<f32>().sqrt();
+    if len == 0.0 {
+        return [0.0, 1.0, 0.0];
+    }
+    v.map(|v| v / len)
+}
+
+/// Row-vector (D3D style) transform matrix
+fn transform_matrix(pos: [f32; 3], [x, y, z, w]: [f32; 4], scale: f32) -> [[f32; 4]; 4] {
+    [
+        [
+            (1.0 - 2.0 * (y * y + z * z)) * scale,
+            2.0 * (x * y + z * w) * scale,
+            2.0 * (x * z - y * w) * scale,
+            0.0,
+        ],
+        [
+            2.0 * (x * y - z * w) * scale,
+            (1.0 - 2.0 * (x * x + z * z)) * scale,
+            2.0 * (y * z + x * w) * scale,
+            0.0,
+        ],
+        [
+            2.0 * (x * z + y * w) * scale,
+            2.0 * (y * z - x * w) * scale,
+            (1.0 - 2.0 * (x * x + y * y)) * scale,
+            0.0,
+        ],
+        [pos[0], pos[1], pos[2], 1.0],
+    ]
+}
+
+fn mat_mul(a: &[[f32; 4]; 4], b: &[[f32; 4]; 4]) -> [[f32; 4]; 4] {
+    let mut ret = [[0.0; 4]; 4];
+    for (row, a_row) in ret.iter_mut().zip(a) {
+        for (col, value) in row.iter_mut().enumerate() {
+            *value = a_row.iter().zip(b).map(|(a, b_row)| a * b_row[col]).sum();
+        }
+    }
+    ret
+}
+
+/// Builds an [`SM3`] from a glTF 2.0 document (.gltf with external buffers or .glb)
+struct Importer {
+    doc: Document,
+    buffers: Vec<Vec<u8>>,
+    nodes: Vec<crate::Node>,
+    names: HashSet<String>,
+    /// glTF nodes already added, a node may only have one parent
+    visited: HashSet<usize>,
+}
+
+impl Importer {
+    fn new(data: &[u8], base_dir: &Path) -> Result<Self> {
+        let (json, mut bin) = if data.starts_with(GLB_MAGIC) {
+            let version = u32_at(data, 4)?;
+            if version != 2 {
+                bail!("Unsupported GLB version: {version}");
+            }
+            let mut json = None;
+            let mut bin = None;
+            let mut offset = 12;
+            while offset < data.len() {
+                let size = usize::try_from(u32_at(data, offset)?)?;
+                let kind = u32_at(data, offset + 4)?;
+                let chunk = data
+                    .get(offset + 8..offset + 8 + size)
+                    .ok_or_else(|| anyhow!("Truncated GLB chunk"))?;
+                match kind {
+                    GLB_JSON => json = Some(chunk),
+                    GLB_BIN => bin = Some(chunk.to_vec()),
+                    _ => (),
+                }
+                offset += 8 + size;
+            }
+            (json.ok_or_else(|| anyhow!("GLB has no JSON chunk"))?, bin)
+        } else {
+            (data, None)
+        };
+        let doc: Document = serde_json::from_slice(json)?;
+        let mut buffers = vec![];
+        for buffer in &doc.buffers {
+            let data = match buffer.uri.as_deref() {
+                None => bin
+                    .take()
+                    .ok_or_else(|| anyhow!("Missing GLB binary chunk"))?,
+                Some(uri) if uri.starts_with("data:") => {
+                    bail!("Embedded data URIs are not supported, export with a separate .bin")
+                }
+                Some(uri) => fs_err::read(base_dir.join(percent_decode(uri)))?,
+            };
+            buffers.push(data);
+        }
+        Ok(Self {
+            doc,
+            buffers,
+            nodes: vec![],
+            names: HashSet::new(),
+            visited: HashSet::new(),
+        })
+    }
+
+    /// Reads an accessor as a flat list of floats, returns the number of components per element
+    fn read(&self, idx: usize) -> Result<(usize, Vec<f32>)> {
+        let accessor = self
+            .doc
+            .accessors
+            .get(idx)
+            .ok_or_else(|| anyhow!("Invalid accessor index: {idx}"))?;
+        let components = match accessor.kind.as_str() {
+            "SCALAR" => 1,
+            "VEC2" => 2,
+            "VEC3" => 3,
+            "VEC4" => 4,
+            other => bail!("Unsupported accessor type: {other}"),
+        };
+        let (size, max): (usize, f32) = match accessor.component_type {
+            5120 => (1, i8::MAX.into()),
+            5121 => (1, u8::MAX.into()),
+            5122 => (2, i16::MAX.into()),
+            5123 => (2, u16::MAX.into()),
+            5125 => (4, 1.0),
+            5126 => (4, 1.0),
+            other => bail!("Unsupported accessor component type: {other}"),
+        };
+        let Some(view) = accessor.buffer_view else {
+            return Ok((components, vec![0.0; accessor.count * components]));
+        };
+        let view = self
+            .doc
+            .buffer_views
+            .get(view)
+            .ok_or_else(|| anyhow!("Invalid buffer view index: {view}"))?;
+        let buffer = self
+            .buffers
+            .get(view.buffer)
+            .ok_or_else(|| anyhow!("Invalid buffer index: {}", view.buffer))?;
+        let data = buffer
+            .get(view.byte_offset..view.byte_offset + view.byte_length)
+            .ok_or_else(|| anyhow!("Buffer view out of range"))?;
+        let stride = view.byte_stride.unwrap_or(size * components);
+        let mut ret = Vec::with_capacity(accessor.count * components);
+        for n in 0..accessor.count {
+            for c in 0..components {
+                let offset = accessor.byte_offset + n * stride + c * size;
+                let bytes = data
+                    .get(offset..offset + size)
+                    .ok_or_else(|| anyhow!("Accessor {idx} out of range"))?;
+                let value = match accessor.component_type {
+                    5120 => f32::from(bytes[0] as i8),
+                    5121 => f32::from(bytes[0]),
+                    5122 => f32::from(i16::from_le_bytes([bytes[0], bytes[1]])),
+                    5123 => f32::from(u16::from_le_bytes([bytes[0], bytes[1]])),
+                    5125 => u32::from_le_bytes(bytes.try_into()?) as f32,
+                    _ => f32::from_le_bytes(bytes.try_into()?),
+                };
+                ret.push(if accessor.normalized {
+                    (value / max).max(-1.0)
+                } else {
+                    value
+                });
+            }
+        }
+        Ok((components, ret))
+    }
+
+    fn read_vec<const N: usize>(&self, idx: usize) -> Result<Vec<[f32; N]>> {
+        let (components, data) = self.read(idx)?;
+        if components != N {
+            bail!("Accessor {idx} has {components} components, expected {N}");
+        }
+        Ok(data
+            .chunks_exact(N)
+            .filter_map(|v| v.try_into().ok())
+            .collect())
+    }
+
+    fn texture_path(&self, tex: Option<&TextureRef>) -> Option<String> {
+        let image = self.doc.textures.get(tex?.index)?.source?;
+        let uri = self.doc.images.get(image)?.uri.as_deref()?;
+        if uri.starts_with("data:") {
+            return None;
+        }
+        Some(with_extension(&percent_decode(uri), ""))
+    }
+
+    fn map(&self, tex: Option<&TextureRef>) -> Optional<MAP> {
+        Optional {
+            value: self.texture_path(tex).map(|texture| MAP {
+                version: 2,
+                texture: pascal_string(&texture),
+                unk_1: [0; 7],
+                unk_bbox: [[0.0; 2]; 2],
+                unk_2: 0.0,
+                unk_3: None,
+            }),
+        }
+    }
+
+    fn material(&self, material: &Material, idx: usize) -> MAT {
+        let name = material
+            .name
+            .clone()
+            .unwrap_or_else(|| format!("material_{idx}"));
+        let white = RGBA {
+            r: 0xff,
+            g: 0xff,
+            b: 0xff,
+            a: 0xff,
+        };
+        let base = material
+            .pbr_metallic_roughness
+            .as_ref()
+            .and_then(|pbr| pbr.base_color_texture.as_ref());
+        MAT {
+            version: 3,
+            name: Some(pascal_string(&name)),
+            unk_f: std::array::from_fn(|_| white.clone()),
+            unk_data: std::array::from_fn(|_| RGBA {
+                r: 0,
+                g: 0,
+                b: 0,
+                a: 0,
+            }),
+            maps: Textures {
+                base: self.map(base),
+                metallic: Optional { value: None },
+                reflections: Optional { value: None },
+                bump: self.map(material.normal_texture.as_ref()),
+                glow: self.map(material.emissive_texture.as_ref()),
+            },
+        }
+    }
+
+    fn md3d(&self, name: &str, prim: &Primitive) -> Result<MD3D> {
+        if !matches!(prim.mode, None | Some(4)) {
+            bail!("{name}: only triangle lists are supported");
+        }
+        let Some(&pos) = prim.attributes.get("POSITION") else {
+            bail!("{name}: primitive has no POSITION attribute");
+        };
+        let pos: Vec<[f32; 3]> = self.read_vec(pos)?.into_iter().map(from_gltf_pos).collect();
+        if pos.len() > usize::from(u16::MAX) + 1 {
+            bail!("{name}: too many vertices ({})", pos.len());
+        }
+        let indices: Vec<u16> = match prim.indices {
+            Some(idx) => self
+                .read(idx)?
+                .1
+                .into_iter()
+                .map(|v| {
+                    u16::try_from(v as u32)
+                        .map_err(|_| anyhow!("{name}: vertex index {v} doesn't fit in 16 bits"))
+                })
+                .collect::<Result<_>>()?,
+            None => (0..pos.len()).map(|v| v as u16).collect(),
+        };
+        let tris: Vec<[u16; 3]> = indices
+            .chunks_exact(3)
+            // flipping the Z axis flips the winding order too
+            .map(|tri| [tri[0], tri[2], tri[1]])
+            .collect();
+        if let Some(tri) = tris
+            .iter()
+            .find(|tri| tri.iter().any(|&idx| usize::from(idx) >= pos.len()))
+        {
+            bail!("{name}: triangle {tri:?} references a missing vertex");
+        }
+        let normals: Vec<[f32; 3]> = match prim.attributes.get("NORMAL") {
+            Some(&idx) => self.read_vec(idx)?.into_iter().map(convert_dir).collect(),
+            None => {
+                let mut normals = vec![[0.0; 3]; pos.len()];
+                for tri in &tris {
+                    let [a, b, c] = tri.map(|idx| pos[usize::from(idx)]);
+                    let normal = cross(sub(b, a), sub(c, a));
+                    for &idx in tri {
+                        for (acc, v) in normals[usize::from(idx)].iter_mut().zip(normal) {
+                            *acc += v;
+                        }
+                    }
+                }
+                normals.into_iter().map(normalize).collect()
+            }
+        };
+        let uv = |attr: &str| -> Result<Option<Vec<[f32; 2]>>> {
+            prim.attributes
+                .get(attr)
+                .map(|&idx| self.read_vec(idx))
+                .transpose()
+        };
+        let uv_0 = uv("TEXCOORD_0")?.unwrap_or_else(|| vec![[0.0; 2]; pos.len()]);
+        let uv_1 = uv("TEXCOORD_1")?;
+        let colors = match prim.attributes.get("COLOR_0") {
+            Some(&idx) => {
+                let (components, data) = self.read(idx)?;
+                let colors: Vec<RGBA> = data
+                    .chunks_exact(components)
+                    .map(|c| {
+                        let c: Vec<u8> = c.iter().map(|v| (v * 255.0).round() as u8).collect();
+                        // D3DCOLOR is BGRA in memory
+                        RGBA {
+                            r: c.get(2).copied().unwrap_or(0),
+                            g: c.get(1).copied().unwrap_or(0),
+                            b: c[0],
+                            a: c.get(3).copied().unwrap_or(0xff),
+                        }
+                    })
+                    .collect();
+                Some(colors)
+            }
+            None => None,
+        };
+        // `build` appends the default material for primitives without one
+        let material = prim.material.unwrap_or(self.doc.materials.len() - 1);
+        if material >= self.doc.materials.len() {
+            bail!("Material index out of range: {material}");
+        }
+        let material = u32::try_from(material)?;
+        let (fmt_id, fvf) = FORMATS[usize::from(colors.is_some())][usize::from(uv_1.is_some())];
+        let vert_fmt = vertex_format_from_id(fmt_id, fvf)?;
+        let data = (0..pos.len())
+            .map(|n| Vertex {
+                xyz: pos[n],
+                normal: normals.get(n).copied(),
+                point_size: None,
+                diffuse: colors.as_ref().and_then(|c| c.get(n).cloned()),
+                specular: None,
+                tex_1: uv_0.get(n).map(|uv| TexCoords(uv.to_vec())),
+                tex_2: uv_1
+                    .as_ref()
+                    .and_then(|uv| uv.get(n))
+                    .map(|uv| TexCoords(uv.to_vec())),
+                tex_3: None,
+                tex_4: None,
+                tex_5: None,
+                tex_6: None,
+                tex_7: None,
+                tex_8: None,
+            })
+            .collect();
+        Ok(MD3D {
+            version: 1,
+            name: pascal_string(name),
+            unk_table_1: RawTable {
+                data: (0..pos.len())
+                    .map(|n| (n as u16).to_le_bytes().to_vec())
+                    .collect(),
+            },
+            unk_table_5: RawTable {
+                data: vec![material.to_le_bytes().to_vec(); tris.len()],
+            },
+            tris: MD3D_Tris { tris },
+            verts: LFVF {
+                version: 1,
+                fmt_id,
+                inner: Some(LFVFInner {
+                    vert_fmt,
+                    vert_size: vertex_size_from_id(fmt_id)?,
+                    data,
+                }),
+            },
+            unk_int_1: 0,
+            unk_table_2: RawTable { data: vec![] },
+            unk_table_3: RawTable { data: vec![] },
+            unk_table_4: RawTable { data: vec![] },
+            unk_int_2: 1,
+            unk_table_6: None,
+            unk_int_4: 0,
+            unk_int_5: 0,
+            unk_int_6: 0,
+            unk_bytes_1: vec![0; 0x18],
+            unk_bytes_2: vec![0; 0x18],
+            unk_bytes_3: vec![0; 0xc],
+            has_child: 0,
+            child: None,
+        })
+    }
+
+    fn unique_name(&mut self, name: String) -> String {
+        let mut ret = name.clone();
+        let mut n = 1;
+        while !self.names.insert(ret.clone()) {
+            ret = format!("{name}.{n:03}");
+            n += 1;
+        }
+        ret
+    }
+
+    fn push_node(
+        &mut self,
+        name: String,
+        parent: &str,
+        transform: ([f32; 3], [f32; 4], f32),
+        world: [[f32; 4]; 4],
+        content: Option<NodeData>,
+    ) {
+        let (pos, rot, scale) = transform;
+        self.nodes.push(crate::Node {
+            node_index: self.nodes.len() as i32,
+            unk_idx_1: -1,
+            unk_idx_2: -1,
+            flags: BTreeSet::new(),
+            unk_flags: 0,
+            unk_f20_0x50: 0,
+            name: pascal_string(&name),
+            parent: pascal_string(parent),
+            pos_offset: pos,
+            rotation: rot,
+            scale,
+            mat_1: transform_matrix(pos, rot, scale),
+            mat_2: world,
+            unk_rot: [0.0, 0.0, 0.0, 1.0],
+            axis_scale: [1.0; 3],
+            info: Optional { value: None },
+            content: Optional { value: content },
+        });
+    }
+
+    fn add_node(&mut self, idx: usize, parent: &str, parent_world: [[f32; 4]; 4]) -> Result<()> {
+        let node = self
+            .doc
+            .nodes
+            .get(idx)
+            .ok_or_else(|| anyhow!("Invalid node index: {idx}"))?;
+        if !self.visited.insert(idx) {
+            bail!("Node {idx} has more than one parent or is part of a cycle");
+        }
+        let name = node.name.clone().unwrap_or_else(|| format!("node_{idx}"));
+        if node.matrix.is_some() {
+            bail!("{name}: matrix transforms are not supported, use translation/rotation/scale");
+        }
+        let pos = from_gltf_pos(node.translation.unwrap_or_default());
+        let rot = convert_rot(node.rotation.unwrap_or([0.0, 0.0, 0.0, 1.0]));
+        // nodes only have a uniform scale
+        let [sx, ..] = node.scale.unwrap_or([1.0; 3]);
+        let world = mat_mul(&transform_matrix(pos, rot, sx), &parent_world);
+        let mut meshes = vec![];
+        if let Some(mesh_idx) = node.mesh {
+            let mesh = self
+                .doc
+                .meshes
+                .get(mesh_idx)
+                .ok_or_else(|| anyhow!("Invalid mesh index: {mesh_idx}"))?;
+            let mesh_name = mesh.name.clone().unwrap_or_else(|| name.clone());
+            for (n, prim) in mesh.primitives.iter().enumerate() {
+                let name = match n {
+                    0 => mesh_name.clone(),
+                    n => format!("{mesh_name}_{n}"),
+                };
+                meshes.push(self.md3d(&name, prim)?);
+            }
+        }
+        let children = node.children.clone();
+        let name = self.unique_name(name);
+        let mut meshes = meshes.into_iter();
+        let content = meshes.next().map(|md3d| NodeData::D3DMesh(Box::new(md3d)));
+        self.push_node(name.clone(), parent, (pos, rot, sx), world, content);
+        // Nodes hold a single mesh, additional primitives become child nodes
+        for md3d in meshes {
+            let prim_name = self.unique_name(md3d.name.string.clone());
+            let identity = ([0.0; 3], [0.0, 0.0, 0.0, 1.0], 1.0);
+            let content = Some(NodeData::D3DMesh(Box::new(md3d)));
+            self.push_node(prim_name, &name, identity, world, content);
+        }
+        for child in children {
+            self.add_node(child, &name, world)?;
+        }
+        Ok(())
+    }
+
+    fn build(mut self, model_name: &str) -> Result<SM3> {
+        let default_material = self
+            .doc
+            .meshes
+            .iter()
+            .flat_map(|mesh| &mesh.primitives)
+            .any(|prim| prim.material.is_none());
+        if default_material {
+            self.doc.materials.push(Material::default());
+        }
+        let mat = self
+            .doc
+            .materials
+            .iter()
+            .enumerate()
+            .map(|(n, material)| self.material(material, n))
+            .collect();
+        let roots = match self.doc.scenes.get(self.doc.scene.unwrap_or(0)) {
+            Some(scene) => scene.nodes.clone(),
+            None => {
+                let children: HashSet<usize> = self
+                    .doc
+                    .nodes
+                    .iter()
+                    .flat_map(|node| node.children.iter().copied())
+                    .collect();
+                (0..self.doc.nodes.len())
+                    .filter(|n| !children.contains(n))
+                    .collect()
+            }
+        };
+        let identity = transform_matrix([0.0; 3], [0.0, 0.0, 0.0, 1.0], 1.0);
+        for root in roots {
+            self.add_node(root, "", identity)?;
+        }
+        let now = Utc::now();
+        Ok(SM3 {
+            time_1: now,
+            time_2: now,
+            scene: SCN {
+                model_name: pascal_string(model_name),
+                node_name: pascal_string(model_name),
+                node_props: Optional { value: None },
+                // defaults documented on SCN
+                unk_f_1: [f32::from_bits(0xb0b0b), 1.0, 0.0, 1.0],
+                unk_1: [0.0; 6],
+                unk_f_2: 0.0,
+                user_props: Optional { value: None },
+                mat,
+                nodes: self.nodes,
+                ani: Optional { value: None },
+            },
+        })
+    }
+}
+
+/// Converts a glTF 2.0 file into an [`SM3`] model
+///
+/// Materials are stored in glTF order and referenced from the MD3D attribute table,
+/// followed by an untextured one if any primitive has no material. Texture paths are
+/// the image URIs without extension. Non-uniform node scales are
+/// replaced by their X component.
+pub(crate) fn import(path: &Path) -> Result<SM3> {
+    let data = fs_err::read(path)?;
+    let base_dir = path.parent().unwrap_or(Path::new("."));
+    let model_name = path
+        .file_stem()
+        .and_then(|name| name.to_str())
+        .unwrap_or("model");
+    Importer::new(&data, base_dir)
+        .and_then(|importer| importer.build(model_name))
+        .with_context(|| format!("Failed to import {}", path.display()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Data;
+    use binrw::io::Cursor;
+    use binrw::{BinReaderExt, BinWriterExt};
+
+    fn glb(material: bool) -> Vec<u8> {
+        let mut bin = vec![];
+        for v in [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0] {
+            bin.extend(v.to_le_bytes());
+        }
+        for idx in [0u16, 1, 2, 0] {
+            bin.extend(idx.to_le_bytes());
+        }
+        let mut json = serde_json::json!({
+            "asset": {"version": "2.0"},
+            "scene": 0,
+            "scenes": [{"nodes": [0]}],
+            "nodes": [
+                {"name": "root", "children": [1], "translation": [0.0, 0.0, 1.0]},
+                {"name": "tri", "mesh": 0}
+            ],
+            "meshes": [{"primitives": [{"attributes": {"POSITION": 0}, "indices": 1, "material": 0}]}],
+            "materials": [{"name": "hull", "pbrMetallicRoughness": {"baseColorTexture": {"index": 0}}}],
+            "textures": [{"source": 0}],
+            "images": [{"uri": "textures/hull%20a.png"}],
+            "accessors": [
+                {"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3"},
+                {"bufferView": 0, "byteOffset": 36, "componentType": 5123, "count": 3, "type": "SCALAR"}
+            ],
+            "bufferViews": [{"buffer": 0, "byteLength": bin.len()}],
+            "buffers": [{"byteLength": bin.len()}]
+        });
+        if !material {
+            json.as_object_mut().unwrap().remove("materials");
+            json["meshes"][0]["primitives"][0]
+                .as_object_mut()
+                .unwrap()
+                .remove("material");
+        }
+        let mut json = serde_json::to_vec(&json).unwrap();
+        json.resize(json.len().next_multiple_of(4), b' ');
+        let mut data = GLB_MAGIC.to_vec();
+        data.extend(2u32.to_le_bytes());
+        data.extend(((12 + 8 + json.len() + 8 + bin.len()) as u32).to_le_bytes());
+        data.extend((json.len() as u32).to_le_bytes());
+        data.extend(GLB_JSON.to_le_bytes());
+        data.extend(json);
+        data.extend((bin.len() as u32).to_le_bytes());
+        data.extend(GLB_BIN.to_le_bytes());
+        data.extend(bin);
+        data
+    }
+
+    #[test]
+    fn glb_import() {
+        let sm3 = Importer::new(&glb(true), Path::new("."))
+            .and_then(|importer| importer.build("test"))
+            .unwrap();
+        let mut out = Cursor::new(vec![]);
+        out.write_le(&Data::SM3(sm3)).unwrap();
+        out.set_position(0);
+        let Data::SM3(sm3) = out.read_le().unwrap() else {
+            panic!("expected SM3");
+        };
+        let scene = &sm3.scene;
+        let texture = &scene.mat[0].maps.base.value.as_ref().unwrap().texture;
+        assert_eq!(texture.string, "textures/hull a");
+        assert_eq!(scene.nodes.len(), 2);
+        assert_eq!(scene.nodes[0].pos_offset, [0.0, 0.0, -1000.0]);
+        assert_eq!(scene.nodes[1].parent.string, "root");
+        let Some(NodeData::D3DMesh(md3d)) = scene.nodes[1].content.value.as_ref() else {
+            panic!("expected mesh");
+        };
+        assert_eq!(md3d.tris.tris, vec![[0, 2, 1]]);
+        assert_eq!(md3d.verts.fmt_id, 1);
+        let verts = &md3d.verts.inner.as_ref().unwrap().data;
+        assert_eq!(verts[2].xyz, [0.0, 1000.0, 0.0]);
+        assert_eq!(verts[0].normal, Some([0.0, 0.0, -1.0]));
+        assert_eq!(md3d.face_materials(), Some(vec![0]));
+    }
+
+    #[test]
+    fn default_material() {
+        let sm3 = Importer::new(&glb(false), Path::new("."))
+            .and_then(|importer| importer.build("test"))
+            .unwrap();
+        assert_eq!(sm3.scene.mat.len(), 1);
+        assert!(sm3.scene.mat[0].maps.base.value.is_none());
+        let Some(NodeData::D3DMesh(md3d)) = sm3.scene.nodes[1].content.value.as_ref() else {
+            panic!("expected mesh");
+        };
+        assert_eq!(md3d.face_materials(), Some(vec![0]));
+    }
+
+    #[test]
+    fn node_cycle() {
+        let json = serde_json::json!({
+            "asset": {"version": "2.0"},
+            "scenes": [{"nodes": [0]}],
+            "nodes": [{"children": [1]}, {"children": [0]}]
+        });
+        let err = Importer::new(&serde_json::to_vec(&json).unwrap(), Path::new("."))
+            .and_then(|importer| importer.build("test"))
+            .unwrap_err();
+        assert!(err.to_string().contains("cycle"));
+    }
+}
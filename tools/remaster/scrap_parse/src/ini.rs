@@ -0,0 +1,314 @@
+//! Order preserving INI files for `INI` chunks and the `.ini` files of levels
+//!
+//! Every line is kept as written, so duplicate keys, casing, comments and whitespace
+//! survive a read/write round trip. Sections and keys are matched case insensitively and
+//! with surrounding whitespace ignored, entries before the first header are in section `""`.
+use crate::encode_latin1;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum IniLine {
+    /// `[section]`, optionally surrounded by whitespace and followed by a comment
+    Section {
+        section: String,
+        /// Line as written if it isn't exactly `[section]`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        text: Option<String>,
+    },
+    /// `key=value` split at the first `=`, `value` is `None` for lines without one
+    Entry {
+        key: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        value: Option<String>,
+    },
+    /// Comments, blank lines and anything else
+    Other { text: String },
+}
+
+fn is_comment(text: &str) -> bool {
+    text.starts_with([';', '#']) || text.starts_with("//")
+}
+
+impl IniLine {
+    pub(crate) fn parse(line: &str) -> Self {
+        let header = line
+            .trim()
+            .strip_prefix('[')
+            .and_then(|rest| rest.split_once(']'))
+            .filter(|(_, rest)| {
+                let rest = rest.trim_start();
+                rest.is_empty() || is_comment(rest)
+            });
+        if let Some((section, _)) = header {
+            let exact = line.len() == section.len() + 2;
+            return IniLine::Section {
+                section: section.to_owned(),
+                text: (!exact).then(|| line.to_owned()),
+            };
+        }
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() || trimmed.starts_with('[') || is_comment(trimmed) {
+            return IniLine::Other {
+                text: line.to_owned(),
+            };
+        }
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key, Some(value.to_owned())),
+            None => (line, None),
+        };
+        IniLine::Entry {
+            key: key.to_owned(),
+            value,
+        }
+    }
+}
+
+impl fmt::Display for IniLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IniLine::Section {
+                text: Some(text), ..
+            } => write!(f, "{text}"),
+            IniLine::Section {
+                section,
+                text: None,
+            } => write!(f, "[{section}]"),
+            IniLine::Entry { key, value: None } => write!(f, "{key}"),
+            IniLine::Entry {
+                key,
+                value: Some(value),
+            } => write!(f, "{key}={value}"),
+            IniLine::Other { text } => write!(f, "{text}"),
+        }
+    }
+}
+
+fn same_name(name: &str, other: &str) -> bool {
+    name.trim().eq_ignore_ascii_case(other.trim())
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct IniFile {
+    pub(crate) lines: Vec<IniLine>,
+    /// Lines end with `\r\n` instead of `\n`
+    #[serde(default)]
+    pub(crate) crlf: bool,
+}
+
+impl IniFile {
+    pub(crate) fn parse(text: &str) -> Self {
+        let crlf =
+            text.contains("\r\n") && text.matches('\n').count() == text.matches("\r\n").count();
+        let newline = if crlf { "\r\n" } else { "\n" };
+        IniFile {
+            lines: text.split(newline).map(IniLine::parse).collect(),
+            crlf,
+        }
+    }
+
+    /// .ini files are Latin-1 like the rest of the game data
+    pub(crate) fn from_bytes(data: &[u8]) -> Self {
+        Self::parse(&data.iter().map(|&c| char::from(c)).collect::<String>())
+    }
+
+    pub(crate) fn to_bytes(&self) -> Result<Vec<u8>> {
+        Ok(encode_latin1(&self.to_string(), &[])?)
+    }
+
+    /// Lines with the section each of them is in
+    fn with_sections(&self) -> impl Iterator<Item = (&str, &IniLine)> {
+        let mut current = "";
+        self.lines.iter().map(move |line| {
+            if let IniLine::Section { section, .. } = line {
+                current = section.trim();
+            }
+            (current, line)
+        })
+    }
+
+    /// `(section, key, value)` of all entries in file order, trimmed
+    pub(crate) fn entries(&self) -> impl Iterator<Item = (&str, &str, Option<&str>)> {
+        self.with_sections()
+            .filter_map(|(section, line)| match line {
+                IniLine::Entry { key, value } => {
+                    Some((section, key.trim(), value.as_deref().map(str::trim)))
+                }
+                _ => None,
+            })
+    }
+
+    /// Section names in order of their first header, with the casing of that header
+    pub(crate) fn sections(&self) -> Vec<&str> {
+        let mut ret: Vec<&str> = vec![];
+        for line in &self.lines {
+            if let IniLine::Section { section, .. } = line {
+                if !ret.iter().any(|other| same_name(other, section)) {
+                    ret.push(section.trim());
+                }
+            }
+        }
+        ret
+    }
+
+    /// Entries of all headers named `section`
+    pub(crate) fn section<'a>(
+        &'a self,
+        section: &'a str,
+    ) -> impl Iterator<Item = (&'a str, Option<&'a str>)> + 'a {
+        self.entries()
+            .filter(move |(name, _, _)| same_name(name, section))
+            .map(|(_, key, value)| (key, value))
+    }
+
+    /// Value of the first entry named `key` in `section`
+    pub(crate) fn get(&self, section: &str, key: &str) -> Option<&str> {
+        self.get_all(section, key).into_iter().next()
+    }
+
+    /// Values of all entries named `key` in `section`, for keys that can be repeated
+    pub(crate) fn get_all(&self, section: &str, key: &str) -> Vec<&str> {
+        self.entries()
+            .filter(|(name, entry, _)| same_name(name, section) && same_name(entry, key))
+            .filter_map(|(_, _, value)| value)
+            .collect()
+    }
+
+    /// Replaces the value of the first entry named `key` in `section`, otherwise adds the
+    /// entry after the last one of the section, adding the section if it doesn't exist
+    pub(crate) fn set(&mut self, section: &str, key: &str, value: &str) {
+        let mut last = None;
+        let mut current = "";
+        for (n, line) in self.lines.iter_mut().enumerate() {
+            match line {
+                IniLine::Section { section: name, .. } => current = name,
+                IniLine::Entry {
+                    key: name,
+                    value: old,
+                } if same_name(current, section) => {
+                    if same_name(name, key) {
+                        *old = Some(value.to_owned());
+                        return;
+                    }
+                    last = Some(n);
+                }
+                _ => {}
+            }
+            if same_name(current, section) && last.is_none() {
+                last = Some(n);
+            }
+        }
+        let entry = IniLine::Entry {
+            key: key.to_owned(),
+            value: Some(value.to_owned()),
+        };
+        match last {
+            Some(n) => self.lines.insert(n + 1, entry),
+            None if section.trim().is_empty() => self.lines.insert(0, entry),
+            None => {
+                // keep a trailing newline at the end
+                let end = match self.lines.last() {
+                    Some(IniLine::Other { text }) if text.is_empty() => self.lines.len() - 1,
+                    _ => self.lines.len(),
+                };
+                self.lines.splice(
+                    end..end,
+                    [
+                        IniLine::Section {
+                            section: section.to_owned(),
+                            text: None,
+                        },
+                        entry,
+                    ],
+                );
+            }
+        }
+    }
+
+    /// Removes all entries named `key` in `section`, returns if there were any
+    pub(crate) fn remove(&mut self, section: &str, key: &str) -> bool {
+        let len = self.lines.len();
+        let mut current = String::new();
+        self.lines.retain(|line| match line {
+            IniLine::Section { section: name, .. } => {
+                current.clone_from(name);
+                true
+            }
+            IniLine::Entry { key: name, .. } => {
+                !(same_name(&current, section) && same_name(name, key))
+            }
+            IniLine::Other { .. } => true,
+        });
+        self.lines.len() != len
+    }
+}
+
+impl fmt::Display for IniFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let newline = if self.crlf { "\r\n" } else { "\n" };
+        for (n, line) in self.lines.iter().enumerate() {
+            if n != 0 {
+                f.write_str(newline)?;
+            }
+            write!(f, "{line}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn faithful() {
+        let text = "; level config\r\n[Model]\r\nTexturePath = Models/Textures\r\nLod=1\r\nlod=2\r\n\r\n[model]\r\nFlag\r\n";
+        let mut ini = IniFile::parse(text);
+        assert!(ini.crlf);
+        assert_eq!(ini.to_string(), text);
+        assert_eq!(ini.sections(), ["Model"]);
+        assert_eq!(ini.get("MODEL", "texturepath"), Some("Models/Textures"));
+        assert_eq!(ini.get_all("model", "LOD"), ["1", "2"]);
+        assert_eq!(ini.section("model").last(), Some(("Flag", None)));
+
+        ini.set("Model", "Lod", "3");
+        ini.set("Model", "Scale", "2");
+        ini.set("Extra", "Key", "Value");
+        assert!(ini.remove("model", "flag"));
+        assert_eq!(
+            ini.to_string(),
+            "; level config\r\n[Model]\r\nTexturePath = Models/Textures\r\nLod=3\r\nlod=2\r\n\r\n[model]\r\nScale=2\r\n[Extra]\r\nKey=Value\r\n"
+        );
+
+        let json = serde_json::to_string(&ini.lines[..3]).unwrap();
+        assert_eq!(
+            json,
+            r#"[{"text":"; level config"},{"section":"Model"},{"key":"TexturePath ","value":" Models/Textures"}]"#
+        );
+        let lines: Vec<IniLine> = serde_json::from_str(&json).unwrap();
+        assert_eq!(lines, ini.lines[..3]);
+    }
+
+    #[test]
+    fn loose_headers() {
+        // mixed line endings, so `\r` stays part of the lines
+        let text = "[Model] \nLod=1\n  [Sound] ; effects\r\nVolume=2\r\n[Light]\r\nOn=1\n[Not a header] x\n";
+        let ini = IniFile::parse(text);
+        assert!(!ini.crlf);
+        assert_eq!(ini.to_string(), text);
+        assert_eq!(ini.sections(), ["Model", "Sound", "Light"]);
+        assert_eq!(ini.get("model", "lod"), Some("1"));
+        assert_eq!(ini.get("sound", "volume"), Some("2"));
+        assert_eq!(ini.get("light", "on"), Some("1"));
+        assert_eq!(
+            ini.lines[2],
+            IniLine::Section {
+                section: "Sound".to_owned(),
+                text: Some("  [Sound] ; effects\r".to_owned())
+            }
+        );
+        assert!(matches!(ini.lines[6], IniLine::Other { .. }));
+    }
+}
@@ -2,70 +2,146 @@
 use anyhow::{anyhow, bail, Result};
 use bilge::prelude::*;
 use binrw::args;
+use binrw::error::ContextExt;
 use binrw::helpers::until_exclusive;
+use binrw::io::Cursor;
 use binrw::prelude::*;
 use chrono::{DateTime, NaiveDateTime, Utc};
-use configparser::ini::Ini;
 use enum_iterator::Sequence;
-use indexmap::IndexMap;
+use error::ParseError;
+use ini::{IniFile, IniLine};
 use num_derive::ToPrimitive;
 use num_traits::ToPrimitive;
 use rhexdump::rhexdumps;
-use serde::Serialize;
-use std::collections::{BTreeSet, HashMap};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fmt::Debug;
-use std::io::{BufReader, Read, Seek};
+use std::io::{BufWriter, Read, Seek, SeekFrom};
 use std::path::Path;
 use std::path::PathBuf;
 use vfs::VfsPath;
 use walkdir::WalkDir;
 
+mod ai_path;
+mod chunks;
+mod collision;
+mod coverage;
+mod deps;
+mod error;
 mod find_scrap;
-mod packed_vfs;
+mod gltf;
+mod gltf_import;
+mod ini;
 mod pixel_shader;
-
-type IniData = IndexMap<String, IndexMap<String, Option<String>>>;
-
-#[binread]
-#[derive(Serialize, Debug, Clone)]
-struct PackedEntry {
-    path: PascalString,
-    size: u32,
-    offset: u32,
+mod save;
+mod space;
+mod sprites;
+#[cfg(test)]
+mod test_util;
+mod texture;
+
+/// Reads `args.count` entries like `Vec<T>` does, errors get the entry index as context so
+/// [`ParseError`] can show it in the chunk path
+fn indexed<'a, R: Read + Seek, T: BinRead>(
+    reader: &mut R,
+    endian: binrw::Endian,
+    args: binrw::VecArgs<T::Args<'a>>,
+) -> BinResult<Vec<T>>
+where
+    T::Args<'a>: Clone,
+{
+    (0..args.count)
+        .map(|index| {
+            let offset = reader.stream_position()?;
+            T::read_options(reader, endian, args.inner.clone())
+                .with_context(error::EntryIndex { index, offset })
+        })
+        .collect()
 }
 
-#[binread]
-#[br(magic = b"BFPK")]
-#[derive(Serialize, Debug)]
-struct PackedHeader {
-    #[br(temp,assert(version==0))]
-    version: u32,
-    #[br(temp)]
-    num_files: u32,
-    #[br(count=num_files)]
-    pub files: Vec<PackedEntry>,
+/// [`indexed`] for fixed size arrays
+fn indexed_array<R: Read + Seek, T: for<'a> BinRead<Args<'a> = ()>, const N: usize>(
+    reader: &mut R,
+    endian: binrw::Endian,
+    _: (),
+) -> BinResult<[T; N]> {
+    let entries: Vec<T> = indexed(
+        reader,
+        endian,
+        binrw::VecArgs {
+            count: N,
+            inner: (),
+        },
+    )?;
+    Ok(entries
+        .try_into()
+        .unwrap_or_else(|_| unreachable!("read {N} entries")))
+}
+
+/// Records where a chunk's `size` field starts, the written value is a placeholder patched by [`ChunkEnd`]
+fn chunk_start<W: Seek>(writer: &mut W) -> BinResult<u32> {
+    let pos = writer.stream_position()?;
+    u32::try_from(pos).map_err(|e| binrw::Error::Custom {
+        pos,
+        err: Box::new(e),
+    })
+}
+
+/// Patches the `size` field written at the given offset with the number of bytes written since
+#[derive(Debug, Default, Clone, Copy)]
+struct ChunkEnd(u32);
+
+impl BinWrite for ChunkEnd {
+    type Args<'a> = ();
+
+    fn write_options<W: std::io::Write + Seek>(
+        &self,
+        writer: &mut W,
+        endian: binrw::Endian,
+        _: Self::Args<'_>,
+    ) -> BinResult<()> {
+        let end = writer.stream_position()?;
+        let start = u64::from(self.0);
+        let size = end
+            .checked_sub(start + 4)
+            .and_then(|size| u32::try_from(size).ok())
+            .ok_or_else(|| binrw::Error::AssertFail {
+                pos: start,
+                message: format!("Invalid chunk bounds: {start}..{end}"),
+            })?;
+        writer.seek(SeekFrom::Start(start))?;
+        size.write_options(writer, endian, ())?;
+        writer.seek(SeekFrom::Start(end))?;
+        Ok(())
+    }
 }
 
-#[binread]
-#[derive(Serialize, Debug)]
+#[binrw]
+#[derive(Serialize, Deserialize, Debug)]
 #[br(import(msg: &'static str))]
 struct Unparsed<const SIZE: u64> {
     #[br(count=SIZE, try_map=|data: Vec<u8>| Err(anyhow!("Unparsed data: {}\n{}", msg, rhexdumps!(data))))]
+    #[bw(ignore)]
     data: (),
 }
-#[binread]
-#[derive(Serialize, Debug)]
+#[binrw]
+#[derive(Serialize, Deserialize, Debug)]
 struct RawTable<const SIZE: u32> {
+    #[br(temp)]
+    #[bw(try_calc = u32::try_from(data.len()))]
     num_entries: u32,
-    #[br(assert(entry_size==SIZE))]
+    #[br(temp, assert(entry_size==SIZE))]
+    #[bw(calc = SIZE)]
     entry_size: u32,
     #[br(count=num_entries, args {inner: args!{count: entry_size.try_into().unwrap()}})]
     data: Vec<Vec<u8>>,
 }
 
-#[binread]
-#[derive(Serialize, Debug)]
-struct Table<T: for<'a> BinRead<Args<'a> = ()> + 'static> {
+#[binrw]
+#[derive(Serialize, Deserialize, Debug)]
+struct Table<T: for<'a> BinRead<Args<'a> = ()> + for<'a> BinWrite<Args<'a> = ()> + 'static> {
+    #[br(temp)]
+    #[bw(try_calc = u32::try_from(data.len()))]
     num_entries: u32,
     entry_size: u32,
     #[br(count=num_entries)]
@@ -80,15 +156,17 @@ struct Table<T: for<'a> BinRead<Args<'a> = ()> + 'static> {
 //     }
 // }
 
-#[binread]
-struct Optional<T: for<'a> BinRead<Args<'a> = ()>> {
+#[binrw]
+struct Optional<T: for<'a> BinRead<Args<'a> = ()> + for<'a> BinWrite<Args<'a> = ()>> {
     #[br(temp)]
+    #[bw(calc = value.is_some().into())]
     has_value: u32,
     #[br(if(has_value!=0))]
     value: Option<T>,
 }
 
-impl<T: for<'a> BinRead<Args<'a> = ()> + Debug> Debug for Optional<T>
+impl<T: for<'a> BinRead<Args<'a> = ()> + for<'a> BinWrite<Args<'a> = ()> + Debug> Debug
+    for Optional<T>
 where
     T: Debug,
 {
@@ -97,7 +175,9 @@ where
     }
 }
 
-impl<T: for<'a> BinRead<Args<'a> = ()> + std::ops::Deref> std::ops::Deref for Optional<T> {
+impl<T: for<'a> BinRead<Args<'a> = ()> + for<'a> BinWrite<Args<'a> = ()> + std::ops::Deref>
+    std::ops::Deref for Optional<T>
+{
     type Target = Option<T>;
 
     fn deref(&self) -> &Self::Target {
@@ -105,7 +185,9 @@ impl<T: for<'a> BinRead<Args<'a> = ()> + std::ops::Deref> std::ops::Deref for Op
     }
 }
 
-impl<T: for<'a> BinRead<Args<'a> = ()> + Serialize> Serialize for Optional<T> {
+impl<T: for<'a> BinRead<Args<'a> = ()> + for<'a> BinWrite<Args<'a> = ()> + Serialize> Serialize
+    for Optional<T>
+{
     fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
     where
         S: serde::Serializer,
@@ -114,25 +196,56 @@ impl<T: for<'a> BinRead<Args<'a> = ()> + Serialize> Serialize for Optional<T> {
     }
 }
 
-#[binread]
-#[derive(Serialize, Debug)]
-struct Chunk {
-    #[br(map=|c:[u8;4]| c.into_iter().map(|v| v as char).collect())]
-    magic: Vec<char>,
-    size: u32,
-    #[br(temp,count=size)]
-    _data: Vec<u8>,
+impl<'de, T> Deserialize<'de> for Optional<T>
+where
+    T: for<'a> BinRead<Args<'a> = ()> + for<'a> BinWrite<Args<'a> = ()> + Deserialize<'de>,
+{
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        Option::deserialize(deserializer).map(|value| Optional { value })
+    }
 }
 
-#[binread]
+#[binrw]
 #[derive(Clone)]
 struct PascalString {
     #[br(temp)]
+    #[bw(try_calc = u32::try_from(string.chars().count() + padding.len()))]
     length: u32,
-    #[br(count=length, map=|bytes: Vec<u8>| {
-        String::from_utf8_lossy(&bytes.iter().copied().take_while(|&v| v!=0).collect::<Vec<u8>>()).into_owned()
-    })]
+    #[br(temp, count=length)]
+    #[bw(try_calc = encode_latin1(string, padding))]
+    bytes: Vec<u8>,
+    #[br(calc = bytes.iter().take_while(|&&v| v != 0).map(|&v| char::from(v)).collect())]
+    #[bw(ignore)]
     string: String,
+    /// Terminator (and any garbage after it) kept so unchanged strings are written back verbatim
+    #[br(calc = bytes.iter().copied().skip_while(|&v| v != 0).collect())]
+    #[bw(ignore)]
+    padding: Vec<u8>,
+}
+
+fn encode_latin1(string: &str, padding: &[u8]) -> BinResult<Vec<u8>> {
+    string
+        .chars()
+        .map(|c| {
+            u8::try_from(c).map_err(|_| binrw::Error::AssertFail {
+                pos: 0,
+                message: format!("Character {c:?} in {string:?} can't be encoded as Latin-1"),
+            })
+        })
+        .chain(padding.iter().copied().map(Ok))
+        .collect()
+}
+
+/// JSON form of [`PascalString`], strings with anything but a single NUL terminator keep
+/// their padding
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum PascalStringJson<S> {
+    Plain(S),
+    Padded { string: S, padding: Vec<u8> },
 }
 
 impl Serialize for PascalString {
@@ -140,7 +253,29 @@ impl Serialize for PascalString {
     where
         S: serde::Serializer,
     {
-        self.string.serialize(serializer)
+        match self.padding.as_slice() {
+            [0] => PascalStringJson::Plain(&self.string),
+            padding => PascalStringJson::Padded {
+                string: &self.string,
+                padding: padding.to_vec(),
+            },
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for PascalString {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        use serde::de::Error;
+        let (string, padding) = match PascalStringJson::<String>::deserialize(deserializer)? {
+            PascalStringJson::Plain(string) => (string, vec![0]),
+            PascalStringJson::Padded { string, padding } => (string, padding),
+        };
+        encode_latin1(&string, &padding).map_err(Error::custom)?;
+        Ok(PascalString { string, padding })
     }
 }
 
@@ -150,48 +285,94 @@ impl Debug for PascalString {
     }
 }
 
-#[binread]
-#[derive(Debug, Serialize)]
+#[binrw]
+#[derive(Debug)]
 struct IniSection {
     #[br(temp)]
+    #[bw(try_calc = u32::try_from(sections.len()))]
     num_lines: u32,
     #[br(count=num_lines)]
     sections: Vec<PascalString>,
 }
 
-#[binread]
-#[br(magic = b"INI\0")]
-#[derive(Debug)]
-struct INI {
-    #[br(temp)]
-    _size: u32,
-    #[br(temp)]
-    num_sections: u32,
-    #[br(count=num_sections)]
-    sections: Vec<IniSection>,
+/// JSON form of a line in an [`INI`] chunk, the padding is only kept if it isn't a
+/// single NUL terminator
+#[derive(Serialize, Deserialize)]
+struct IniChunkLine {
+    #[serde(flatten)]
+    line: IniLine,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    padding: Option<Vec<u8>>,
 }
 
-impl Serialize for INI {
+impl Serialize for IniSection {
     fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
     where
         S: serde::Serializer,
     {
-        use serde::ser::Error;
-        let blocks: Vec<String> = self
-            .sections
-            .iter()
-            .flat_map(|s| s.sections.iter())
-            .map(|s| s.string.clone())
-            .collect();
-        Ini::new()
-            .read(blocks.join("\n"))
-            .map_err(Error::custom)?
-            .serialize(serializer)
+        serializer.collect_seq(self.sections.iter().map(|line| IniChunkLine {
+            line: IniLine::parse(&line.string),
+            padding: (line.padding != [0]).then(|| line.padding.clone()),
+        }))
     }
 }
 
-#[binread]
-#[derive(Debug, Serialize, Clone)]
+impl<'de> Deserialize<'de> for IniSection {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        use serde::de::Error;
+        let lines = Vec::<IniChunkLine>::deserialize(deserializer)?;
+        let sections = lines
+            .into_iter()
+            .map(|IniChunkLine { line, padding }| {
+                let string = line.to_string();
+                let padding = padding.unwrap_or_else(|| vec![0]);
+                encode_latin1(&string, &padding).map_err(Error::custom)?;
+                Ok(PascalString { string, padding })
+            })
+            .collect::<std::result::Result<_, D::Error>>()?;
+        Ok(IniSection { sections })
+    }
+}
+
+#[binrw]
+#[brw(magic = b"INI\0")]
+#[bw(stream = s)]
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(transparent)]
+struct INI {
+    #[br(temp)]
+    #[bw(try_calc = chunk_start(s))]
+    size: u32,
+    #[br(temp)]
+    #[bw(try_calc = u32::try_from(sections.len()))]
+    num_sections: u32,
+    #[br(count=num_sections)]
+    sections: Vec<IniSection>,
+    #[br(temp, calc = ChunkEnd::default())]
+    #[bw(calc = ChunkEnd(size))]
+    _end: ChunkEnd,
+}
+
+impl INI {
+    /// All lines of all sections as one file
+    fn ini(&self) -> IniFile {
+        IniFile {
+            lines: self
+                .sections
+                .iter()
+                .flat_map(|section| section.sections.iter())
+                .map(|line| IniLine::parse(&line.string))
+                .collect(),
+            crlf: false,
+        }
+    }
+}
+
+#[binrw]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 struct RGBA {
     r: u8,
     g: u8,
@@ -199,13 +380,13 @@ struct RGBA {
     a: u8,
 }
 
-#[binread]
-#[derive(Debug, Serialize, Clone)]
+#[binrw]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 #[br(import(n_dims: usize))]
 struct TexCoords(#[br(count=n_dims)] Vec<f32>);
 
-#[binread]
-#[derive(Debug, Serialize, Clone)]
+#[binrw]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 #[br(import(vert_fmt: FVF))]
 // https://github.com/elishacloud/dxwrapper/blob/23ffb74c4c93c4c760bb5f1de347a0b039897210/ddraw/IDirect3DDeviceX.cpp#L2642
 struct Vertex {
@@ -328,23 +509,44 @@ fn vertex_format_from_id(fmt_id: u32, fmt: u32) -> Result<FVF> {
     FVF::try_from(fvf).map_err(|fvf| anyhow!("Invalid vertex format: {fvf:?}"))
 }
 
-#[binread]
+/// Reads the `{"value": ..}` form the derived `Serialize` gives [`FVF`]
+fn deserialize_fvf<'de, D>(deserializer: D) -> std::result::Result<FVF, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    struct Bits {
+        value: u32,
+    }
+    let Bits { value } = Bits::deserialize(deserializer)?;
+    FVF::try_from(value)
+        .map_err(|_| serde::de::Error::custom(format!("Invalid vertex format: {value:#x}")))
+}
+
+#[binrw]
 #[br(import(fmt_id: u32))]
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 struct LFVFInner {
     #[br(try_map=|v:  u32| vertex_format_from_id(fmt_id,v))]
+    #[bw(map=|v: &FVF| u32::from(*v))]
+    #[serde(deserialize_with = "deserialize_fvf")]
     vert_fmt: FVF,
     #[br(assert(vert_size==vertex_size_from_id(fmt_id).unwrap()))]
     vert_size: u32,
+    #[br(temp)]
+    #[bw(try_calc = u32::try_from(data.len()))]
     num_verts: u32,
     #[br(count=num_verts, args {inner: (vert_fmt,)})]
     data: Vec<Vertex>,
 }
 
-#[binread]
-#[br(magic = b"LFVF")]
-#[derive(Debug, Serialize)]
+#[binrw]
+#[brw(magic = b"LFVF")]
+#[bw(stream = s)]
+#[derive(Debug, Serialize, Deserialize)]
 struct LFVF {
+    #[br(temp)]
+    #[bw(try_calc = chunk_start(s))]
     size: u32,
     #[br(assert(version==1,"invalid LFVF version"))]
     version: u32,
@@ -352,22 +554,31 @@ struct LFVF {
     fmt_id: u32,
     #[br(if(fmt_id!=0),args(fmt_id))]
     inner: Option<LFVFInner>,
+    #[br(temp, calc = ChunkEnd::default())]
+    #[bw(calc = ChunkEnd(size))]
+    _end: ChunkEnd,
 }
 
-#[binread]
-#[derive(Debug, Serialize)]
+#[binrw]
+#[derive(Debug, Serialize, Deserialize)]
 struct MD3D_Tris {
+    #[br(temp)]
+    #[bw(try_calc = u32::try_from(tris.len()))]
     num_tris: u32,
-    #[br(assert(tri_size==6,"Invalid MD3D tri size"))]
+    #[br(temp, assert(tri_size==6,"Invalid MD3D tri size"))]
+    #[bw(calc = 6)]
     tri_size: u32,
     #[br(count=num_tris)]
     tris: Vec<[u16; 3]>,
 }
 
-#[binread]
-#[br(magic = b"MD3D")]
-#[derive(Debug, Serialize)]
+#[binrw]
+#[brw(magic = b"MD3D")]
+#[bw(stream = s)]
+#[derive(Debug, Serialize, Deserialize)]
 struct MD3D {
+    #[br(temp)]
+    #[bw(try_calc = chunk_start(s))]
     size: u32,
     #[br(assert(version==1,"Invalid MD3D version"))]
     version: u32,
@@ -379,6 +590,7 @@ struct MD3D {
     unk_table_2: RawTable<0x10>,
     unk_table_3: RawTable<8>,
     unk_table_4: RawTable<0xc>,
+    /// One `u32` per triangle, the D3DX attribute id (index into `SCN.mat`)
     unk_table_5: RawTable<4>, // Tri Flags
     unk_int_2: u32,
     #[br(if(unk_int_2==0))]
@@ -395,38 +607,58 @@ struct MD3D {
     has_child: u32,
     #[br(if(has_child!=0))]
     child: Option<Box<MD3D>>,
+    #[br(temp, calc = ChunkEnd::default())]
+    #[bw(calc = ChunkEnd(size))]
+    _end: ChunkEnd,
+}
+
+impl MD3D {
+    /// Material index of each triangle, `None` if the attribute table doesn't cover all of them
+    fn face_materials(&self) -> Option<Vec<u32>> {
+        let table = &self.unk_table_5.data;
+        if table.len() != self.tris.tris.len() {
+            return None;
+        }
+        table
+            .iter()
+            .map(|entry| Some(u32::from_le_bytes(entry.as_slice().try_into().ok()?)))
+            .collect()
+    }
 }
 
-#[binread]
-#[derive(Debug, Serialize)]
+#[binrw]
+#[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "type")]
 enum NodeData {
-    #[br(magic = 0x0u32)]
+    #[brw(magic = 0x0u32)]
     Dummy,
-    #[br(magic = 0xa1_00_00_01_u32)]
+    #[brw(magic = 0xa1_00_00_01_u32)]
     TriangleMesh(Unparsed<0x100>), // TODO: Empty or unused?
-    #[br(magic = 0xa1_00_00_02_u32)]
+    #[brw(magic = 0xa1_00_00_02_u32)]
     D3DMesh(Box<MD3D>),
-    #[br(magic = 0xa2_00_00_04_u32)]
+    #[brw(magic = 0xa2_00_00_04_u32)]
     Camera(CAM),
-    #[br(magic = 0xa3_00_00_08_u32)]
+    #[brw(magic = 0xa3_00_00_08_u32)]
     Light(LUZ),
-    #[br(magic = 0xa4_00_00_10_u32)]
+    #[brw(magic = 0xa4_00_00_10_u32)]
     Ground(SUEL),
-    #[br(magic = 0xa5_00_00_20_u32)]
+    #[brw(magic = 0xa5_00_00_20_u32)]
     SistPart,
-    #[br(magic = 0xa6_00_00_40_u32)]
+    #[brw(magic = 0xa6_00_00_40_u32)]
     Graphic3D(SPR3),
-    #[br(magic = 0xa6_00_00_80_u32)]
+    #[brw(magic = 0xa6_00_00_80_u32)]
     Flare,
-    #[br(magic = 0xa7_00_01_00u32)]
+    #[brw(magic = 0xa7_00_01_00u32)]
     Portal(PORT),
 }
 
-#[binread]
-#[br(magic = b"SPR3")]
-#[derive(Debug, Serialize)]
+#[binrw]
+#[brw(magic = b"SPR3")]
+#[bw(stream = s)]
+#[derive(Debug, Serialize, Deserialize)]
 struct SPR3 {
+    #[br(temp)]
+    #[bw(try_calc = chunk_start(s))]
     size: u32,
     #[br(assert(version==1,"Invalid SPR3 version"))]
     version: u32,
@@ -435,12 +667,18 @@ struct SPR3 {
     name_1: PascalString,
     name_2: PascalString,
     unk_2: u32,
+    #[br(temp, calc = ChunkEnd::default())]
+    #[bw(calc = ChunkEnd(size))]
+    _end: ChunkEnd,
 }
 
-#[binread]
-#[br(magic = b"SUEL")]
-#[derive(Debug, Serialize)]
+#[binrw]
+#[brw(magic = b"SUEL")]
+#[bw(stream = s)]
+#[derive(Debug, Serialize, Deserialize)]
 struct SUEL {
+    #[br(temp)]
+    #[bw(try_calc = chunk_start(s))]
     size: u32,
     #[br(assert(version==1,"Invalid SUEL version"))]
     version: u32,
@@ -450,12 +688,18 @@ struct SUEL {
     num_nodes: u32,
     unk_4: [u8; 4],
     bbox_2: [[f32; 3]; 2],
+    #[br(temp, calc = ChunkEnd::default())]
+    #[bw(calc = ChunkEnd(size))]
+    _end: ChunkEnd,
 }
 
-#[binread]
-#[br(magic = b"CAM\0")]
-#[derive(Debug, Serialize)]
+#[binrw]
+#[brw(magic = b"CAM\0")]
+#[bw(stream = s)]
+#[derive(Debug, Serialize, Deserialize)]
 struct CAM {
+    #[br(temp)]
+    #[bw(try_calc = chunk_start(s))]
     size: u32,
     #[br(assert(version==1,"Invalid CAM version"))]
     version: u32,
@@ -470,12 +714,18 @@ struct CAM {
     unk_9: [u8; 4],
     unk_10: [u8; 4],
     unk_11: [u8; 4],
+    #[br(temp, calc = ChunkEnd::default())]
+    #[bw(calc = ChunkEnd(size))]
+    _end: ChunkEnd,
 }
 
-#[binread]
-#[br(magic = b"LUZ\0")]
-#[derive(Debug, Serialize)]
+#[binrw]
+#[brw(magic = b"LUZ\0")]
+#[bw(stream = s)]
+#[derive(Debug, Serialize, Deserialize)]
 struct LUZ {
+    #[br(temp)]
+    #[bw(try_calc = chunk_start(s))]
     size: u32,
     #[br(assert(version==1,"Invalid LUZ version"))]
     version: u32,
@@ -492,21 +742,30 @@ struct LUZ {
     unk_11: [u8; 4],
     unk_12: [u8; 4],
     unk_13: u32,
+    #[br(temp, calc = ChunkEnd::default())]
+    #[bw(calc = ChunkEnd(size))]
+    _end: ChunkEnd,
 }
 
-#[binread]
-#[br(magic = b"PORT")]
-#[derive(Debug, Serialize)]
+#[binrw]
+#[brw(magic = b"PORT")]
+#[bw(stream = s)]
+#[derive(Debug, Serialize, Deserialize)]
 struct PORT {
+    #[br(temp)]
+    #[bw(try_calc = chunk_start(s))]
     size: u32,
     #[br(assert(version==1,"Invalid PORT version"))]
     version: u32,
     width: u32,
     height: u32,
     sides: [u32; 2],
+    #[br(temp, calc = ChunkEnd::default())]
+    #[bw(calc = ChunkEnd(size))]
+    _end: ChunkEnd,
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Sequence, Serialize, ToPrimitive)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Sequence, Serialize, Deserialize, ToPrimitive)]
 #[repr(u8)]
 enum NodeFlags {
     ROOT,
@@ -541,14 +800,28 @@ fn parse_node_flags(flags: u32) -> BTreeSet<NodeFlags> {
         .collect()
 }
 
-#[binread]
-#[derive(Debug, Serialize)]
+fn node_flags_to_bits(flags: &BTreeSet<NodeFlags>) -> u32 {
+    flags
+        .iter()
+        .filter_map(|flag| flag.to_u8())
+        .fold(0, |bits, flag| bits | (1 << flag))
+}
+
+#[binrw]
+#[derive(Debug, Serialize, Deserialize)]
 struct Node {
     node_index: i32,
     unk_idx_1: i32,
     unk_idx_2: i32,
-    #[br(map=parse_node_flags)]
+    #[br(temp)]
+    #[bw(calc = node_flags_to_bits(flags) | unk_flags)]
+    raw_flags: u32,
+    #[br(calc = parse_node_flags(raw_flags))]
+    #[bw(ignore)]
     flags: BTreeSet<NodeFlags>,
+    #[br(calc = raw_flags & !node_flags_to_bits(&flags))]
+    #[bw(ignore)]
+    unk_flags: u32,
     unk_f20_0x50: i32,
     name: PascalString,
     parent: PascalString,
@@ -563,10 +836,13 @@ struct Node {
     content: Optional<NodeData>,
 }
 
-#[binread]
-#[br(magic = b"MAP\0")]
-#[derive(Debug, Serialize)]
+#[binrw]
+#[brw(magic = b"MAP\0")]
+#[bw(stream = s)]
+#[derive(Debug, Serialize, Deserialize)]
 struct MAP {
+    #[br(temp)]
+    #[bw(try_calc = chunk_start(s))]
     size: u32,
     #[br(assert((2..=3).contains(&version),"invalid MAP version"))]
     version: u32,
@@ -576,10 +852,13 @@ struct MAP {
     unk_2: f32,
     #[br(if(version==3))]
     unk_3: Option<[u8; 0xc]>,
+    #[br(temp, calc = ChunkEnd::default())]
+    #[bw(calc = ChunkEnd(size))]
+    _end: ChunkEnd,
 }
 
-#[binread]
-#[derive(Debug, Serialize)]
+#[binrw]
+#[derive(Debug, Serialize, Deserialize)]
 struct Textures {
     base: Optional<MAP>,
     metallic: Optional<MAP>,
@@ -588,10 +867,13 @@ struct Textures {
     glow: Optional<MAP>,
 }
 
-#[binread]
-#[br(magic = b"MAT\0")]
-#[derive(Debug, Serialize)]
+#[binrw]
+#[brw(magic = b"MAT\0")]
+#[bw(stream = s)]
+#[derive(Debug, Serialize, Deserialize)]
 struct MAT {
+    #[br(temp)]
+    #[bw(try_calc = chunk_start(s))]
     size: u32,
     #[br(assert((1..=3).contains(&version),"invalid MAT version"))]
     version: u32,
@@ -600,15 +882,22 @@ struct MAT {
     unk_f: [RGBA; 7],
     unk_data: [RGBA; 0x18 / 4],
     maps: Textures,
+    #[br(temp, calc = ChunkEnd::default())]
+    #[bw(calc = ChunkEnd(size))]
+    _end: ChunkEnd,
 }
 
-#[binread]
-#[br(magic = b"SCN\0")]
-#[derive(Debug, Serialize)]
+#[binrw]
+#[brw(magic = b"SCN\0")]
+#[bw(stream = s)]
+#[derive(Debug, Serialize, Deserialize)]
 struct SCN {
     // 0x650220
+    #[br(temp)]
+    #[bw(try_calc = chunk_start(s))]
     size: u32,
     #[br(temp,assert(version==1))]
+    #[bw(calc = 1)]
     version: u32,
     model_name: PascalString,
     node_name: PascalString,
@@ -626,16 +915,22 @@ struct SCN {
     unk_f_2: f32,
     user_props: Optional<INI>,
     #[br(temp)]
+    #[bw(try_calc = u32::try_from(mat.len()))]
     num_materials: u32,
-    #[br(count=num_materials)]
+    #[br(parse_with = indexed, count = num_materials)]
     mat: Vec<MAT>,
     #[br(temp,assert(unk_3==1))]
+    #[bw(calc = 1)]
     unk_3: u32,
     #[br(temp)]
+    #[bw(try_calc = u32::try_from(nodes.len()))]
     num_nodes: u32,
-    #[br(count = num_nodes)] // 32
+    #[br(parse_with = indexed, count = num_nodes)] // 32
     nodes: Vec<Node>,
     ani: Optional<ANI>, // TODO: ?
+    #[br(temp, calc = ChunkEnd::default())]
+    #[bw(calc = ChunkEnd(size))]
+    _end: ChunkEnd,
 }
 
 fn convert_timestamp(dt: u32) -> Result<DateTime<Utc>> {
@@ -645,31 +940,44 @@ fn convert_timestamp(dt: u32) -> Result<DateTime<Utc>> {
     Ok(DateTime::from_naive_utc_and_offset(dt, Utc))
 }
 
-#[binread]
-#[derive(Debug, Serialize)]
+#[binrw]
+#[derive(Debug, Serialize, Deserialize)]
 struct VertexAnim {
+    #[br(temp)]
+    #[bw(try_calc = u32::try_from(tris.len()))]
     n_tr: u32,
     maybe_duration: f32,
     #[br(count=n_tr)]
     tris: Vec<[u8; 3]>,
 }
 
-#[binread]
-#[br(magic = b"EVA\0")]
-#[derive(Debug, Serialize)]
+#[binrw]
+#[brw(magic = b"EVA\0")]
+#[bw(stream = s)]
+#[derive(Debug, Serialize, Deserialize)]
 struct EVA {
+    #[br(temp)]
+    #[bw(try_calc = chunk_start(s))]
     size: u32,
     #[br(assert(version==1,"Invalid EVA version"))]
     version: u32,
+    #[br(temp)]
+    #[bw(try_calc = u32::try_from(verts.len()))]
     num_verts: u32,
     #[br(count=num_verts)]
     verts: Vec<Optional<VertexAnim>>,
+    #[br(temp, calc = ChunkEnd::default())]
+    #[bw(calc = ChunkEnd(size))]
+    _end: ChunkEnd,
 }
 
-#[binread]
-#[br(magic = b"NAM\0")]
-#[derive(Debug, Serialize)]
+#[binrw]
+#[brw(magic = b"NAM\0")]
+#[bw(stream = s)]
+#[derive(Debug, Serialize, Deserialize)]
 struct NAM {
+    #[br(temp)]
+    #[bw(try_calc = chunk_start(s))]
     size: u32,
     #[br(assert(version==1))]
     version: u32,
@@ -682,6 +990,7 @@ struct NAM {
     #[br(assert(stm_flags&0xfff8==0,"Invalid NAM stm_flags"))]
     stm_flags: u32,
     #[br(map=|_:()| flags&(opt_flags|0x8000)&stm_flags)]
+    #[bw(ignore)]
     combined_flags: u32,
     #[br(if(combined_flags&0x1!=0))]
     unk_flags_1: Option<u32>,
@@ -697,49 +1006,77 @@ struct NAM {
     unk_flags_6: Option<u32>,
     #[br(if(flags&0x1000!=0))]
     eva: Option<EVA>,
+    #[br(temp, calc = ChunkEnd::default())]
+    #[bw(calc = ChunkEnd(size))]
+    _end: ChunkEnd,
 }
 
-#[binread]
-#[br(magic = b"NABK")]
-#[derive(Debug, Serialize)]
+#[binrw]
+#[brw(magic = b"NABK")]
+#[bw(stream = s)]
+#[derive(Debug, Serialize, Deserialize)]
 struct NABK {
+    #[br(temp)]
+    #[bw(try_calc = chunk_start(s))]
     size: u32,
-    #[br(temp,count=size)]
-    _data: Vec<u8>,
+    #[br(count=size)]
+    data: Vec<u8>,
+    #[br(temp, calc = ChunkEnd::default())]
+    #[bw(calc = ChunkEnd(size))]
+    _end: ChunkEnd,
 }
 
-#[binread]
-#[br(magic = b"ANI\0")]
-#[derive(Debug, Serialize)]
+#[binrw]
+#[brw(magic = b"ANI\0")]
+#[bw(stream = s)]
+#[derive(Debug, Serialize, Deserialize)]
 struct ANI {
+    #[br(temp)]
+    #[bw(try_calc = chunk_start(s))]
     size: u32,
     #[br(assert(version==2, "Invalid ANI version"))]
     version: u32,
     fps: f32,
     unk_1: u32,
     unk_2: u32,
+    #[br(temp)]
+    #[bw(try_calc = u32::try_from(nam.len()))]
     num_objects: u32,
     unk_flags: u32,
+    #[br(temp)]
+    #[bw(try_calc = u32::try_from(data.len()))]
     num: u32,
-    #[br(temp,count=num)]
-    _data: Vec<u8>,
+    #[br(count=num)]
+    data: Vec<u8>,
     nabk: NABK,
-    #[br(count=num_objects)]
+    #[br(parse_with = indexed, count = num_objects)]
     nam: Vec<NAM>,
+    #[br(temp, calc = ChunkEnd::default())]
+    #[bw(calc = ChunkEnd(size))]
+    _end: ChunkEnd,
 }
 
-#[binread]
-#[br(magic = b"SM3\0")]
-#[derive(Debug, Serialize)]
+#[binrw]
+#[brw(magic = b"SM3\0")]
+#[bw(stream = s)]
+#[derive(Debug, Serialize, Deserialize)]
 struct SM3 {
+    #[br(temp)]
+    #[bw(try_calc = chunk_start(s))]
     size: u32,
     #[br(temp,assert(const_1==0x6515f8,"Invalid timestamp"))]
+    #[bw(calc = 0x6515f8)]
     const_1: u32,
     #[br(try_map=convert_timestamp)]
+    #[bw(try_map=|dt: &DateTime<Utc>| u32::try_from(dt.timestamp()))]
     time_1: DateTime<Utc>,
     #[br(try_map=convert_timestamp)]
+    #[bw(try_map=|dt: &DateTime<Utc>| u32::try_from(dt.timestamp()))]
     time_2: DateTime<Utc>,
     scene: SCN,
+    #[br(temp, calc = ChunkEnd::default())]
+    #[bw(calc = ChunkEnd(size))]
+    _end: ChunkEnd,
 }
 
 impl SM3 {
@@ -763,18 +1100,27 @@ impl SM3 {
     }
 }
 
-#[binread]
-#[br(magic = b"CM3\0")]
-#[derive(Debug, Serialize)]
+#[binrw]
+#[brw(magic = b"CM3\0")]
+#[bw(stream = s)]
+#[derive(Debug, Serialize, Deserialize)]
 struct CM3 {
+    #[br(temp)]
+    #[bw(try_calc = chunk_start(s))]
     size: u32,
     #[br(temp,assert(const_1==0x6515f8,"Invalid timestamp"))]
+    #[bw(calc = 0x6515f8)]
     const_1: u32,
     #[br(try_map=convert_timestamp)]
+    #[bw(try_map=|dt: &DateTime<Utc>| u32::try_from(dt.timestamp()))]
     time_1: DateTime<Utc>,
     #[br(try_map=convert_timestamp)]
+    #[bw(try_map=|dt: &DateTime<Utc>| u32::try_from(dt.timestamp()))]
     time_2: DateTime<Utc>,
     scene: SCN,
+    #[br(temp, calc = ChunkEnd::default())]
+    #[bw(calc = ChunkEnd(size))]
+    _end: ChunkEnd,
 }
 impl CM3 {
     fn dependencies(&self) -> Vec<String> {
@@ -797,8 +1143,8 @@ impl CM3 {
     }
 }
 
-#[binread]
-#[derive(Debug, Serialize)]
+#[binrw]
+#[derive(Debug, Serialize, Deserialize)]
 struct Dummy {
     has_next: u32,
     name: PascalString,
@@ -807,37 +1153,72 @@ struct Dummy {
     info: Optional<INI>,
 }
 
-#[binread]
-#[br(magic = b"DUM\0")]
-#[derive(Debug, Serialize)]
+#[binrw]
+#[brw(magic = b"DUM\0")]
+#[bw(stream = s)]
+#[derive(Debug, Serialize, Deserialize)]
 struct DUM {
+    #[br(temp)]
+    #[bw(try_calc = chunk_start(s))]
     size: u32,
     #[br(assert(version==1, "Invalid DUM version"))]
     version: u32,
+    #[br(temp)]
+    #[bw(try_calc = u32::try_from(dummies.len()))]
     num_dummies: u32,
-    #[br(count=num_dummies)]
+    #[br(parse_with = indexed, count = num_dummies)]
     dummies: Vec<Dummy>,
+    #[br(temp, calc = ChunkEnd::default())]
+    #[bw(calc = ChunkEnd(size))]
+    _end: ChunkEnd,
 }
 
-#[binread]
-#[br(magic = b"QUAD")]
-#[derive(Debug, Serialize)]
+#[binrw]
+#[brw(magic = b"QUAD")]
+#[bw(stream = s)]
+#[derive(Debug, Serialize, Deserialize)]
 struct QUAD {
+    #[br(temp)]
+    #[bw(try_calc = chunk_start(s))]
     size: u32,
     #[br(assert(version==1, "Invalid QUAD version"))]
     version: u32,
     mesh: u32,
     table: Table<u16>,
     f_4: [f32; 4],
+    #[br(temp)]
+    #[bw(try_calc = u32::try_from(children.len()))]
     num_children: u32,
-    #[br(count=num_children)]
+    #[br(parse_with = indexed, count = num_children)]
     children: Vec<QUAD>,
+    #[br(temp, calc = ChunkEnd::default())]
+    #[bw(calc = ChunkEnd(size))]
+    _end: ChunkEnd,
+}
+
+/// Collision triangle, kept as stored since the layout is unverified. It's inferred
+/// from the entry size (0x1c) and the engine drawing collision geometry as planes:
+/// indices into `CMSH.verts` followed by the face plane (normal and distance).
+#[binrw]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+struct CollisionFace {
+    raw: [u8; 0x1c],
+}
+
+impl CollisionFace {
+    /// Vertex indices, assuming the inferred layout
+    fn indices(&self) -> [u32; 3] {
+        std::array::from_fn(|n| u32::from_le_bytes(std::array::from_fn(|b| self.raw[n * 4 + b])))
+    }
 }
 
-#[binread]
-#[br(magic = b"CMSH")]
-#[derive(Debug, Serialize)]
+#[binrw]
+#[brw(magic = b"CMSH")]
+#[bw(stream = s)]
+#[derive(Debug, Serialize, Deserialize)]
 struct CMSH {
+    #[br(temp)]
+    #[bw(try_calc = chunk_start(s))]
     size: u32,
     #[br(assert(version==2, "Invalid CMSH version"))]
     version: u32,
@@ -850,16 +1231,50 @@ struct CMSH {
     index: u8,
     unk_4: u8,
     bbox_1: [[f32; 3]; 2],
+    verts: Table<[f32; 3]>,
+    #[br(assert(faces.entry_size==0x1c, "Invalid collision face size"))]
+    faces: Table<CollisionFace>,
+    #[br(temp, calc = ChunkEnd::default())]
+    #[bw(calc = ChunkEnd(size))]
+    _end: ChunkEnd,
+}
+
+#[binrw]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+struct Tile {
+    pos: [u16; 2],
+    size: [u16; 2],
+}
+
+/// Sprite sheet layout, the tiles are rectangles in the base texture of the same name
+#[binrw]
+#[brw(magic = b"MST\0")]
+#[bw(stream = s)]
+#[derive(Debug, Serialize, Deserialize)]
+struct MST {
     #[br(temp)]
-    _t_1: Table<[f32; 3]>,
+    #[bw(try_calc = chunk_start(s))]
+    size: u32,
+    #[br(assert(version==100, "Invalid MST version"))]
+    version: u32,
+    image_size: [u32; 2],
     #[br(temp)]
-    _t_2: RawTable<0x1c>,
-}
-
-#[binread]
-#[br(magic = b"AMC\0")]
-#[derive(Debug, Serialize)]
+    #[bw(try_calc = u32::try_from(tiles.len()))]
+    num_tiles: u32,
+    #[br(count=num_tiles)]
+    tiles: Vec<Tile>,
+    #[br(temp, calc = ChunkEnd::default())]
+    #[bw(calc = ChunkEnd(size))]
+    _end: ChunkEnd,
+}
+
+#[binrw]
+#[brw(magic = b"AMC\0")]
+#[bw(stream = s)]
+#[derive(Debug, Serialize, Deserialize)]
 struct AMC {
+    #[br(temp)]
+    #[bw(try_calc = chunk_start(s))]
     size: u32,
     #[br(assert(version==100,"Invalid AMC version"))]
     version: u32,
@@ -869,26 +1284,36 @@ struct AMC {
     scale: f32,
     bbox_2: [[f32; 3]; 2],
     unk: [f32; 3],
+    #[br(parse_with = indexed_array)]
     cmsh: [CMSH; 2],
+    #[br(temp)]
+    #[bw(try_calc = u32::try_from(sector_col.len()))]
     num_sectors: u32,
-    #[br(count=num_sectors)]
+    #[br(parse_with = indexed, count = num_sectors)]
     sector_col: Vec<[CMSH; 2]>,
     unk_num_1: u32,
     unk_num_2: u32,
     unk_f: [f32; 4],
+    #[br(temp)]
+    #[bw(try_calc = u32::try_from(quads.len()))]
     num_quads: u32,
-    #[br(count=num_quads)]
+    #[br(parse_with = indexed, count = num_quads)]
     quads: Vec<QUAD>,
+    #[br(temp, calc = ChunkEnd::default())]
+    #[bw(calc = ChunkEnd(size))]
+    _end: ChunkEnd,
 }
 
-#[binread]
+#[binrw]
 #[br(import(version: u32))]
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 struct TriV104 {
     #[br(if(version>=0x69))]
     sector_name: Option<PascalString>,
     mat_key: u32,
     map_key: u32,
+    #[br(temp)]
+    #[bw(try_calc = u32::try_from(tris.len()))]
     num_tris: u32,
     #[br(count=num_tris)]
     tris: Vec<[u16; 3]>,
@@ -896,41 +1321,62 @@ struct TriV104 {
     verts_2: LFVF,
 }
 
-#[binread]
-#[br(magic = b"TRI\0", import(version: u32))]
-#[derive(Debug, Serialize)]
+#[binrw]
+#[brw(magic = b"TRI\0")]
+#[br(import(version: u32))]
+#[bw(stream = s)]
+#[derive(Debug, Serialize, Deserialize)]
 struct TRI {
+    #[br(temp)]
+    #[bw(try_calc = chunk_start(s))]
     size: u32,
     flags: u32,
     name: PascalString,
     sector_num: u32, // if 0xffffffff sometimes TriV104 has no name_2 field
     #[br(args(version))]
     data: TriV104,
+    #[br(temp, calc = ChunkEnd::default())]
+    #[bw(calc = ChunkEnd(size))]
+    _end: ChunkEnd,
 }
 
-#[binread]
-#[derive(Debug, Serialize)]
+#[binrw]
+#[derive(Debug, Serialize, Deserialize)]
 struct EMI_Textures {
     key: u32,
     #[br(if(key!=0))]
     data: Option<(PascalString, u32, PascalString)>,
 }
 
-#[binread]
-#[br(magic = b"EMI\0")]
-#[derive(Debug, Serialize)]
+#[binrw]
+#[brw(magic = b"EMI\0")]
+#[bw(stream = s)]
+#[derive(Debug, Serialize, Deserialize)]
 struct EMI {
+    #[br(temp)]
+    #[bw(try_calc = chunk_start(s))]
     size: u32,
     #[br(assert((103..=105).contains(&version)))]
     version: u32,
+    #[br(temp)]
+    #[bw(try_calc = u32::try_from(materials.len()))]
     num_materials: u32,
-    #[br(count=num_materials)]
+    #[br(parse_with = indexed, count = num_materials)]
     materials: Vec<(u32, MAT)>,
     #[br(parse_with = until_exclusive(|v: &EMI_Textures| v.key==0))]
     maps: Vec<EMI_Textures>,
+    // terminating entry (key==0) consumed by `until_exclusive`
+    #[br(temp, calc = 0)]
+    #[bw(calc = 0)]
+    maps_end: u32,
+    #[br(temp)]
+    #[bw(try_calc = u32::try_from(tri.len()))]
     num_lists: u32,
-    #[br(count=num_lists,args{inner: (version,)})]
+    #[br(parse_with = indexed, count = num_lists, args { inner: (version,) })]
     tri: Vec<TRI>,
+    #[br(temp, calc = ChunkEnd::default())]
+    #[bw(calc = ChunkEnd(size))]
+    _end: ChunkEnd,
 }
 
 impl EMI {
@@ -960,8 +1406,8 @@ impl EMI {
     }
 }
 
-#[binread]
-#[derive(Debug, Serialize)]
+#[binrw]
+#[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "type")]
 enum Data {
     SM3(SM3),
@@ -969,9 +1415,23 @@ enum Data {
     DUM(DUM),
     AMC(AMC),
     EMI(EMI),
+    MST(MST),
+    /// No magic, only read by [`parse_file`] based on the extension
+    #[br(pre_assert(false))]
+    #[serde(skip_deserializing)]
+    PTH(ai_path::Graph),
+    /// Same as `PTH`, has its own JSON format in [`save::Save::from_json`]
+    #[br(pre_assert(false))]
+    #[serde(skip_deserializing)]
+    SAV(save::Save),
 }
 
 impl Data {
+    /// Inverse of serializing to JSON, for editing files as text
+    fn from_json(data: &str) -> Result<Self> {
+        Ok(serde_json::from_str(data)?)
+    }
+
     fn dependencies(&self) -> Vec<String> {
         match self {
             Data::SM3(sm3) => sm3.dependencies(),
@@ -982,118 +1442,303 @@ impl Data {
     }
 }
 
-fn parse_file(path: &VfsPath) -> Result<Data> {
-    let mut rest_size = 0;
-    let mut fh = BufReader::new(path.open_file()?);
-    let ret = fh.read_le()?;
-    let pos = fh.stream_position().unwrap_or(0);
-    eprintln!("Read {} bytes from {}", pos, path.as_str());
-    let mut buffer = [0u8; 0x1000];
-    if let Ok(n) = fh.read(&mut buffer) {
-        if n != 0 {
-            eprintln!("Rest:\n{}", rhexdumps!(&buffer[..n], pos));
-        }
-    };
-    while let Ok(n) = fh.read(&mut buffer) {
-        if n == 0 {
-            break;
-        }
-        rest_size += n;
+/// A parsed file and how much of it the parser consumed
+#[derive(Debug)]
+struct ParsedFile {
+    data: Data,
+    /// Offset of the first byte the parser didn't consume
+    end: u64,
+    /// Bytes left over after `end`
+    trailing_bytes: u64,
+}
+
+/// Parses `data` read from `path`, the extension selects the format for files that aren't
+/// chunked
+fn parse_data(path: &VfsPath, data: &[u8]) -> std::result::Result<ParsedFile, Box<ParseError>> {
+    let mut fh = Cursor::new(data);
+    let ext = path.extension().unwrap_or_default().to_ascii_lowercase();
+    let ret = match ext.as_str() {
+        "pth" => fh.read_le().map(Data::PTH),
+        "sav" => fh.read_le().map(Data::SAV),
+        _ => fh.read_le(),
+    }
+    .map_err(|e| ParseError::new(path.as_str(), &e, data))?;
+    let end = fh.position();
+    Ok(ParsedFile {
+        data: ret,
+        end,
+        trailing_bytes: data.len() as u64 - end,
+    })
+}
+
+fn parse_file(path: &VfsPath) -> std::result::Result<ParsedFile, Box<ParseError>> {
+    let mut data = vec![];
+    path.open_file()
+        .and_then(|mut fh| Ok(fh.read_to_end(&mut data)?))
+        .map_err(|e| ParseError::io(path.as_str(), e))?;
+    parse_data(path, &data)
+}
+
+fn write_file<P: AsRef<Path>>(data: &Data, path: P) -> Result<()> {
+    let mut fh = BufWriter::new(fs_err::File::create(path.as_ref())?);
+    fh.write_le(data)?;
+    Ok(())
+}
+
+fn load_ini(path: &VfsPath) -> IniFile {
+    let mut data = vec![];
+    match path
+        .open_file()
+        .and_then(|mut fh| Ok(fh.read_to_end(&mut data)?))
+    {
+        Ok(_) => IniFile::from_bytes(&data),
+        Err(_) => IniFile::default(),
     }
-    eprintln!("+{rest_size} unparsed bytes");
-    Ok(ret)
 }
 
-fn load_ini(path: &VfsPath) -> IniData {
-    let Ok(data) = path.read_to_string() else {
-        return IniData::default();
-    };
-    Ini::new().read(data).unwrap_or_default()
+/// Files in `map/` loaded into dedicated [`Level`] fields, the first three are required
+const LEVEL_FILES: &[&str] = &[
+    "map3d.emi",
+    "map3d.sm3",
+    "map3d.dum",
+    "map3d_2.sm3",
+    "map3d.ini",
+    "moredummies.ini",
+    "map3d.amc",
+];
+const REQUIRED_LEVEL_FILES: usize = 3;
+/// Other files in `map/` with these extensions are parsed into [`Level::other`]
+const LEVEL_EXTRA_EXTENSIONS: &[&str] = &["sm3", "cm3", "dum", "amc", "emi", "mst"];
+
+/// Dummy from `map3d.dum` and/or the `moredummies.ini` section of the same name
+#[derive(Serialize, Debug, PartialEq)]
+struct LevelDummy {
+    name: String,
+    /// Index into `Level.dummies.dummies`
+    dum: Option<usize>,
+    /// Section in `Level.moredummies`
+    moredummies: Option<String>,
+    /// From the DUM entry if there is one, otherwise the `Pos`/`Rot` keys of the section
+    pos: Option<[f32; 3]>,
+    rot: Option<[f32; 3]>,
 }
 
 #[derive(Serialize, Debug)]
 struct Level {
-    config: IniData,
-    moredummies: IniData,
+    config: IniFile,
+    moredummies: IniFile,
     emi: EMI,
     sm3: [Option<SM3>; 2],
     dummies: DUM,
+    collision: Option<AMC>,
+    /// All .pth files below the level directory, keyed by relative path
+    ai_paths: BTreeMap<String, ai_path::Graph>,
+    /// Other parseable files in `map/`, keyed by file name
+    other: BTreeMap<String, Data>,
+    linked_dummies: Vec<LevelDummy>,
+    /// Expected files that don't exist, relative to the level directory
+    missing: Vec<String>,
+    /// Files that failed to parse, relative to the level directory
+    errors: BTreeMap<String, String>,
     path: String,
     dependencies: HashMap<String, String>,
+    /// Texture names none of the search paths contain
+    unresolved: BTreeSet<String>,
+}
+
+fn ini_vec3(ini: &IniFile, section: &str, key: &str) -> Option<[f32; 3]> {
+    let values: Vec<f32> = ini
+        .get(section, key)?
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|v| !v.is_empty())
+        .map(|v| v.parse().ok())
+        .collect::<Option<_>>()?;
+    values.try_into().ok()
+}
+
+/// Matches DUM entries and `moredummies.ini` sections by name (case insensitive), in DUM
+/// order followed by the sections without a DUM entry
+fn link_dummies(dum: &DUM, moredummies: &IniFile) -> Vec<LevelDummy> {
+    let sections = moredummies.sections();
+    let mut linked = vec![false; sections.len()];
+    let mut ret: Vec<LevelDummy> = dum
+        .dummies
+        .iter()
+        .enumerate()
+        .map(|(n, dummy)| {
+            let name = &dummy.name.string;
+            let section = sections
+                .iter()
+                .position(|section| section.eq_ignore_ascii_case(name));
+            if let Some(section) = section {
+                linked[section] = true;
+            }
+            LevelDummy {
+                name: name.clone(),
+                dum: Some(n),
+                moredummies: section.map(|n| sections[n].to_owned()),
+                pos: Some(dummy.pos),
+                rot: Some(dummy.rot),
+            }
+        })
+        .collect();
+    for (name, linked) in sections.into_iter().zip(linked) {
+        if !linked {
+            ret.push(LevelDummy {
+                name: name.to_owned(),
+                dum: None,
+                moredummies: Some(name.to_owned()),
+                pos: ini_vec3(moredummies, name, "pos"),
+                rot: ini_vec3(moredummies, name, "rot"),
+            });
+        }
+    }
+    ret
+}
+
+fn relative_path(base: &VfsPath, path: &VfsPath) -> String {
+    let path = path.as_str();
+    path.strip_prefix(base.as_str())
+        .unwrap_or(path)
+        .trim_start_matches('/')
+        .to_owned()
 }
 
 impl Level {
-    fn load(path: &VfsPath) -> Result<Self> {
-        let map_path = path.join("map")?;
-        let emi_path = map_path.join("map3d.emi")?;
-        let sm3_path = map_path.join("map3d.sm3")?;
-        let sm3_2_path = map_path.join("map3d_2.sm3")?;
-        let dum_path = map_path.join("map3d.dum")?;
-        let config_file = map_path.join("map3d.ini")?;
-        let moredummies = map_path.join("moredummies.ini")?;
+    fn load(path: &VfsPath) -> std::result::Result<Self, Box<ParseError>> {
+        let io = |e: vfs::VfsError| ParseError::io(path.as_str(), e);
+        let map_path = path.join("map").map_err(io)?;
+        let join = |name: &str| map_path.join(name).map_err(io);
+        let emi_path = join("map3d.emi")?;
+        let sm3_path = join("map3d.sm3")?;
+        let sm3_2_path = join("map3d_2.sm3")?;
+        let dum_path = join("map3d.dum")?;
+        let amc_path = join("map3d.amc")?;
+        let config_file = join("map3d.ini")?;
+        let moredummies = join("moredummies.ini")?;
+        let exists = |name: &str| {
+            map_path
+                .join(name)
+                .and_then(|path| path.is_file())
+                .unwrap_or(false)
+        };
+        let missing: Vec<String> = LEVEL_FILES
+            .iter()
+            .filter(|name| !exists(name))
+            .map(|name| format!("map/{name}"))
+            .collect();
+        let required_missing: Vec<&str> = LEVEL_FILES[..REQUIRED_LEVEL_FILES]
+            .iter()
+            .copied()
+            .filter(|name| !exists(name))
+            .collect();
+        if !required_missing.is_empty() {
+            let missing = format!("Missing {}", required_missing.join(", "));
+            return Err(ParseError::io(path.as_str(), missing));
+        }
+        let mut errors = BTreeMap::new();
+        let mut load_optional = |file: &VfsPath| {
+            if !file.exists().unwrap_or(false) {
+                return None;
+            }
+            parse_file(file)
+                .map(|file| file.data)
+                .map_err(|e| errors.insert(relative_path(path, file), e.to_string()))
+                .ok()
+        };
+
         let config = load_ini(&config_file);
         let moredummies = load_ini(&moredummies);
-        let Data::EMI(emi) = parse_file(&emi_path)? else {
-            bail!(
-                "Failed to parse EMI at {emi_path}",
-                emi_path = emi_path.as_str()
-            );
+        let wrong_type = |file: &VfsPath, expected: &str| {
+            ParseError::io(file.as_str(), format!("Not a {expected} file"))
+        };
+        let Data::EMI(emi) = parse_file(&emi_path)?.data else {
+            return Err(wrong_type(&emi_path, "EMI"));
         };
 
-        let sm3 = match parse_file(&sm3_path)? {
-            Data::SM3(sm3) => sm3,
-            _ => bail!(
-                "Failed to parse SM3 at {sm3_path}",
-                sm3_path = sm3_path.as_str()
-            ),
+        let Data::SM3(sm3) = parse_file(&sm3_path)?.data else {
+            return Err(wrong_type(&sm3_path, "SM3"));
         };
 
-        let sm3_2 = match parse_file(&sm3_2_path) {
-            Ok(Data::SM3(sm3_2)) => Some(sm3_2),
-            Ok(_) => bail!(
-                "Failed to parse SM3 at {sm3_2}",
-                sm3_2 = sm3_2_path.as_str()
-            ),
-            Err(e) => {
-                println!(
-                    "Failed to parse {sm3_2_path}: {e}",
-                    sm3_2_path = sm3_2_path.as_str()
-                );
-                None
-            }
+        let sm3_2 = match load_optional(&sm3_2_path) {
+            Some(Data::SM3(sm3_2)) => Some(sm3_2),
+            Some(_) => return Err(wrong_type(&sm3_2_path, "SM3")),
+            None => None,
         };
 
-        let Data::DUM(dummies) = parse_file(&dum_path)? else {
-            bail!(
-                "Failed to parse DUM at {dum_path}",
-                dum_path = dum_path.as_str()
-            );
+        let collision = match load_optional(&amc_path) {
+            Some(Data::AMC(amc)) => Some(amc),
+            Some(_) => return Err(wrong_type(&amc_path, "AMC")),
+            None => None,
         };
 
-        let mut dependencies = HashMap::new();
-        let sm3_2_deps: Vec<String> = sm3_2.iter().flat_map(|v| v.dependencies()).collect();
-        for dep in [sm3.dependencies(), sm3_2_deps, emi.dependencies()]
-            .into_iter()
-            .flatten()
-        {
-            match resolve_dep(&dep, &map_path, &config) {
-                Some(res) => {
-                    dependencies.insert(dep, res.as_str().to_owned());
-                }
-                None => {
-                    println!("Failed to resolve dependency: {}", dep);
-                    continue;
-                }
+        let Data::DUM(dummies) = parse_file(&dum_path)?.data else {
+            return Err(wrong_type(&dum_path, "DUM"));
+        };
+
+        let mut ai_paths = BTreeMap::new();
+        for entry in path.walk_dir().map_err(io)? {
+            let entry = entry.map_err(io)?;
+            let is_pth = entry
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("pth"));
+            if !is_pth || !entry.is_file().map_err(io)? {
+                continue;
+            }
+            if let Some(Data::PTH(graph)) = load_optional(&entry) {
+                ai_paths.insert(relative_path(path, &entry), graph);
+            }
+        }
+
+        let mut other = BTreeMap::new();
+        for entry in map_path.read_dir().map_err(io)? {
+            let filename = entry.filename();
+            let known = LEVEL_FILES
+                .iter()
+                .any(|name| name.eq_ignore_ascii_case(&filename));
+            let parseable = entry.extension().is_some_and(|ext| {
+                LEVEL_EXTRA_EXTENSIONS
+                    .iter()
+                    .any(|extra| extra.eq_ignore_ascii_case(&ext))
+            });
+            if known || !parseable || !entry.is_file().map_err(io)? {
+                continue;
+            }
+            if let Some(data) = load_optional(&entry) {
+                other.insert(filename, data);
             }
         }
+
+        let sm3_2_deps: Vec<String> = sm3_2.iter().flat_map(|v| v.dependencies()).collect();
+        let other_deps: Vec<String> = other.values().flat_map(|v| v.dependencies()).collect();
+        let (dependencies, unresolved) = resolve_deps(
+            [
+                sm3.dependencies(),
+                sm3_2_deps,
+                emi.dependencies(),
+                other_deps,
+            ]
+            .into_iter()
+            .flatten(),
+            &map_path,
+            &config,
+        );
+        let linked_dummies = link_dummies(&dummies, &moredummies);
         Ok(Level {
             config,
             moredummies,
             emi,
             sm3: [Some(sm3), sm3_2],
             dummies,
+            collision,
+            ai_paths,
+            other,
+            linked_dummies,
+            missing,
+            errors,
             path: path.as_str().to_owned(),
             dependencies,
+            unresolved,
         })
     }
 }
@@ -1120,14 +1765,11 @@ fn with_extension(path: &str, ext: &str) -> String {
         .to_owned()
 }
 
-fn resolve_dep(dep: &str, level_path: &VfsPath, config: &IniData) -> Option<VfsPath> {
+fn resolve_dep(dep: &str, level_path: &VfsPath, config: &IniFile) -> Option<VfsPath> {
     let root = level_path.root();
     const EXTS: &[&str] = &["png", "bmp", "dds", "tga", "alpha.dds"];
     let tex_path = config
-        .get("model")
-        .and_then(|config| config.get("texturepath"))
-        .cloned()
-        .flatten()
+        .get("model", "texturepath")
         .and_then(|path| root.join(path).ok())
         .map(|path| ancestors(&path))
         .unwrap_or_default();
@@ -1162,6 +1804,27 @@ fn resolve_dep(dep: &str, level_path: &VfsPath, config: &IniData) -> Option<VfsP
     None
 }
 
+/// Resolved dependencies by name and the names that couldn't be resolved
+fn resolve_deps<I: IntoIterator<Item = String>>(
+    deps: I,
+    level_path: &VfsPath,
+    config: &IniFile,
+) -> (HashMap<String, String>, BTreeSet<String>) {
+    let mut dependencies = HashMap::new();
+    let mut unresolved = BTreeSet::new();
+    for dep in deps {
+        match resolve_dep(&dep, level_path, config) {
+            Some(res) => {
+                dependencies.insert(dep, res.as_str().to_owned());
+            }
+            None => {
+                unresolved.insert(dep);
+            }
+        }
+    }
+    (dependencies, unresolved)
+}
+
 fn find_packed<P: AsRef<Path>>(root: P) -> Result<Vec<PathBuf>> {
     let mut files = vec![];
     for entry in WalkDir::new(root).into_iter().filter_map(|e| e.ok()) {
@@ -1179,15 +1842,55 @@ fn find_packed<P: AsRef<Path>>(root: P) -> Result<Vec<PathBuf>> {
 }
 
 mod python {
-    use crate::packed_vfs::MultiPack;
+    use packed::packed_vfs::MultiPack;
 
     use super::Serialize;
-    use super::{PathBuf, Result};
+    use super::{HashMap, PathBuf, Read, Result};
     use fs_err as fs;
     use pyo3::exceptions::{PyIOError, PyValueError};
     use pyo3::prelude::*;
     use pyo3::types::PyBytes;
-    use vfs::VfsPath;
+    use vfs::{MemoryFS, PhysicalFS, VfsPath};
+
+    use crate::ai_path::Graph;
+    use crate::chunks;
+    use crate::collision::{Aabb, Collision};
+    use crate::deps::AssetGraph;
+    use crate::ini::IniFile;
+    use crate::pixel_shader;
+    use crate::save::Save;
+    use crate::sprites;
+    use crate::texture;
+
+    pyo3::create_exception!(
+        ScraplandTool,
+        ParseError,
+        PyValueError,
+        "File that failed to parse, with `file`, `message`, `chunk_path`, `offset`, `context` and `hexdump` attributes"
+    );
+
+    impl From<Box<crate::ParseError>> for PyErr {
+        fn from(err: Box<crate::ParseError>) -> Self {
+            Python::with_gil(|py| {
+                let ret = ParseError::new_err(err.to_string());
+                let value = ret.value(py);
+                let attrs = [
+                    ("file", err.file.to_object(py)),
+                    ("message", err.message.to_object(py)),
+                    ("chunk_path", err.chunk_path.to_object(py)),
+                    ("offset", err.offset.to_object(py)),
+                    ("context", err.context.to_object(py)),
+                    ("hexdump", err.hexdump.to_object(py)),
+                ];
+                for (name, attr) in attrs {
+                    if let Err(e) = value.setattr(name, attr) {
+                        return e;
+                    }
+                }
+                ret
+            })
+        }
+    }
 
     #[derive(Serialize, Debug)]
     struct Entry {
@@ -1200,6 +1903,7 @@ mod python {
     #[pyo3(name = "MultiPack")]
     pub(crate) struct PyMultiPack {
         fs: VfsPath,
+        files: Vec<PathBuf>,
         current: Vec<String>,
     }
 
@@ -1218,18 +1922,72 @@ mod python {
             }
             Ok(entries)
         }
+
+        /// `path` relative to the current directory
+        fn resolve(&self, path: &str) -> PyResult<VfsPath> {
+            let mut root = self.fs.root();
+            for entry in self.current.iter().map(String::as_str).chain([path]) {
+                root = root
+                    .join(entry)
+                    .map_err(|e| PyIOError::new_err(format!("{e}")))?;
+            }
+            Ok(root)
+        }
+
+        fn sprite_table(&self, path: &str) -> PyResult<super::MST> {
+            let mut root = self.fs.root();
+            for entry in &self.current {
+                root = root
+                    .join(entry)
+                    .map_err(|e| PyIOError::new_err(format!("{e}")))?;
+            }
+            let path = root
+                .join(path)
+                .map_err(|e| PyIOError::new_err(format!("{e}")))?;
+            match super::parse_file(&path).map(|file| file.data) {
+                Ok(super::Data::MST(mst)) => Ok(mst),
+                Ok(_) => Err(PyValueError::new_err(format!(
+                    "{} is not a sprite table",
+                    path.as_str()
+                ))),
+                Err(e) => Err(e.into()),
+            }
+        }
+
+        fn texture(&self, path: &str) -> PyResult<image::RgbaImage> {
+            let mut root = self.fs.root();
+            for entry in &self.current {
+                root = root
+                    .join(entry)
+                    .map_err(|e| PyIOError::new_err(format!("{e}")))?;
+            }
+            root.join(path)
+                .map_err(|e| PyIOError::new_err(format!("{e}")))
+                .and_then(|path| {
+                    texture::load(&path).map_err(|e| PyIOError::new_err(format!("{e}")))
+                })
+        }
     }
 
     #[pymethods]
     impl PyMultiPack {
+        /// `overlay` is a directory whose files override the archive contents and receives
+        /// all writes, with `writable` and no `overlay` writes are kept in memory
         #[new]
-        fn new(files: Vec<String>) -> PyResult<Self> {
-            MultiPack::load_all(&files)
-                .map_err(|e| PyIOError::new_err(format!("{e}")))
-                .map(|fs| PyMultiPack {
-                    fs: fs.into(),
-                    current: vec![],
-                })
+        #[pyo3(signature = (files, overlay=None, writable=false))]
+        fn new(files: Vec<String>, overlay: Option<String>, writable: bool) -> PyResult<Self> {
+            let mut fs =
+                MultiPack::load_all(&files).map_err(|e| PyIOError::new_err(format!("{e}")))?;
+            if let Some(overlay) = overlay {
+                fs = fs.with_overlay(PhysicalFS::new(overlay).into());
+            } else if writable {
+                fs = fs.with_overlay(MemoryFS::new().into());
+            }
+            Ok(PyMultiPack {
+                fs: fs.into(),
+                files: files.into_iter().map(PathBuf::from).collect(),
+                current: vec![],
+            })
         }
 
         fn exists(&self, path: &str) -> PyResult<bool> {
@@ -1347,8 +2105,7 @@ mod python {
             {
                 vfs::VfsFileType::File => {
                     println!("File: {}", path.as_str());
-                    let data =
-                        super::parse_file(&path).map_err(|e| PyIOError::new_err(format!("{e}")))?;
+                    let data = super::parse_file(&path)?.data;
                     data.dependencies()
                         .into_iter()
                         .map(|v| (v.clone(), v))
@@ -1356,8 +2113,7 @@ mod python {
                 }
                 vfs::VfsFileType::Directory => {
                     println!("Level directory: {}", path.as_str());
-                    let level = super::Level::load(&path)
-                        .map_err(|e| PyIOError::new_err(format!("{e}")))?;
+                    let level = super::Level::load(&path)?;
                     level.dependencies
                 }
             };
@@ -1400,6 +2156,62 @@ mod python {
             Ok(bytes.to_object(py))
         }
 
+        fn write_file(&self, path: &str, data: &[u8]) -> PyResult<()> {
+            use std::io::Write;
+            let mut root = self.fs.root();
+            for entry in &self.current {
+                root = root
+                    .join(entry)
+                    .map_err(|e| PyIOError::new_err(format!("{e}")))?;
+            }
+            let path = root
+                .join(path)
+                .map_err(|e| PyIOError::new_err(format!("{e}")))?;
+            path.parent()
+                .create_dir_all()
+                .map_err(|e| PyIOError::new_err(format!("{e}")))?;
+            let mut fh = path
+                .create_file()
+                .map_err(|e| PyIOError::new_err(format!("{e}")))?;
+            Ok(fh.write_all(data)?)
+        }
+
+        fn remove(&self, path: &str) -> PyResult<()> {
+            let mut root = self.fs.root();
+            for entry in &self.current {
+                root = root
+                    .join(entry)
+                    .map_err(|e| PyIOError::new_err(format!("{e}")))?;
+            }
+            let path = root
+                .join(path)
+                .map_err(|e| PyIOError::new_err(format!("{e}")))?;
+            let res = if path
+                .is_dir()
+                .map_err(|e| PyIOError::new_err(format!("{e}")))?
+            {
+                path.remove_dir_all()
+            } else {
+                path.remove_file()
+            };
+            res.map_err(|e| PyIOError::new_err(format!("{e}")))
+        }
+
+        /// Writes the merged view (archives + overlay) into a new .packed file
+        fn commit(&self, out_path: &str) -> PyResult<()> {
+            let out_path = PathBuf::from(out_path);
+            for file in &self.files {
+                if fs::canonicalize(file).ok() == fs::canonicalize(&out_path).ok() {
+                    return Err(PyIOError::new_err(format!(
+                        "Can't overwrite loaded archive {}",
+                        file.display()
+                    )));
+                }
+            }
+            packed::packed_vfs::write_packed(&self.fs, out_path)
+                .map_err(|e| PyIOError::new_err(format!("{e}")))
+        }
+
         fn entries(&self, py: Python) -> PyResult<PyObject> {
             let res: Vec<Entry> = self
                 .get_entries()
@@ -1407,6 +2219,78 @@ mod python {
             Ok(pythonize::pythonize(py, &res)?)
         }
 
+        fn coverage(&self, py: Python) -> PyResult<PyObject> {
+            let mut root = self.fs.root();
+            for entry in &self.current {
+                root = root
+                    .join(entry)
+                    .map_err(|e| PyIOError::new_err(format!("{e}")))?;
+            }
+            let report =
+                crate::coverage::run(&root).map_err(|e| PyIOError::new_err(format!("{e}")))?;
+            Ok(pythonize::pythonize(py, &report)?)
+        }
+
+        /// Chunk tree of any chunked file, including unknown or damaged ones, see
+        /// [`chunks::tree`]
+        #[pyo3(signature = (path, max_depth=None, parse=false))]
+        fn chunk_tree(
+            &self,
+            py: Python,
+            path: &str,
+            max_depth: Option<usize>,
+            parse: bool,
+        ) -> PyResult<PyObject> {
+            let mut root = self.fs.root();
+            for entry in &self.current {
+                root = root
+                    .join(entry)
+                    .map_err(|e| PyIOError::new_err(format!("{e}")))?;
+            }
+            let mut data = vec![];
+            root.join(path)
+                .and_then(|path| Ok(path.open_file()?.read_to_end(&mut data)?))
+                .map_err(|e| PyIOError::new_err(format!("{e}")))?;
+            let tree = chunks::tree(chunks::walk(&data), max_depth, parse);
+            Ok(pythonize::pythonize(py, &tree)?)
+        }
+
+        /// Reads a .ini file, see [`IniFile`]
+        fn read_ini(&self, path: &str) -> PyResult<PyIni> {
+            let mut root = self.fs.root();
+            for entry in &self.current {
+                root = root
+                    .join(entry)
+                    .map_err(|e| PyIOError::new_err(format!("{e}")))?;
+            }
+            let mut data = vec![];
+            root.join(path)
+                .and_then(|path| Ok(path.open_file()?.read_to_end(&mut data)?))
+                .map_err(|e| PyIOError::new_err(format!("{e}")))?;
+            Ok(PyIni(IniFile::from_bytes(&data)))
+        }
+
+        fn write_ini(&self, path: &str, ini: &PyIni) -> PyResult<()> {
+            let data = ini
+                .0
+                .to_bytes()
+                .map_err(|e| PyValueError::new_err(format!("{e}")))?;
+            self.write_file(path, &data)
+        }
+
+        /// Builds the dependency graph of everything below the current directory
+        fn asset_graph(&self) -> PyResult<PyAssetGraph> {
+            let mut root = self.fs.root();
+            for entry in &self.current {
+                root = root
+                    .join(entry)
+                    .map_err(|e| PyIOError::new_err(format!("{e}")))?;
+            }
+            AssetGraph::build(&root)
+                .map(PyAssetGraph)
+                .map_err(|e| PyIOError::new_err(format!("{e}")))
+        }
+
         fn dump_to_json(&self, path: String, out_path: String, pretty: bool) -> PyResult<()> {
             use std::io::Write;
             let mut root = self.fs.root();
@@ -1426,8 +2310,7 @@ mod python {
             {
                 vfs::VfsFileType::File => {
                     println!("File: {}", path.as_str());
-                    let data =
-                        super::parse_file(&path).map_err(|e| PyIOError::new_err(format!("{e}")))?;
+                    let data = super::parse_file(&path)?.data;
                     if pretty {
                         serde_json::to_string_pretty(&data)
                     } else {
@@ -1436,8 +2319,7 @@ mod python {
                 }
                 vfs::VfsFileType::Directory => {
                     println!("Level directory: {}", path.as_str());
-                    let level = super::Level::load(&path)
-                        .map_err(|e| PyIOError::new_err(format!("{e}")))?;
+                    let level = super::Level::load(&path)?;
                     if pretty {
                         serde_json::to_string_pretty(&level)
                     } else {
@@ -1450,6 +2332,246 @@ mod python {
             Ok(fh.write_all(data.as_bytes())?)
         }
 
+        fn rebuild_file(&self, path: String, out_path: String) -> PyResult<()> {
+            let mut root = self.fs.root();
+            for entry in &self.current {
+                root = root
+                    .join(entry)
+                    .map_err(|e| PyIOError::new_err(format!("{e}")))?;
+            }
+            let path = root
+                .join(path)
+                .map_err(|e| PyIOError::new_err(format!("{e}")))?;
+            let data = super::parse_file(&path)?.data;
+            super::write_file(&data, out_path).map_err(|e| PyIOError::new_err(format!("{e}")))
+        }
+
+        /// Returns the textures that couldn't be resolved and the error for each texture
+        /// that couldn't be decoded
+        fn export_gltf(
+            &self,
+            path: String,
+            out_path: String,
+        ) -> PyResult<(Vec<String>, HashMap<String, String>)> {
+            let root = self.fs.root();
+            let path = self.resolve(&path)?;
+            let res = match path
+                .metadata()
+                .map_err(|e| PyIOError::new_err(format!("{e}")))?
+                .file_type
+            {
+                vfs::VfsFileType::File => {
+                    let data = super::parse_file(&path)?.data;
+                    let level_path = path.parent();
+                    let config = level_path
+                        .join("map3d.ini")
+                        .map(|ini| super::load_ini(&ini))
+                        .unwrap_or_default();
+                    let (deps, _) = super::resolve_deps(data.dependencies(), &level_path, &config);
+                    let mut exporter = crate::gltf::Exporter::new(&deps);
+                    exporter
+                        .add_data(&path.filename(), &data)
+                        .and_then(|_| exporter.write(&root, out_path.as_ref()))
+                }
+                vfs::VfsFileType::Directory => {
+                    let level = super::Level::load(&path)?;
+                    let mut exporter = crate::gltf::Exporter::new(&level.dependencies);
+                    exporter.add_level(&level);
+                    exporter.write(&root, out_path.as_ref())
+                }
+            };
+            let (unresolved, failed) = res.map_err(|e| PyIOError::new_err(format!("{e}")))?;
+            Ok((unresolved.into_iter().collect(), texture_errors(failed)))
+        }
+
+        fn ai_graph(&self, path: String) -> PyResult<PyGraph> {
+            let mut root = self.fs.root();
+            for entry in &self.current {
+                root = root
+                    .join(entry)
+                    .map_err(|e| PyIOError::new_err(format!("{e}")))?;
+            }
+            let path = root
+                .join(path)
+                .map_err(|e| PyIOError::new_err(format!("{e}")))?;
+            match super::parse_file(&path).map(|file| file.data) {
+                Ok(super::Data::PTH(graph)) => Ok(PyGraph(graph)),
+                Ok(_) => Err(PyValueError::new_err(format!(
+                    "{} is not an AI path file",
+                    path.as_str()
+                ))),
+                Err(e) => Err(e.into()),
+            }
+        }
+
+        /// Loads the collision of a level directory or a single .amc file
+        fn collision(&self, path: String) -> PyResult<PyCollision> {
+            let mut root = self.fs.root();
+            for entry in &self.current {
+                root = root
+                    .join(entry)
+                    .map_err(|e| PyIOError::new_err(format!("{e}")))?;
+            }
+            let mut path = root
+                .join(path)
+                .map_err(|e| PyIOError::new_err(format!("{e}")))?;
+            if path.is_dir().unwrap_or(false) {
+                path = path
+                    .join("map/map3d.amc")
+                    .map_err(|e| PyIOError::new_err(format!("{e}")))?;
+            }
+            match super::parse_file(&path).map(|file| file.data) {
+                Ok(super::Data::AMC(amc)) => Ok(PyCollision(Collision::new(&amc))),
+                Ok(_) => Err(PyValueError::new_err(format!(
+                    "{} is not an AMC file",
+                    path.as_str()
+                ))),
+                Err(e) => Err(e.into()),
+            }
+        }
+
+        /// Cuts `texture_path` into one `<n>.png` per tile of the sprite table `mst_path`,
+        /// returns the written files
+        fn slice_sprites(
+            &self,
+            mst_path: String,
+            texture_path: String,
+            out_dir: String,
+        ) -> PyResult<Vec<String>> {
+            let mst = self.sprite_table(&mst_path)?;
+            let sheet = self.texture(&texture_path)?;
+            fs::create_dir_all(&out_dir)?;
+            let mut ret = vec![];
+            for (n, sprite) in sprites::slice(&mst, &sheet).into_iter().enumerate() {
+                let out_path = PathBuf::from(&out_dir).join(format!("{n:03}.png"));
+                sprite
+                    .save(&out_path)
+                    .map_err(|e| PyIOError::new_err(format!("{e}")))?;
+                ret.push(out_path.to_string_lossy().into_owned());
+            }
+            Ok(ret)
+        }
+
+        /// Pastes the `<n>.png` files in `sprites_dir` back onto `texture_path`, tiles
+        /// without a file keep their original pixels, format of `out_path` follows its extension
+        fn build_sprite_sheet(
+            &self,
+            mst_path: String,
+            texture_path: String,
+            sprites_dir: String,
+            out_path: String,
+        ) -> PyResult<()> {
+            let mst = self.sprite_table(&mst_path)?;
+            let sheet = self.texture(&texture_path)?;
+            let mut sprites = vec![];
+            for n in 0..mst.tiles.len() {
+                let path = PathBuf::from(&sprites_dir).join(format!("{n:03}.png"));
+                let sprite = match path.exists() {
+                    true => Some(
+                        image::open(&path)
+                            .map_err(|e| PyIOError::new_err(format!("{}: {e}", path.display())))?
+                            .into_rgba8(),
+                    ),
+                    false => None,
+                };
+                sprites.push(sprite);
+            }
+            sprites::build(&mst, &sheet, &sprites)
+                .map_err(|e| PyValueError::new_err(format!("{e}")))?
+                .save(&out_path)
+                .map_err(|e| PyIOError::new_err(format!("{e}")))
+        }
+
+        /// Decodes a texture (with its `.alpha.dds` merged in) to `(width, height, rgba)`
+        fn decode_texture(&self, py: Python, path: String) -> PyResult<(u32, u32, PyObject)> {
+            let image = self.texture(&path)?;
+            let (width, height) = image.dimensions();
+            Ok((width, height, PyBytes::new(py, image.as_raw()).into()))
+        }
+
+        /// Decodes a texture and saves it to `out_path`, format follows the extension
+        fn convert_texture(&self, path: String, out_path: String) -> PyResult<()> {
+            self.texture(&path)?
+                .save(out_path)
+                .map_err(|e| PyIOError::new_err(format!("{e}")))
+        }
+
+        /// Converts all textures used by a file or level directory to PNG files in
+        /// `out_dir`, returns the written file for each dependency and the error for each
+        /// texture that couldn't be decoded
+        fn convert_textures(
+            &self,
+            path: String,
+            out_dir: String,
+        ) -> PyResult<(HashMap<String, String>, HashMap<String, String>)> {
+            let mut root = self.fs.root();
+            for entry in &self.current {
+                root = root
+                    .join(entry)
+                    .map_err(|e| PyIOError::new_err(format!("{e}")))?;
+            }
+            let path = root
+                .join(path)
+                .map_err(|e| PyIOError::new_err(format!("{e}")))?;
+            let deps = match path
+                .metadata()
+                .map_err(|e| PyIOError::new_err(format!("{e}")))?
+                .file_type
+            {
+                vfs::VfsFileType::File => {
+                    let data = super::parse_file(&path)?.data;
+                    let level_path = path.parent();
+                    let config = level_path
+                        .join("map3d.ini")
+                        .map(|ini| super::load_ini(&ini))
+                        .unwrap_or_default();
+                    super::resolve_deps(data.dependencies(), &level_path, &config).0
+                }
+                vfs::VfsFileType::Directory => super::Level::load(&path)?.dependencies,
+            };
+            let (written, failed) = texture::export_png(&deps, &root, out_dir.as_ref())
+                .map_err(|e| PyIOError::new_err(format!("{e}")))?;
+            let written = written
+                .into_iter()
+                .map(|(dep, path)| (dep, path.to_string_lossy().into_owned()))
+                .collect();
+            Ok((written, texture_errors(failed)))
+        }
+
+        /// Parses a ps.1.x pixel shader (.psh), returns the AST or with `lang` ("glsl" or
+        /// "wgsl") the translated source
+        #[pyo3(signature = (path, lang=None))]
+        fn pixel_shader(
+            &self,
+            py: Python,
+            path: String,
+            lang: Option<String>,
+        ) -> PyResult<PyObject> {
+            let mut root = self.fs.root();
+            for entry in &self.current {
+                root = root
+                    .join(entry)
+                    .map_err(|e| PyIOError::new_err(format!("{e}")))?;
+            }
+            let mut data = vec![];
+            root.join(path)
+                .and_then(|path| path.open_file())
+                .and_then(|mut fh| Ok(fh.read_to_end(&mut data)?))
+                .map_err(|e| PyIOError::new_err(format!("{e}")))?;
+            let shader = pixel_shader::parse(&String::from_utf8_lossy(&data))
+                .map_err(|e| PyValueError::new_err(format!("{e:#}")))?;
+            match lang {
+                None => Ok(pythonize::pythonize(py, &shader)?),
+                Some(lang) => {
+                    let source = lang
+                        .parse()
+                        .and_then(|lang| shader.translate(lang))
+                        .map_err(|e| PyValueError::new_err(format!("{e}")))?;
+                    Ok(source.into_py(py))
+                }
+            }
+        }
+
         fn parse_file(&self, py: Python, path: String) -> PyResult<PyObject> {
             let mut root = self.fs.root();
             for entry in &self.current {
@@ -1467,14 +2589,12 @@ mod python {
             {
                 vfs::VfsFileType::File => {
                     println!("File: {}", path.as_str());
-                    let data =
-                        super::parse_file(&path).map_err(|e| PyIOError::new_err(format!("{e}")))?;
+                    let data = super::parse_file(&path)?.data;
                     pythonize::pythonize(py, &data)?
                 }
                 vfs::VfsFileType::Directory => {
                     println!("Level directory: {}", path.as_str());
-                    let level = super::Level::load(&path)
-                        .map_err(|e| PyIOError::new_err(format!("{e}")))?;
+                    let level = super::Level::load(&path)?;
                     pythonize::pythonize(py, &level)?
                 }
             };
@@ -1482,6 +2602,258 @@ mod python {
         }
     }
 
+    /// Spatial queries against the collision meshes of a level, see [`Collision`]
+    #[pyclass]
+    #[pyo3(name = "Collision")]
+    pub(crate) struct PyCollision(Collision);
+
+    #[pymethods]
+    impl PyCollision {
+        fn meshes(&self, py: Python) -> PyResult<PyObject> {
+            Ok(pythonize::pythonize(py, self.0.meshes())?)
+        }
+
+        fn unindexed(&self) -> usize {
+            self.0.unindexed()
+        }
+
+        fn sectors_at(&self, py: Python, point: [f32; 3]) -> PyResult<PyObject> {
+            Ok(pythonize::pythonize(py, &self.0.sectors_at(point))?)
+        }
+
+        fn query_aabb(&self, py: Python, min: [f32; 3], max: [f32; 3]) -> PyResult<PyObject> {
+            Ok(pythonize::pythonize(
+                py,
+                &self.0.query_aabb(&Aabb::new(min, max)),
+            )?)
+        }
+
+        #[pyo3(signature = (origin, direction, max_distance=f32::INFINITY))]
+        fn raycast(
+            &self,
+            py: Python,
+            origin: [f32; 3],
+            direction: [f32; 3],
+            max_distance: f32,
+        ) -> PyResult<PyObject> {
+            Ok(pythonize::pythonize(
+                py,
+                &self.0.raycast(origin, direction, max_distance),
+            )?)
+        }
+    }
+
+    /// AI node graph with path queries, see [`Graph`]
+    #[pyclass]
+    #[pyo3(name = "AIGraph")]
+    pub(crate) struct PyGraph(Graph);
+
+    #[pymethods]
+    impl PyGraph {
+        #[getter]
+        fn nodes(&self) -> Vec<Vec<f32>> {
+            self.0.nodes.clone()
+        }
+
+        #[getter]
+        fn edges(&self) -> Vec<Vec<u32>> {
+            self.0.edges.clone()
+        }
+
+        fn nearest_node(&self, pos: Vec<f32>) -> PyResult<Option<u32>> {
+            self.0
+                .nearest_node(&pos)
+                .map_err(|e| PyValueError::new_err(format!("{e}")))
+        }
+
+        fn shortest_path(&self, py: Python, start: Vec<f32>, end: Vec<f32>) -> PyResult<PyObject> {
+            let path = self
+                .0
+                .shortest_path(&start, &end)
+                .map_err(|e| PyValueError::new_err(format!("{e}")))?;
+            Ok(pythonize::pythonize(py, &path)?)
+        }
+    }
+
+    /// Install-wide asset dependencies, see [`AssetGraph`]
+    #[pyclass]
+    #[pyo3(name = "AssetGraph")]
+    pub(crate) struct PyAssetGraph(AssetGraph);
+
+    #[pymethods]
+    impl PyAssetGraph {
+        fn users_of(&self, py: Python, path: &str) -> PyResult<PyObject> {
+            Ok(pythonize::pythonize(py, &self.0.users_of(path))?)
+        }
+
+        fn affected_by(&self, py: Python, path: &str) -> PyResult<PyObject> {
+            Ok(pythonize::pythonize(py, &self.0.affected_by(path))?)
+        }
+
+        fn orphans(&self) -> Vec<&str> {
+            self.0.orphans()
+        }
+
+        fn unresolved(&self, py: Python) -> PyResult<PyObject> {
+            let unresolved: Vec<_> = self.0.unresolved().collect();
+            Ok(pythonize::pythonize(py, &unresolved)?)
+        }
+
+        fn errors(&self, py: Python) -> PyResult<PyObject> {
+            Ok(pythonize::pythonize(py, self.0.errors())?)
+        }
+
+        #[pyo3(signature = (pretty=true))]
+        fn to_json(&self, pretty: bool) -> PyResult<String> {
+            self.0
+                .to_json(pretty)
+                .map_err(|e| PyValueError::new_err(format!("{e}")))
+        }
+
+        fn to_dot(&self) -> String {
+            self.0.to_dot()
+        }
+    }
+
+    /// Save game (.sav) loaded from disk, see [`Save`]
+    #[pyclass]
+    #[pyo3(name = "Save")]
+    pub(crate) struct PySave(Save);
+
+    #[pymethods]
+    impl PySave {
+        #[staticmethod]
+        fn load(path: &str) -> PyResult<Self> {
+            let data = fs::read(path)?;
+            Save::from_bytes(&data)
+                .map(PySave)
+                .map_err(|e| PyValueError::new_err(format!("{path}: {e}")))
+        }
+
+        #[staticmethod]
+        fn from_json(data: &str) -> PyResult<Self> {
+            Save::from_json(data)
+                .map(PySave)
+                .map_err(|e| PyValueError::new_err(format!("{e}")))
+        }
+
+        fn save(&self, path: &str) -> PyResult<()> {
+            let data = self
+                .0
+                .to_bytes()
+                .map_err(|e| PyValueError::new_err(format!("{e}")))?;
+            Ok(fs::write(path, data)?)
+        }
+
+        #[pyo3(signature = (pretty=true))]
+        fn to_json(&self, pretty: bool) -> PyResult<String> {
+            self.0
+                .to_json(pretty)
+                .map_err(|e| PyValueError::new_err(format!("{e}")))
+        }
+
+        #[getter]
+        fn title(&self) -> &str {
+            self.0.title()
+        }
+
+        #[getter]
+        fn id(&self) -> &str {
+            self.0.id()
+        }
+
+        fn vars(&self) -> Vec<(&str, &str)> {
+            self.0.vars().collect()
+        }
+
+        fn get(&self, name: &str) -> Option<&str> {
+            self.0.get(name)
+        }
+
+        fn set(&mut self, name: &str, value: &str) -> PyResult<()> {
+            self.0
+                .set(name, value)
+                .map_err(|e| PyValueError::new_err(format!("{e}")))
+        }
+
+        fn remove(&mut self, name: &str) -> bool {
+            self.0.remove(name)
+        }
+
+        /// Variables added, removed or changed in `new`
+        fn diff(&self, py: Python, new: &PySave) -> PyResult<PyObject> {
+            Ok(pythonize::pythonize(py, &self.0.diff(&new.0))?)
+        }
+    }
+
+    /// Order preserving .ini file, see [`IniFile`]
+    #[pyclass]
+    #[pyo3(name = "Ini")]
+    pub(crate) struct PyIni(IniFile);
+
+    #[pymethods]
+    impl PyIni {
+        #[staticmethod]
+        fn parse(text: &str) -> Self {
+            PyIni(IniFile::parse(text))
+        }
+
+        #[staticmethod]
+        fn load(path: &str) -> PyResult<Self> {
+            Ok(PyIni(IniFile::from_bytes(&fs::read(path)?)))
+        }
+
+        fn save(&self, path: &str) -> PyResult<()> {
+            let data = self
+                .0
+                .to_bytes()
+                .map_err(|e| PyValueError::new_err(format!("{e}")))?;
+            Ok(fs::write(path, data)?)
+        }
+
+        fn sections(&self) -> Vec<&str> {
+            self.0.sections()
+        }
+
+        /// `(section, key, value)` of all entries in file order
+        fn entries(&self) -> Vec<(&str, &str, Option<&str>)> {
+            self.0.entries().collect()
+        }
+
+        fn get(&self, section: &str, key: &str) -> Option<&str> {
+            self.0.get(section, key)
+        }
+
+        fn get_all(&self, section: &str, key: &str) -> Vec<&str> {
+            self.0.get_all(section, key)
+        }
+
+        fn set(&mut self, section: &str, key: &str, value: &str) {
+            self.0.set(section, key, value)
+        }
+
+        fn remove(&mut self, section: &str, key: &str) -> bool {
+            self.0.remove(section, key)
+        }
+
+        /// Every line, including comments and blank ones
+        fn lines(&self, py: Python) -> PyResult<PyObject> {
+            Ok(pythonize::pythonize(py, &self.0.lines)?)
+        }
+
+        fn __str__(&self) -> String {
+            self.0.to_string()
+        }
+    }
+
+    /// Texture decode failures as returned to Python, error message by path
+    fn texture_errors(failed: texture::DecodeFailures) -> HashMap<String, String> {
+        failed
+            .into_iter()
+            .map(|(path, e)| (path, format!("{e:#}")))
+            .collect()
+    }
+
     #[pyfunction]
     fn find_scrapland() -> Option<PathBuf> {
         super::find_scrap::get_path()
@@ -1492,12 +2864,174 @@ mod python {
         super::find_packed(root).map_err(|e| PyIOError::new_err(format!("{e}")))
     }
 
+    #[pyfunction]
+    fn import_gltf(path: &str, out_path: &str) -> PyResult<()> {
+        let sm3 = crate::gltf_import::import(path.as_ref())
+            .map_err(|e| PyValueError::new_err(format!("{e:?}")))?;
+        super::write_file(&super::Data::SM3(sm3), out_path)
+            .map_err(|e| PyIOError::new_err(format!("{e}")))
+    }
+
+    /// Builds a chunked file from JSON written by `MultiPack.dump_to_json`
+    #[pyfunction]
+    fn import_json(path: &str, out_path: &str) -> PyResult<()> {
+        let data = super::Data::from_json(&fs::read_to_string(path)?)
+            .map_err(|e| PyValueError::new_err(format!("{e}")))?;
+        super::write_file(&data, out_path).map_err(|e| PyIOError::new_err(format!("{e}")))
+    }
+
     #[pymodule]
     #[pyo3(name = "ScraplandTool")]
     fn scrapland_tool(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
         m.add_function(wrap_pyfunction!(find_scrapland, m)?)?;
         m.add_function(wrap_pyfunction!(find_packed, m)?)?;
+        m.add_function(wrap_pyfunction!(import_gltf, m)?)?;
+        m.add_function(wrap_pyfunction!(import_json, m)?)?;
         m.add_class::<PyMultiPack>()?;
+        m.add_class::<PyCollision>()?;
+        m.add_class::<PyGraph>()?;
+        m.add_class::<PySave>()?;
+        m.add_class::<PyIni>()?;
+        m.add_class::<PyAssetGraph>()?;
+        m.add("ParseError", _py.get_type::<ParseError>())?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::chunk;
+    use binrw::io::Cursor;
+
+    #[test]
+    fn dum_roundtrip() {
+        let mut body = vec![];
+        body.extend(1u32.to_le_bytes()); // version
+        body.extend(1u32.to_le_bytes()); // num_dummies
+        body.extend(0u32.to_le_bytes()); // has_next
+        body.extend(4u32.to_le_bytes());
+        body.extend(b"D\xe9m\0");
+        body.extend([0u8; 24]); // pos, rot
+        body.extend(0u32.to_le_bytes()); // info
+        let data = chunk(b"DUM\0", &body);
+
+        let parsed: Data = Cursor::new(&data).read_le().unwrap();
+        let Data::DUM(mut dum) = parsed else {
+            panic!("expected DUM");
+        };
+        assert_eq!(dum.dummies[0].name.string, "D\u{e9}m");
+        let mut out = Cursor::new(vec![]);
+        out.write_le(&dum).unwrap();
+        assert_eq!(out.get_ref(), &data);
+
+        dum.dummies[0].name.string = "Dummy".to_owned();
+        let mut out = Cursor::new(vec![]);
+        out.write_le(&dum).unwrap();
+        let out = out.into_inner();
+        assert_eq!(out.len(), data.len() + 2);
+        assert_eq!(
+            out[4..8],
+            u32::try_from(out.len() - 8).unwrap().to_le_bytes()
+        );
+    }
+
+    #[test]
+    fn json_roundtrip() {
+        let info = b"[Props]\0";
+        let mut ini = 1u32.to_le_bytes().to_vec(); // num_sections
+        ini.extend(1u32.to_le_bytes()); // num_lines
+        ini.extend(u32::try_from(info.len()).unwrap().to_le_bytes());
+        ini.extend(info);
+        let mut body = vec![];
+        body.extend(1u32.to_le_bytes()); // version
+        body.extend(1u32.to_le_bytes()); // num_dummies
+        body.extend(0u32.to_le_bytes()); // has_next
+        body.extend(5u32.to_le_bytes());
+        body.extend(b"D\xe9m\0\xcd");
+        body.extend(1.5f32.to_le_bytes());
+        body.extend([0u8; 20]); // pos y, z, rot
+        body.extend(1u32.to_le_bytes()); // info
+        body.extend(chunk(b"INI\0", &ini));
+        let data = chunk(b"DUM\0", &body);
+
+        let parsed: Data = Cursor::new(&data).read_le().unwrap();
+        let json = serde_json::to_string(&parsed).unwrap();
+        assert!(json.contains(r#"{"string":"Dém","padding":[0,205]}"#));
+        assert!(json.contains(r#""info":[[{"section":"Props"}]]"#));
+        let rebuilt = Data::from_json(&json).unwrap();
+        let mut out = Cursor::new(vec![]);
+        out.write_le(&rebuilt).unwrap();
+        assert_eq!(out.into_inner(), data);
+        assert!(Data::from_json(&json.replace("Dém", "\u{263a}")).is_err());
+    }
+
+    #[test]
+    fn level_dummies() {
+        let mut body = vec![];
+        body.extend(1u32.to_le_bytes()); // version
+        body.extend(2u32.to_le_bytes()); // num_dummies
+        for (name, x) in [(&b"DM_Spawn\0"[..], 1.0f32), (b"DM_Track\0", 2.0)] {
+            body.extend(1u32.to_le_bytes()); // has_next
+            body.extend(u32::try_from(name.len()).unwrap().to_le_bytes());
+            body.extend(name);
+            body.extend(x.to_le_bytes());
+            body.extend([0u8; 20]); // pos y, z, rot
+            body.extend(0u32.to_le_bytes()); // info
+        }
+        let data = chunk(b"DUM\0", &body);
+        let dum: DUM = Cursor::new(&data).read_le().unwrap();
+
+        let moredummies =
+            IniFile::parse("[dm_spawn]\npos=5 5 5\n[Extra]\npos=1, 2, 3\nRot=0 0 1\n");
+        let linked = link_dummies(&dum, &moredummies);
+        let names: Vec<_> = linked
+            .iter()
+            .map(|d| (d.name.as_str(), d.dum, d.moredummies.as_deref()))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                ("DM_Spawn", Some(0), Some("dm_spawn")),
+                ("DM_Track", Some(1), None),
+                ("Extra", None, Some("Extra")),
+            ]
+        );
+        assert_eq!(linked[0].pos, Some([1.0, 0.0, 0.0]));
+        assert_eq!(linked[2].pos, Some([1.0, 2.0, 3.0]));
+        assert_eq!(linked[2].rot, Some([0.0, 0.0, 1.0]));
+    }
+
+    #[test]
+    fn cmsh_roundtrip() {
+        let mut body = vec![];
+        body.extend(2u32.to_le_bytes()); // version
+        body.extend(0x34u32.to_le_bytes()); // collide_mesh_size
+        body.extend(4u32.to_le_bytes());
+        body.extend(b"col\0");
+        body.extend([0u8; 8]); // unk_1, sector, unk_2, index, unk_4
+        body.extend([0u8; 24]); // bbox_1
+        body.extend(3u32.to_le_bytes()); // num verts
+        body.extend(12u32.to_le_bytes());
+        for v in [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0] {
+            body.extend(v.to_le_bytes());
+        }
+        body.extend(1u32.to_le_bytes()); // num faces
+        body.extend(0x1cu32.to_le_bytes());
+        for idx in [0u32, 1, 2] {
+            body.extend(idx.to_le_bytes());
+        }
+        for v in [0.0f32, 1.0, 0.0, 0.0] {
+            body.extend(v.to_le_bytes());
+        }
+        let data = chunk(b"CMSH", &body);
+
+        let cmsh: CMSH = Cursor::new(&data).read_le().unwrap();
+        assert_eq!(cmsh.verts.data[1], [1.0, 0.0, 0.0]);
+        assert_eq!(cmsh.faces.data.len(), 1);
+        assert_eq!(cmsh.faces.data[0].indices(), [0, 1, 2]);
+        let mut out = Cursor::new(vec![]);
+        out.write_le(&cmsh).unwrap();
+        assert_eq!(out.into_inner(), data);
+    }
+}
@@ -1,4 +1,3 @@
-#![allow(dead_code)]
 // https://learn.microsoft.com/en-us/windows/win32/direct3dhlsl/dx9-graphics-reference-asm-ps-1-x
 // https://learn.microsoft.com/en-us/windows/win32/direct3dhlsl/dx9-graphics-reference-asm-ps-registers-modifiers-source
 // http://archive.gamedev.net/archive/columns/hardcore/dxshader3/page4.html
@@ -8,68 +7,1066 @@
 // [inst]_x(v) -> res*=v
 // [inst]_d(v) -> res/=v
 // [inst]_sat -> res=clamp(res,0,1)
+//
+// Translated shaders take the vertex colors as v0/v1 at locations 0/1 and texture coordinate N
+// as tcN at location 2+N, sampler N is bound as sN (GLSL) or texN/sampN at group 1, binding
+// 2N/2N+1 (WGSL). Bump env matrices are uniforms `bump_env_matN` holding
+// [M00, M01, M10, M11] (column major) and `bump_lumN` holding (scale, offset), bump maps have
+// to be bound as signed textures. The [-1, 1] (ps.1.1-1.3) and [-8, 8] (ps.1.4) register
+// range clamping of the hardware is not emulated.
 
-use std::{convert::Infallible, str::FromStr};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+use std::str::FromStr;
 
-use anyhow::Result;
-use fs_err as fs;
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Serialize;
 
-#[derive(Debug)]
-struct Arg {
-    name: String,
-    modifiers: Vec<String>,
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub(crate) enum RegisterKind {
+    /// r
+    Temp,
+    /// c
+    Constant,
+    /// t, texture registers before ps.1.4, texture coordinates in ps.1.4
+    Texture,
+    /// v
+    Color,
 }
 
-#[derive(Debug)]
-struct Cmd {
-    name: String,
-    modifiers: Vec<String>,
-    args: Vec<Arg>,
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub(crate) struct Register {
+    pub(crate) kind: RegisterKind,
+    pub(crate) index: u8,
 }
 
-impl FromStr for Cmd {
-    type Err = Infallible;
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub(crate) enum SourceModifier {
+    /// _bias
+    Bias,
+    /// _bx2
+    SignedScale,
+    /// _x2
+    Scale2,
+    /// _dz/_db, texld and texcrd only
+    DivideZ,
+    /// _dw/_da, texld and texcrd only
+    DivideW,
+}
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let line: Vec<&str> = s
-            .split(|c: char| c.is_ascii_whitespace() || c == ',')
-            .map(|c| c.trim_end_matches(',').trim_start_matches('+'))
-            .filter(|w| !w.is_empty())
-            .collect();
-        let (cmd, args) = match line.split_first() {
-            Some((&cmd, args)) => (cmd, args),
-            None => unreachable!(),
-        };
-        println!("{line:?} -> {cmd}{args:?}");
-        Ok(Cmd {
-            name: String::new(),
-            modifiers: vec![],
-            args: vec![],
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct Source {
+    pub(crate) reg: Register,
+    /// -r0
+    pub(crate) negate: bool,
+    /// 1-r0
+    pub(crate) invert: bool,
+    pub(crate) modifier: Option<SourceModifier>,
+    /// Component read for x, y, z and w, replicate swizzles like `.a` are expanded
+    pub(crate) swizzle: [u8; 4],
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct Destination {
+    pub(crate) reg: Register,
+    pub(crate) mask: [bool; 4],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Opcode {
+    Add,
+    Sub,
+    Mul,
+    Mad,
+    Lrp,
+    Cnd,
+    Cmp,
+    Dp3,
+    Dp4,
+    Mov,
+    Nop,
+    Bem,
+    Tex,
+    Texbem,
+    Texbeml,
+    Texcoord,
+    Texkill,
+    Texm3x2pad,
+    Texm3x2tex,
+    Texm3x2depth,
+    Texm3x3pad,
+    Texm3x3tex,
+    Texm3x3spec,
+    Texm3x3vspec,
+    Texm3x3,
+    Texdp3,
+    Texdp3tex,
+    Texreg2ar,
+    Texreg2gb,
+    Texreg2rgb,
+    Texdepth,
+    Texld,
+    Texcrd,
+}
+
+/// Name, opcode, number of operands (including the destination) and required version
+const OPCODES: &[(&str, Opcode, usize, Option<bool>)] = {
+    // Some(false): before ps.1.4, Some(true): ps.1.4 only
+    const OLD: Option<bool> = Some(false);
+    const NEW: Option<bool> = Some(true);
+    &[
+        ("add", Opcode::Add, 3, None),
+        ("sub", Opcode::Sub, 3, None),
+        ("mul", Opcode::Mul, 3, None),
+        ("mad", Opcode::Mad, 4, None),
+        ("lrp", Opcode::Lrp, 4, None),
+        ("cnd", Opcode::Cnd, 4, None),
+        ("cmp", Opcode::Cmp, 4, None),
+        ("dp3", Opcode::Dp3, 3, None),
+        ("dp4", Opcode::Dp4, 3, None),
+        ("mov", Opcode::Mov, 2, None),
+        ("nop", Opcode::Nop, 0, None),
+        ("bem", Opcode::Bem, 3, NEW),
+        ("tex", Opcode::Tex, 1, OLD),
+        ("texbem", Opcode::Texbem, 2, OLD),
+        ("texbeml", Opcode::Texbeml, 2, OLD),
+        ("texcoord", Opcode::Texcoord, 1, OLD),
+        ("texkill", Opcode::Texkill, 1, None),
+        ("texm3x2pad", Opcode::Texm3x2pad, 2, OLD),
+        ("texm3x2tex", Opcode::Texm3x2tex, 2, OLD),
+        ("texm3x2depth", Opcode::Texm3x2depth, 2, OLD),
+        ("texm3x3pad", Opcode::Texm3x3pad, 2, OLD),
+        ("texm3x3tex", Opcode::Texm3x3tex, 2, OLD),
+        ("texm3x3spec", Opcode::Texm3x3spec, 3, OLD),
+        ("texm3x3vspec", Opcode::Texm3x3vspec, 2, OLD),
+        ("texm3x3", Opcode::Texm3x3, 2, OLD),
+        ("texdp3", Opcode::Texdp3, 2, OLD),
+        ("texdp3tex", Opcode::Texdp3tex, 2, OLD),
+        ("texreg2ar", Opcode::Texreg2ar, 2, OLD),
+        ("texreg2gb", Opcode::Texreg2gb, 2, OLD),
+        ("texreg2rgb", Opcode::Texreg2rgb, 2, OLD),
+        ("texdepth", Opcode::Texdepth, 1, NEW),
+        ("texld", Opcode::Texld, 2, NEW),
+        ("texcrd", Opcode::Texcrd, 2, NEW),
+    ]
+};
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct Instruction {
+    pub(crate) opcode: Opcode,
+    /// Result multiplier from _x2, _x4, _x8, _d2, _d4 and _d8
+    pub(crate) scale: f32,
+    /// _sat
+    pub(crate) saturate: bool,
+    /// Prefixed with `+`, runs in parallel with the previous instruction
+    pub(crate) coissue: bool,
+    pub(crate) dst: Option<Destination>,
+    pub(crate) src: Vec<Source>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) enum Statement {
+    Def(Register, [f32; 4]),
+    /// Separates texture and arithmetic phases in ps.1.4
+    Phase,
+    Instruction(Instruction),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct Shader {
+    /// (major, minor)
+    pub(crate) version: (u8, u8),
+    pub(crate) statements: Vec<Statement>,
+}
+
+fn components(s: &str) -> Result<Vec<u8>> {
+    s.chars()
+        .map(|c| match c {
+            'r' | 'x' => Ok(0),
+            'g' | 'y' => Ok(1),
+            'b' | 'z' => Ok(2),
+            'a' | 'w' => Ok(3),
+            _ => Err(anyhow!("Invalid component: {c}")),
         })
+        .collect()
+}
+
+impl FromStr for Register {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (kind, max) = match s.chars().next() {
+            Some('r') => (RegisterKind::Temp, 6),
+            Some('c') => (RegisterKind::Constant, 8),
+            Some('t') => (RegisterKind::Texture, 6),
+            Some('v') => (RegisterKind::Color, 2),
+            _ => bail!("Invalid register: {s}"),
+        };
+        let index: u8 = s[1..]
+            .parse()
+            .map_err(|_| anyhow!("Invalid register: {s}"))?;
+        if index >= max {
+            bail!("Invalid register: {s}");
+        }
+        Ok(Register { kind, index })
+    }
+}
+
+/// Splits `r0_bx2.a` into `r0` and the `_`/`.` separated suffixes
+fn split_suffixes(s: &str) -> Result<(Register, Vec<(char, &str)>)> {
+    let end = 1 + s.chars().skip(1).take_while(|c| c.is_ascii_digit()).count();
+    let reg = s.get(..end).unwrap_or(s).parse()?;
+    let mut rest = &s[end..];
+    let mut suffixes = vec![];
+    while let Some(sep) = rest.chars().next() {
+        if sep != '_' && sep != '.' {
+            bail!("Invalid operand: {s}");
+        }
+        let body = &rest[1..];
+        let end = body.find(['_', '.']).unwrap_or(body.len());
+        suffixes.push((sep, &body[..end]));
+        rest = &body[end..];
+    }
+    Ok((reg, suffixes))
+}
+
+impl FromStr for Source {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (negate, s) = s.strip_prefix('-').map_or((false, s), |s| (true, s));
+        let (invert, s) = s.strip_prefix("1-").map_or((false, s), |s| (true, s));
+        let (reg, suffixes) = split_suffixes(s)?;
+        let mut ret = Source {
+            reg,
+            negate,
+            invert,
+            modifier: None,
+            swizzle: [0, 1, 2, 3],
+        };
+        for (sep, word) in suffixes {
+            if sep == '.' {
+                let comps = components(word)?;
+                let Some(&last) = comps.last() else {
+                    bail!("Empty swizzle: {s}");
+                };
+                if comps.len() > 4 {
+                    bail!("Invalid swizzle: {s}");
+                }
+                // Missing components repeat the last one
+                ret.swizzle = std::array::from_fn(|i| comps.get(i).copied().unwrap_or(last));
+                continue;
+            }
+            let modifier = match word {
+                "bias" => SourceModifier::Bias,
+                "bx2" => SourceModifier::SignedScale,
+                "x2" => SourceModifier::Scale2,
+                "dz" | "db" => SourceModifier::DivideZ,
+                "dw" | "da" => SourceModifier::DivideW,
+                _ => bail!("Invalid source modifier: _{word}"),
+            };
+            if ret.modifier.replace(modifier).is_some() {
+                bail!("Multiple source modifiers: {s}");
+            }
+        }
+        Ok(ret)
+    }
+}
+
+impl FromStr for Destination {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (reg, suffixes) = split_suffixes(s)?;
+        if !matches!(reg.kind, RegisterKind::Temp | RegisterKind::Texture) {
+            bail!("Invalid destination register: {s}");
+        }
+        let mut mask = [true; 4];
+        match suffixes.as_slice() {
+            [] => (),
+            [('.', word)] => {
+                let comps = components(word)?;
+                if comps.is_empty() || comps.windows(2).any(|w| w[0] >= w[1]) {
+                    bail!("Invalid write mask: {s}");
+                }
+                mask = std::array::from_fn(|i| comps.contains(&(i as u8)));
+            }
+            _ => bail!("Invalid destination: {s}"),
+        }
+        Ok(Destination { reg, mask })
+    }
+}
+
+fn parse_version(line: &str) -> Option<(u8, u8)> {
+    let rest = line.strip_prefix("ps")?;
+    let sep = rest.chars().next().filter(|c| *c == '.' || *c == '_')?;
+    let (major, minor) = rest[1..].split_once(sep)?;
+    Some((major.parse().ok()?, minor.parse().ok()?))
+}
+
+fn strip_comments(source: &str) -> String {
+    let mut ret = String::with_capacity(source.len());
+    let mut rest = source;
+    while let Some(start) = rest.find("/*") {
+        ret.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find("*/")
+            .map_or(rest.len(), |end| start + end + 2);
+        // Keep line numbers intact
+        ret.extend(rest[start..end].chars().filter(|c| *c == '\n'));
+        rest = &rest[end..];
     }
+    ret.push_str(rest);
+    ret
 }
 
-fn parse(path: &str) -> Result<()> {
-    let data = fs::read_to_string(path)?;
-    for line in data.lines() {
-        let mut line = line.trim().split("//");
-        let line = line.next().unwrap_or_default();
-        if line.is_empty() || line.starts_with("ps.") {
+fn parse_statement(line: &str, version: (u8, u8)) -> Result<Statement> {
+    let (coissue, line) = line
+        .strip_prefix('+')
+        .map_or((false, line), |line| (true, line.trim_start()));
+    let (name, args) = line
+        .split_once(|c: char| c.is_ascii_whitespace())
+        .unwrap_or((line, ""));
+    let args: Vec<String> = args
+        .split(',')
+        .map(|arg| arg.chars().filter(|c| !c.is_whitespace()).collect())
+        .filter(|arg: &String| !arg.is_empty())
+        .collect();
+    if name == "phase" {
+        if version < (1, 4) || !args.is_empty() {
+            bail!("Invalid phase instruction");
+        }
+        return Ok(Statement::Phase);
+    }
+    if name == "def" {
+        let [reg, values @ ..] = args.as_slice() else {
+            bail!("Missing register");
+        };
+        let reg: Register = reg.parse()?;
+        if reg.kind != RegisterKind::Constant || values.len() != 4 {
+            bail!("Expected def cN, x, y, z, w");
+        }
+        let mut value = [0.0; 4];
+        for (v, s) in value.iter_mut().zip(values) {
+            *v = s
+                .parse::<f32>()
+                .ok()
+                .filter(|v| v.is_finite())
+                .ok_or_else(|| anyhow!("Invalid value: {s}"))?;
+        }
+        return Ok(Statement::Def(reg, value));
+    }
+    let mut parts = name.split('_');
+    let name = parts.next().unwrap_or_default();
+    let &(_, opcode, num_args, new) = OPCODES
+        .iter()
+        .find(|(op, ..)| *op == name)
+        .ok_or_else(|| anyhow!("Unknown instruction: {name}"))?;
+    if new.is_some_and(|new| new != (version >= (1, 4))) {
+        bail!("{name} is not available in ps.{}.{}", version.0, version.1);
+    }
+    let mut scale = None;
+    let mut saturate = false;
+    for modifier in parts {
+        let value = match modifier {
+            "x2" => 2.0,
+            "x4" => 4.0,
+            "x8" => 8.0,
+            "d2" => 0.5,
+            "d4" => 0.25,
+            "d8" => 0.125,
+            "sat" => {
+                saturate = true;
+                continue;
+            }
+            _ => bail!("Invalid instruction modifier: _{modifier}"),
+        };
+        if scale.replace(value).is_some() {
+            bail!("Multiple scale modifiers");
+        }
+    }
+    if args.len() != num_args {
+        bail!("{name} takes {num_args} operands, got {}", args.len());
+    }
+    let (dst, src) = match args.split_first() {
+        Some((dst, src)) => (Some(dst.parse()?), src),
+        None => (None, &[][..]),
+    };
+    Ok(Statement::Instruction(Instruction {
+        opcode,
+        scale: scale.unwrap_or(1.0),
+        saturate,
+        coissue,
+        dst,
+        src: src.iter().map(|s| s.parse()).collect::<Result<_>>()?,
+    }))
+}
+
+/// Parses ps.1.1 to ps.1.4 assembly
+pub(crate) fn parse(source: &str) -> Result<Shader> {
+    let source = strip_comments(&source.to_ascii_lowercase());
+    let mut version = None;
+    let mut statements = vec![];
+    for (n, line) in source.lines().enumerate() {
+        let line = line.split("//").next().unwrap_or_default();
+        let line = line.split(';').next().unwrap_or_default().trim();
+        if line.is_empty() {
             continue;
         }
+        let res = match version {
+            None => parse_version(line)
+                .filter(|v| (1..=4).contains(&v.1) && v.0 == 1)
+                .map(|v| version = Some(v))
+                .ok_or_else(|| anyhow!("Expected ps.1.1 to ps.1.4, got {line}")),
+            Some(version) => parse_statement(line, version).map(|stmt| statements.push(stmt)),
+        };
+        res.with_context(|| format!("Line {}: {line}", n + 1))?;
+    }
+    Ok(Shader {
+        version: version.ok_or_else(|| anyhow!("Missing version"))?,
+        statements,
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Language {
+    Glsl,
+    Wgsl,
+}
+
+impl FromStr for Language {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "glsl" => Ok(Language::Glsl),
+            "wgsl" => Ok(Language::Wgsl),
+            _ => bail!("Unknown shader language: {s}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SamplerKind {
+    Texture2D,
+    Cube,
+}
+
+struct Emitter<'a> {
+    shader: &'a Shader,
+    lang: Language,
+    body: Vec<String>,
+    defined: BTreeMap<u8, [f32; 4]>,
+    constants: BTreeSet<u8>,
+    colors: BTreeSet<u8>,
+    texcoords: BTreeSet<u8>,
+    temps: BTreeSet<u8>,
+    /// t registers before ps.1.4
+    textures: BTreeSet<u8>,
+    samplers: BTreeMap<u8, SamplerKind>,
+    bump_env: BTreeSet<u8>,
+    bump_lum: BTreeSet<u8>,
+    /// Destinations of preceding texm3x2pad/texm3x3pad
+    pads: Vec<u8>,
+    tmp: usize,
+}
+
+fn float(v: f32) -> String {
+    format!("{v:?}")
+}
+
+fn swizzle(comps: &[u8]) -> String {
+    comps.iter().map(|&c| b"xyzw"[c as usize] as char).collect()
+}
+
+impl<'a> Emitter<'a> {
+    fn new(shader: &'a Shader, lang: Language) -> Self {
+        Self {
+            shader,
+            lang,
+            body: vec![],
+            defined: BTreeMap::new(),
+            constants: BTreeSet::new(),
+            colors: BTreeSet::new(),
+            texcoords: BTreeSet::new(),
+            temps: BTreeSet::from([0]),
+            textures: BTreeSet::new(),
+            samplers: BTreeMap::new(),
+            bump_env: BTreeSet::new(),
+            bump_lum: BTreeSet::new(),
+            pads: vec![],
+            tmp: 0,
+        }
+    }
+
+    fn vec(&self, n: usize, args: &str) -> String {
+        format!("{}({args})", self.ty(n))
+    }
+
+    fn ty(&self, n: usize) -> String {
+        match self.lang {
+            Language::Glsl => format!("vec{n}"),
+            Language::Wgsl => format!("vec{n}<f32>"),
+        }
+    }
+
+    fn let_(&mut self, n: usize, expr: String) -> String {
+        let name = format!("tmp{}", self.tmp);
+        self.tmp += 1;
+        let line = match self.lang {
+            Language::Glsl => format!("{} {name} = {expr};", self.ty(n)),
+            Language::Wgsl => format!("let {name} = {expr};"),
+        };
+        self.body.push(line);
+        name
+    }
+
+    fn saturate(&self, expr: &str) -> String {
+        match self.lang {
+            Language::Glsl => format!("clamp({expr}, 0.0, 1.0)"),
+            Language::Wgsl => format!("saturate({expr})"),
+        }
+    }
+
+    /// Picks `b` where `a` is greater than 0.5 (or greater or equal 0 with `cmp`), `c` otherwise
+    fn select(&self, cmp: bool, a: &str, b: &str, c: &str) -> String {
+        let (op, func, limit) = match cmp {
+            false => (">", "greaterThan", self.vec(4, "0.5")),
+            true => (">=", "greaterThanEqual", self.vec(4, "0.0")),
+        };
+        match self.lang {
+            Language::Glsl => format!("mix({c}, {b}, {func}({a}, {limit}))"),
+            Language::Wgsl => format!("select({c}, {b}, {a} {op} {limit})"),
+        }
+    }
+
+    fn sample(&mut self, stage: u8, kind: SamplerKind, coord: &str) -> Result<String> {
+        if *self.samplers.entry(stage).or_insert(kind) != kind {
+            bail!("Sampler {stage} used as 2D and cube texture");
+        }
+        Ok(match self.lang {
+            Language::Glsl => format!("texture(s{stage}, {coord})"),
+            Language::Wgsl => format!("textureSample(tex{stage}, samp{stage}, {coord})"),
+        })
+    }
+
+    fn texcoord(&mut self, n: u8) -> String {
+        self.texcoords.insert(n);
+        format!("tc{n}")
+    }
+
+    fn reg(&mut self, reg: Register) -> String {
+        let n = reg.index;
+        match reg.kind {
+            RegisterKind::Temp => {
+                self.temps.insert(n);
+                format!("r{n}")
+            }
+            RegisterKind::Constant => {
+                if !self.defined.contains_key(&n) {
+                    self.constants.insert(n);
+                }
+                format!("c{n}")
+            }
+            RegisterKind::Color => {
+                self.colors.insert(n);
+                format!("v{n}")
+            }
+            RegisterKind::Texture if self.shader.version >= (1, 4) => self.texcoord(n),
+            RegisterKind::Texture => {
+                self.textures.insert(n);
+                format!("t{n}")
+            }
+        }
+    }
+
+    /// Source with swizzle and negate/invert applied, `bias`: also apply _bias/_bx2/_x2
+    fn source(&mut self, src: &Source, bias: bool) -> Result<String> {
+        let mut ret = self.reg(src.reg);
+        if src.swizzle != [0, 1, 2, 3] {
+            ret = format!("{ret}.{}", swizzle(&src.swizzle));
+        }
+        ret = match (bias, src.modifier) {
+            (true, Some(SourceModifier::Bias)) => format!("({ret} - 0.5)"),
+            (true, Some(SourceModifier::SignedScale)) => format!("(2.0 * ({ret} - 0.5))"),
+            (true, Some(SourceModifier::Scale2)) => format!("(2.0 * {ret})"),
+            (true, Some(_)) => bail!("_dz/_dw are only valid for texld and texcrd"),
+            _ => ret,
+        };
+        if src.invert {
+            ret = format!("(1.0 - {ret})");
+        }
+        if src.negate {
+            ret = format!("(-{ret})");
+        }
+        Ok(ret)
+    }
+
+    /// Texture coordinate operand of texld/texcrd, with the projective divide applied
+    fn tex_source(&mut self, src: &Source, n: usize) -> Result<String> {
+        let value = self.source(src, false)?;
+        let value = match src.modifier {
+            Some(SourceModifier::DivideZ) => format!("({value}.xy / {value}.z)"),
+            Some(SourceModifier::DivideW) => format!("({value}.xy / {value}.w)"),
+            Some(_) => bail!("Invalid modifier for texture coordinates"),
+            None if n == 2 => return Ok(format!("{value}.xy")),
+            None => return Ok(format!("{value}.xyz")),
+        };
+        Ok(match n {
+            2 => value,
+            _ => self.vec(3, &format!("{value}, 1.0")),
+        })
+    }
+
+    /// Texture coordinate of the stage written by a ps.1.1-1.3 texture instruction
+    fn stage(&mut self, dst: &Destination) -> (u8, String) {
+        let n = dst.reg.index;
+        (n, self.texcoord(n))
+    }
+
+    /// Normal of texm3x2/texm3x3 from the preceding pad instructions
+    fn matrix_row(&mut self, pads: usize, dst: &Destination, src: &Source) -> Result<String> {
+        if self.pads.len() != pads {
+            bail!("Expected {pads} preceding pad instructions");
+        }
+        let (_, tc) = self.stage(dst);
+        let src = self.source(src, true)?;
+        let mut rows: Vec<String> = self.pads.drain(..).map(|n| format!("t{n}.x")).collect();
+        rows.push(format!("dot({tc}.xyz, {src}.xyz)"));
+        Ok(self.vec(rows.len(), &rows.join(", ")))
+    }
+
+    /// Result of an instruction before it is written to its destination
+    fn value(&mut self, inst: &Instruction) -> Result<Option<String>> {
+        let dst = inst.dst.as_ref();
+        let Some(dst) = dst else {
+            return Ok(None);
+        };
+        let src = |s: &mut Self, n: usize| s.source(&inst.src[n], true);
+        let value = match inst.opcode {
+            Opcode::Nop => return Ok(None),
+            Opcode::Mov => src(self, 0)?,
+            Opcode::Add => format!("{} + {}", src(self, 0)?, src(self, 1)?),
+            Opcode::Sub => format!("{} - {}", src(self, 0)?, src(self, 1)?),
+            Opcode::Mul => format!("{} * {}", src(self, 0)?, src(self, 1)?),
+            Opcode::Mad => format!("{} * {} + {}", src(self, 0)?, src(self, 1)?, src(self, 2)?),
+            Opcode::Lrp => {
+                let (a, b, c) = (src(self, 0)?, src(self, 1)?, src(self, 2)?);
+                format!("mix({c}, {b}, {a})")
+            }
+            Opcode::Cnd | Opcode::Cmp => {
+                let (a, b, c) = (src(self, 0)?, src(self, 1)?, src(self, 2)?);
+                self.select(inst.opcode == Opcode::Cmp, &a, &b, &c)
+            }
+            Opcode::Dp3 => {
+                let dot = format!("dot({}.xyz, {}.xyz)", src(self, 0)?, src(self, 1)?);
+                self.vec(4, &dot)
+            }
+            Opcode::Dp4 => {
+                let dot = format!("dot({}, {})", src(self, 0)?, src(self, 1)?);
+                self.vec(4, &dot)
+            }
+            Opcode::Bem => {
+                let n = dst.reg.index;
+                self.bump_env.insert(n);
+                let (a, b) = (src(self, 0)?, src(self, 1)?);
+                self.vec(4, &format!("{a}.xy + bump_env_mat{n} * {b}.xy, 0.0, 0.0"))
+            }
+            Opcode::Tex => {
+                let (n, tc) = self.stage(dst);
+                self.sample(n, SamplerKind::Texture2D, &format!("{tc}.xy"))?
+            }
+            Opcode::Texcoord => {
+                let (_, tc) = self.stage(dst);
+                let value = self.vec(4, &format!("{tc}.xyz, 1.0"));
+                self.saturate(&value)
+            }
+            Opcode::Texkill => {
+                let value = match self.shader.version < (1, 4) {
+                    true => self.stage(dst).1,
+                    false => self.reg(dst.reg),
+                };
+                let zero = self.vec(3, "0.0");
+                self.body.push(match self.lang {
+                    Language::Glsl => {
+                        format!("if (any(lessThan({value}.xyz, {zero}))) discard;")
+                    }
+                    Language::Wgsl => format!("if any({value}.xyz < {zero}) {{ discard; }}"),
+                });
+                return Ok(None);
+            }
+            Opcode::Texbem | Opcode::Texbeml => {
+                let (n, tc) = self.stage(dst);
+                let du_dv = src(self, 0)?;
+                self.bump_env.insert(n);
+                let coord = format!("{tc}.xy + bump_env_mat{n} * {du_dv}.xy");
+                let value = self.sample(n, SamplerKind::Texture2D, &coord)?;
+                if inst.opcode == Opcode::Texbem {
+                    value
+                } else {
+                    self.bump_lum.insert(n);
+                    let value = self.let_(4, value);
+                    let lum = format!("{du_dv}.z * bump_lum{n}.x + bump_lum{n}.y");
+                    self.vec(4, &format!("{value}.xyz * ({lum}), {value}.w"))
+                }
+            }
+            Opcode::Texm3x2pad | Opcode::Texm3x3pad => {
+                let (n, tc) = self.stage(dst);
+                let dot = format!("dot({tc}.xyz, {}.xyz)", src(self, 0)?);
+                self.pads.push(n);
+                self.vec(4, &dot)
+            }
+            Opcode::Texm3x2tex => {
+                let coord = self.matrix_row(1, dst, &inst.src[0])?;
+                self.sample(dst.reg.index, SamplerKind::Texture2D, &coord)?
+            }
+            Opcode::Texm3x3 => {
+                let normal = self.matrix_row(2, dst, &inst.src[0])?;
+                self.vec(4, &format!("{normal}, 1.0"))
+            }
+            Opcode::Texm3x3tex => {
+                let normal = self.matrix_row(2, dst, &inst.src[0])?;
+                self.sample(dst.reg.index, SamplerKind::Cube, &normal)?
+            }
+            Opcode::Texm3x3spec | Opcode::Texm3x3vspec => {
+                let eye = match inst.opcode {
+                    Opcode::Texm3x3spec => format!("{}.xyz", src(self, 1)?),
+                    _ => {
+                        let rows: Vec<String> = self
+                            .pads
+                            .clone()
+                            .into_iter()
+                            .chain([dst.reg.index])
+                            .map(|n| format!("{}.w", self.texcoord(n)))
+                            .collect();
+                        self.vec(3, &rows.join(", "))
+                    }
+                };
+                let normal = self.matrix_row(2, dst, &inst.src[0])?;
+                let normal = self.let_(3, normal);
+                let eye = self.let_(3, eye);
+                let reflected = format!(
+                    "2.0 * dot({normal}, {eye}) / dot({normal}, {normal}) * {normal} - {eye}"
+                );
+                self.sample(dst.reg.index, SamplerKind::Cube, &reflected)?
+            }
+            Opcode::Texdp3 | Opcode::Texdp3tex => {
+                let (n, tc) = self.stage(dst);
+                let dot = format!("dot({tc}.xyz, {}.xyz)", src(self, 0)?);
+                match inst.opcode {
+                    Opcode::Texdp3 => self.vec(4, &dot),
+                    _ => {
+                        let coord = self.vec(2, &format!("{dot}, 0.0"));
+                        self.sample(n, SamplerKind::Texture2D, &coord)?
+                    }
+                }
+            }
+            Opcode::Texreg2ar | Opcode::Texreg2gb | Opcode::Texreg2rgb => {
+                let comps = match inst.opcode {
+                    Opcode::Texreg2ar => "wx",
+                    Opcode::Texreg2gb => "yz",
+                    // Volume textures are not supported, sampled as 2D
+                    _ => "xy",
+                };
+                let coord = format!("{}.{comps}", src(self, 0)?);
+                self.sample(dst.reg.index, SamplerKind::Texture2D, &coord)?
+            }
+            Opcode::Texm3x2depth | Opcode::Texdepth => {
+                bail!("Depth output is not supported")
+            }
+            Opcode::Texld => {
+                let coord = self.tex_source(&inst.src[0], 2)?;
+                self.sample(dst.reg.index, SamplerKind::Texture2D, &coord)?
+            }
+            Opcode::Texcrd => {
+                let coord = self.tex_source(&inst.src[0], 3)?;
+                self.vec(4, &format!("{coord}, 1.0"))
+            }
+        };
+        let value = match inst.scale {
+            1.0 => value,
+            scale => format!("({value}) * {}", float(scale)),
+        };
+        let value = match inst.saturate {
+            true => self.saturate(&value),
+            false => value,
+        };
+        Ok(Some(self.let_(4, value)))
+    }
+
+    fn assign(&mut self, dst: &Destination, value: &str) {
+        let name = self.reg(dst.reg);
+        let line = if dst.mask == [true; 4] {
+            format!("{name} = {value};")
+        } else {
+            let comps: Vec<String> = (0..4)
+                .map(|i| match dst.mask[i] {
+                    true => format!("{value}.{}", swizzle(&[i as u8])),
+                    false => format!("{name}.{}", swizzle(&[i as u8])),
+                })
+                .collect();
+            format!("{name} = {};", self.vec(4, &comps.join(", ")))
+        };
+        self.body.push(line);
+    }
+
+    fn emit_body(&mut self) -> Result<()> {
+        let statements = &self.shader.statements;
+        for statement in statements {
+            if let Statement::Def(reg, value) = statement {
+                self.defined.insert(reg.index, *value);
+            }
+        }
+        let instructions: Vec<&Instruction> = statements
+            .iter()
+            .filter_map(|statement| match statement {
+                Statement::Instruction(inst) => Some(inst),
+                _ => None,
+            })
+            .collect();
+        // Co-issued instructions read their operands before any of them writes
+        for group in instructions.chunk_by(|_, next| next.coissue) {
+            let mut results = vec![];
+            for inst in group {
+                if let (Some(dst), Some(value)) = (&inst.dst, self.value(inst)?) {
+                    results.push((dst, value));
+                }
+            }
+            for (dst, value) in results {
+                self.assign(dst, &value);
+            }
+        }
+        Ok(())
+    }
 
-        let cmd: Cmd = line.parse()?;
-        dbg!(cmd);
+    fn glsl(&self, header: &str) -> String {
+        let mut ret = format!("#version 330 core\n{header}");
+        for n in &self.colors {
+            let _ = writeln!(ret, "layout(location = {n}) in vec4 v{n};");
+        }
+        for n in &self.texcoords {
+            let _ = writeln!(ret, "layout(location = {}) in vec4 tc{n};", 2 + n);
+        }
+        for n in &self.constants {
+            let _ = writeln!(ret, "uniform vec4 c{n};");
+        }
+        for (n, value) in &self.defined {
+            let value: Vec<String> = value.iter().copied().map(float).collect();
+            let _ = writeln!(ret, "const vec4 c{n} = vec4({});", value.join(", "));
+        }
+        for (n, kind) in &self.samplers {
+            let ty = match kind {
+                SamplerKind::Texture2D => "sampler2D",
+                SamplerKind::Cube => "samplerCube",
+            };
+            let _ = writeln!(ret, "uniform {ty} s{n};");
+        }
+        for n in &self.bump_env {
+            let _ = writeln!(ret, "uniform mat2 bump_env_mat{n};");
+        }
+        for n in &self.bump_lum {
+            let _ = writeln!(ret, "uniform vec2 bump_lum{n};");
+        }
+        ret.push_str("layout(location = 0) out vec4 frag_color;\n\nvoid main() {\n");
+        for n in &self.temps {
+            let _ = writeln!(ret, "    vec4 r{n} = vec4(0.0);");
+        }
+        for n in &self.textures {
+            let _ = writeln!(ret, "    vec4 t{n} = vec4(0.0);");
+        }
+        for line in &self.body {
+            let _ = writeln!(ret, "    {line}");
+        }
+        ret.push_str("    frag_color = r0;\n}\n");
+        ret
+    }
+
+    fn wgsl(&self, header: &str) -> String {
+        let mut ret = header.to_owned();
+        let mut uniforms: Vec<(String, &str)> = vec![];
+        uniforms.extend(
+            self.constants
+                .iter()
+                .map(|n| (format!("c{n}"), "vec4<f32>")),
+        );
+        uniforms.extend(
+            self.bump_env
+                .iter()
+                .map(|n| (format!("bump_env_mat{n}"), "mat2x2<f32>")),
+        );
+        uniforms.extend(
+            self.bump_lum
+                .iter()
+                .map(|n| (format!("bump_lum{n}"), "vec2<f32>")),
+        );
+        if !uniforms.is_empty() {
+            ret.push_str("struct Uniforms {\n");
+            for (name, ty) in &uniforms {
+                let _ = writeln!(ret, "    {name}: {ty},");
+            }
+            ret.push_str("}\n\n@group(0) @binding(0) var<uniform> uniforms: Uniforms;\n");
+        }
+        for (n, value) in &self.defined {
+            let value: Vec<String> = value.iter().copied().map(float).collect();
+            let _ = writeln!(ret, "const c{n} = vec4<f32>({});", value.join(", "));
+        }
+        for (n, kind) in &self.samplers {
+            let ty = match kind {
+                SamplerKind::Texture2D => "texture_2d<f32>",
+                SamplerKind::Cube => "texture_cube<f32>",
+            };
+            let _ = writeln!(ret, "@group(1) @binding({}) var tex{n}: {ty};", 2 * n);
+            let _ = writeln!(
+                ret,
+                "@group(1) @binding({}) var samp{n}: sampler;",
+                2 * n + 1
+            );
+        }
+        let mut inputs: Vec<(u8, String)> = vec![];
+        inputs.extend(self.colors.iter().map(|&n| (n, format!("v{n}"))));
+        inputs.extend(self.texcoords.iter().map(|&n| (2 + n, format!("tc{n}"))));
+        if !inputs.is_empty() {
+            ret.push_str("\nstruct Input {\n");
+            for (location, name) in &inputs {
+                let _ = writeln!(ret, "    @location({location}) {name}: vec4<f32>,");
+            }
+            ret.push_str("}\n");
+        }
+        let args = match inputs.is_empty() {
+            true => "",
+            false => "input: Input",
+        };
+        let _ = writeln!(
+            ret,
+            "\n@fragment\nfn main({args}) -> @location(0) vec4<f32> {{"
+        );
+        for (name, _) in &uniforms {
+            let _ = writeln!(ret, "    let {name} = uniforms.{name};");
+        }
+        for (_, name) in &inputs {
+            let _ = writeln!(ret, "    let {name} = input.{name};");
+        }
+        for n in &self.temps {
+            let _ = writeln!(ret, "    var r{n} = vec4<f32>(0.0);");
+        }
+        for n in &self.textures {
+            let _ = writeln!(ret, "    var t{n} = vec4<f32>(0.0);");
+        }
+        for line in &self.body {
+            let _ = writeln!(ret, "    {line}");
+        }
+        ret.push_str("    return r0;\n}\n");
+        ret
+    }
+}
+
+impl Shader {
+    /// Fragment shader source equivalent to this shader, see the top of this file for the
+    /// interface
+    pub(crate) fn translate(&self, lang: Language) -> Result<String> {
+        let mut emitter = Emitter::new(self, lang);
+        emitter.emit_body()?;
+        let (major, minor) = self.version;
+        let header = format!("// Translated from ps.{major}.{minor}\n");
+        Ok(match lang {
+            Language::Glsl => emitter.glsl(&header),
+            Language::Wgsl => emitter.wgsl(&header),
+        })
     }
-    todo!()
 }
 
 #[cfg(test)]
 mod test {
+    use super::*;
+
+    const BUMP: &str = "
+        ps.1.1 // environment bump
+        /* base, bump
+           and environment */
+        def c1, 1.0, 0.5, 0, -1
+        tex t0
+        tex t1
+        texbem t2, t1
+        mul_x2 r0.rgb, t0, t2_bx2
+        +mov_sat r0.a, 1-c0.a
+        cnd r1, r0.a, -v0, c1
+        add r0, r0, r1
+    ";
+
+    #[test]
+    fn parse_ps11() {
+        let shader = parse(BUMP).unwrap();
+        assert_eq!(shader.version, (1, 1));
+        assert_eq!(shader.statements.len(), 8);
+        assert_eq!(
+            shader.statements[0],
+            Statement::Def(
+                Register {
+                    kind: RegisterKind::Constant,
+                    index: 1
+                },
+                [1.0, 0.5, 0.0, -1.0]
+            )
+        );
+        let Statement::Instruction(mul) = &shader.statements[4] else {
+            panic!("Expected instruction");
+        };
+        assert_eq!(
+            (mul.opcode, mul.scale, mul.coissue),
+            (Opcode::Mul, 2.0, false)
+        );
+        assert_eq!(mul.dst.as_ref().unwrap().mask, [true, true, true, false]);
+        assert_eq!(mul.src[1].modifier, Some(SourceModifier::SignedScale));
+        let Statement::Instruction(mov) = &shader.statements[5] else {
+            panic!("Expected instruction");
+        };
+        assert!(mov.coissue && mov.saturate && mov.src[0].invert);
+        assert_eq!(mov.src[0].swizzle, [3; 4]);
+
+        assert!(parse("ps.1.1\ntexld r0, t0").is_err());
+        assert!(parse("ps.1.4\nmul r0, r1").is_err());
+        assert!(parse("ps.1.4\nmul_x3 r0, r1, r2").is_err());
+        assert!(parse("mov r0, v0").is_err());
+    }
+
     #[test]
-    fn test() {
-        super::parse(r"E:\Games\Steam\steamapps\common\Scrapland\ext\bmp\glowmapmaskenvbump.psh")
-            .unwrap();
-        // super::parse(r"E:\Games\Steam\steamapps\common\Scrapland\ext\bmp\hologram.psh").unwrap();
+    fn translate() {
+        let shader = parse(BUMP).unwrap();
+        let glsl = shader.translate(Language::Glsl).unwrap();
+        assert!(glsl.starts_with("#version 330 core\n// Translated from ps.1.1\n"));
+        assert!(glsl.contains("uniform mat2 bump_env_mat2;"));
+        assert!(glsl.contains("const vec4 c1 = vec4(1.0, 0.5, 0.0, -1.0);"));
+        assert!(glsl.contains("vec4 tmp2 = texture(s2, tc2.xy + bump_env_mat2 * t1.xy);"));
+        // The co-issued mov is computed before r0 is written
+        assert!(glsl.contains(
+            "    vec4 tmp3 = (t0 * (2.0 * (t2 - 0.5))) * 2.0;\n    \
+             vec4 tmp4 = clamp((1.0 - c0.wwww), 0.0, 1.0);\n    \
+             r0 = vec4(tmp3.x, tmp3.y, tmp3.z, r0.w);\n    \
+             r0 = vec4(r0.x, r0.y, r0.z, tmp4.w);\n"
+        ));
+        assert!(glsl.contains("mix(c1, (-v0), greaterThan(r0.wwww, vec4(0.5)))"));
+
+        let wgsl = shader.translate(Language::Wgsl).unwrap();
+        assert!(wgsl.contains("    bump_env_mat2: mat2x2<f32>,\n"));
+        assert!(wgsl.contains("    @location(4) tc2: vec4<f32>,\n"));
+        assert!(
+            wgsl.contains("let tmp2 = textureSample(tex2, samp2, tc2.xy + bump_env_mat2 * t1.xy);")
+        );
+        assert!(wgsl.contains("select(c1, (-v0), r0.wwww > vec4<f32>(0.5))"));
+
+        let shader = parse(
+            "ps.1.4
+            texld r0, t0
+            texcrd r1.rgb, t1_dw.xyw
+            phase
+            texld r2, r1
+            cmp r0, r2, r0, r1",
+        )
+        .unwrap();
+        let glsl = shader.translate(Language::Glsl).unwrap();
+        assert!(glsl.contains("vec4 tmp1 = vec4(vec3((tc1.xyww.xy / tc1.xyww.w), 1.0), 1.0);"));
+        assert!(glsl.contains("texture(s2, r1.xy)"));
+        assert!(glsl.contains("mix(r1, r0, greaterThanEqual(r2, vec4(0.0)))"));
     }
 }
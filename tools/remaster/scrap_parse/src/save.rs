@@ -0,0 +1,270 @@
+//! Save games (.sav), a title, an id and a list of name/value pairs
+//!
+//! ```text
+//! title: PascalString
+//! id: PascalString
+//! num_vars: u32
+//! vars: [{name: PascalString, value: PascalString}; num_vars]
+//! ```
+//!
+//! JSON is `{"id": .., "title": .., "data": [[name, value], ..]}` with strings in the JSON form
+//! of [`PascalString`], so terminators and duplicate names survive a round trip. The layout
+//! of `tools/save_to_json.py` (`"data": {name: value}`) is accepted as well.
+use crate::{encode_latin1, PascalString};
+use anyhow::{bail, Result};
+use binrw::io::Cursor;
+use binrw::prelude::*;
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+
+#[binrw]
+#[derive(Debug, Clone)]
+struct SaveVar {
+    name: PascalString,
+    value: PascalString,
+}
+
+#[binrw]
+#[derive(Debug, Clone)]
+pub(crate) struct Save {
+    title: PascalString,
+    id: PascalString,
+    #[br(temp)]
+    #[bw(try_calc = u32::try_from(vars.len()))]
+    num_vars: u32,
+    #[br(count = num_vars)]
+    vars: Vec<SaveVar>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SaveJson {
+    id: PascalString,
+    title: PascalString,
+    data: SaveJsonVars,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum SaveJsonVars {
+    /// `[name, value]` pairs in file order
+    Pairs(Vec<(PascalString, PascalString)>),
+    /// Written by `tools/save_to_json.py`, duplicate names are already merged
+    Map(IndexMap<String, PascalString>),
+}
+
+/// Variable that differs between two saves, `None` if it is missing on that side
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct Change {
+    pub(crate) name: String,
+    pub(crate) old: Option<String>,
+    pub(crate) new: Option<String>,
+}
+
+fn pascal_string(string: &str, padding: Vec<u8>) -> Result<PascalString> {
+    encode_latin1(string, &[])?;
+    Ok(PascalString {
+        string: string.to_owned(),
+        padding,
+    })
+}
+
+impl Save {
+    pub(crate) fn from_bytes(data: &[u8]) -> Result<Self> {
+        let mut fh = Cursor::new(data);
+        let save: Save = fh.read_le()?;
+        if fh.position() != data.len() as u64 {
+            bail!(
+                "{} bytes of trailing data",
+                data.len() as u64 - fh.position()
+            );
+        }
+        Ok(save)
+    }
+
+    pub(crate) fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut fh = Cursor::new(vec![]);
+        fh.write_le(self)?;
+        Ok(fh.into_inner())
+    }
+
+    /// Strings without explicit padding get a single NUL terminator
+    pub(crate) fn from_json(data: &str) -> Result<Self> {
+        let json: SaveJson = serde_json::from_str(data)?;
+        let vars = match json.data {
+            SaveJsonVars::Pairs(vars) => vars,
+            SaveJsonVars::Map(vars) => vars
+                .into_iter()
+                .map(|(name, value)| Ok((pascal_string(&name, vec![0])?, value)))
+                .collect::<Result<_>>()?,
+        };
+        Ok(Save {
+            title: json.title,
+            id: json.id,
+            vars: vars
+                .into_iter()
+                .map(|(name, value)| SaveVar { name, value })
+                .collect(),
+        })
+    }
+
+    pub(crate) fn to_json(&self, pretty: bool) -> Result<String> {
+        Ok(match pretty {
+            true => serde_json::to_string_pretty(self)?,
+            false => serde_json::to_string(self)?,
+        })
+    }
+
+    pub(crate) fn title(&self) -> &str {
+        &self.title.string
+    }
+
+    pub(crate) fn id(&self) -> &str {
+        &self.id.string
+    }
+
+    pub(crate) fn vars(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.vars
+            .iter()
+            .map(|var| (var.name.string.as_str(), var.value.string.as_str()))
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<&str> {
+        self.vars()
+            .find(|(var, _)| *var == name)
+            .map(|(_, value)| value)
+    }
+
+    /// Updates `name` in place or appends it, new entries copy the string terminators used
+    /// by the existing ones
+    pub(crate) fn set(&mut self, name: &str, value: &str) -> Result<()> {
+        if let Some(var) = self.vars.iter_mut().find(|var| var.name.string == name) {
+            let padding = std::mem::take(&mut var.value.padding);
+            var.value = pascal_string(value, padding)?;
+            return Ok(());
+        }
+        let (name_padding, value_padding) = self
+            .vars
+            .first()
+            .map(|var| (var.name.padding.clone(), var.value.padding.clone()))
+            .unwrap_or_default();
+        self.vars.push(SaveVar {
+            name: pascal_string(name, name_padding)?,
+            value: pascal_string(value, value_padding)?,
+        });
+        Ok(())
+    }
+
+    /// Removes all entries named `name`, returns if there were any
+    pub(crate) fn remove(&mut self, name: &str) -> bool {
+        let len = self.vars.len();
+        self.vars.retain(|var| var.name.string != name);
+        self.vars.len() != len
+    }
+
+    /// Variables that were added, removed or changed in `new`, in the order of `self`
+    /// followed by the ones only in `new`
+    pub(crate) fn diff(&self, new: &Save) -> Vec<Change> {
+        let old: IndexMap<&str, &str> = self.vars().collect();
+        let new: IndexMap<&str, &str> = new.vars().collect();
+        old.keys()
+            .chain(new.keys().filter(|name| !old.contains_key(*name)))
+            .filter_map(|&name| {
+                let (old, new) = (old.get(name), new.get(name));
+                (old != new).then(|| Change {
+                    name: name.to_owned(),
+                    old: old.map(|v| v.to_string()),
+                    new: new.map(|v| v.to_string()),
+                })
+            })
+            .collect()
+    }
+}
+
+impl Serialize for Save {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        SaveJson {
+            id: self.id.clone(),
+            title: self.title.clone(),
+            data: SaveJsonVars::Pairs(
+                self.vars
+                    .iter()
+                    .map(|var| (var.name.clone(), var.value.clone()))
+                    .collect(),
+            ),
+        }
+        .serialize(serializer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn roundtrip_and_edit() {
+        let mut data = vec![];
+        for s in ["Outskirts", "save0"] {
+            data.extend((s.len() as u32).to_le_bytes());
+            data.extend(s.as_bytes());
+        }
+        data.extend(2u32.to_le_bytes());
+        for s in ["Money", "100\0", "Visited", "1\0"] {
+            data.extend((s.len() as u32).to_le_bytes());
+            data.extend(s.as_bytes());
+        }
+        let save = Save::from_bytes(&data).unwrap();
+        assert_eq!((save.title(), save.id()), ("Outskirts", "save0"));
+        assert_eq!(save.get("Money"), Some("100"));
+        assert_eq!(save.to_bytes().unwrap(), data);
+        assert!(Save::from_bytes(&[&data[..], &[0]].concat()).is_err());
+
+        let mut edited = save.clone();
+        edited.set("Money", "5000").unwrap();
+        edited.set("Dead", "0").unwrap();
+        assert!(edited.remove("Visited"));
+        assert!(edited.set("Name", "\u{263a}").is_err());
+        let diff = save.diff(&edited);
+        let diff: Vec<_> = diff
+            .iter()
+            .map(|c| (c.name.as_str(), c.old.as_deref(), c.new.as_deref()))
+            .collect();
+        assert_eq!(
+            diff,
+            [
+                ("Money", Some("100"), Some("5000")),
+                ("Visited", Some("1"), None),
+                ("Dead", None, Some("0")),
+            ]
+        );
+        let edited = Save::from_bytes(&edited.to_bytes().unwrap()).unwrap();
+        assert_eq!(edited.get("Dead"), Some("0"));
+
+        let json = save.to_json(false).unwrap();
+        let unterminated = |s: &str| format!(r#"{{"string":"{s}","padding":[]}}"#);
+        assert_eq!(
+            json,
+            format!(
+                r#"{{"id":{},"title":{},"data":[[{},"100"],[{},"1"]]}}"#,
+                unterminated("save0"),
+                unterminated("Outskirts"),
+                unterminated("Money"),
+                unterminated("Visited")
+            )
+        );
+        assert_eq!(Save::from_json(&json).unwrap().to_bytes().unwrap(), data);
+
+        // duplicate names are kept
+        let mut twice = save.clone();
+        twice.vars.push(twice.vars[0].clone());
+        let json = twice.to_json(false).unwrap();
+        assert_eq!(Save::from_json(&json).unwrap().vars.len(), 3);
+
+        let legacy = r#"{"id":"save0","title":"Outskirts","data":{"Money":"100"}}"#;
+        let legacy = Save::from_json(legacy).unwrap();
+        assert_eq!(legacy.get("Money"), Some("100"));
+        assert_eq!(legacy.vars[0].value.padding, [0]);
+    }
+}
@@ -0,0 +1,23 @@
+//! Conversion between Scrapland space (left handed, Y-up, millimeters) and glTF space
+//! (right handed, Y-up, meters)
+
+/// Scrapland units are millimeters, glTF uses meters
+const MODEL_SCALE: f32 = 1000.0;
+
+pub(crate) fn to_gltf_pos([x, y, z]: [f32; 3]) -> [f32; 3] {
+    [x / MODEL_SCALE, y / MODEL_SCALE, -z / MODEL_SCALE]
+}
+
+pub(crate) fn from_gltf_pos([x, y, z]: [f32; 3]) -> [f32; 3] {
+    [x * MODEL_SCALE, y * MODEL_SCALE, -z * MODEL_SCALE]
+}
+
+/// Mirrors a direction along Z, its own inverse
+pub(crate) fn convert_dir([x, y, z]: [f32; 3]) -> [f32; 3] {
+    [x, y, -z]
+}
+
+/// Mirrors a rotation quaternion along Z, its own inverse
+pub(crate) fn convert_rot([x, y, z, w]: [f32; 4]) -> [f32; 4] {
+    [-x, -y, z, w]
+}
@@ -0,0 +1,90 @@
+//! Cutting MST sprite sheets into single sprites and putting them back together
+use crate::{Tile, MST};
+use anyhow::{bail, Result};
+use image::{imageops, RgbaImage};
+
+/// Pixel rectangle (x, y, width, height) of `tile` in a sheet of `width` x `height`
+///
+/// Tile coordinates refer to `MST.image_size` and are scaled if the texture was resized.
+fn rect(mst: &MST, tile: &Tile, (width, height): (u32, u32)) -> (u32, u32, u32, u32) {
+    let [base_w, base_h] = mst.image_size.map(|v| v.max(1));
+    let scale =
+        |v: u16, size: u32, base: u32| (u64::from(v) * u64::from(size) / u64::from(base)) as u32;
+    let x = scale(tile.pos[0], width, base_w).min(width);
+    let y = scale(tile.pos[1], height, base_h).min(height);
+    let w = scale(tile.size[0], width, base_w).min(width - x);
+    let h = scale(tile.size[1], height, base_h).min(height - y);
+    (x, y, w, h)
+}
+
+pub(crate) fn slice(mst: &MST, sheet: &RgbaImage) -> Vec<RgbaImage> {
+    mst.tiles
+        .iter()
+        .map(|tile| {
+            let (x, y, w, h) = rect(mst, tile, sheet.dimensions());
+            imageops::crop_imm(sheet, x, y, w, h).to_image()
+        })
+        .collect()
+}
+
+/// Pastes `sprites` (in tile order, `None` keeps the original) onto a copy of `sheet`
+pub(crate) fn build(
+    mst: &MST,
+    sheet: &RgbaImage,
+    sprites: &[Option<RgbaImage>],
+) -> Result<RgbaImage> {
+    if sprites.len() > mst.tiles.len() {
+        bail!("{} sprites for {} tiles", sprites.len(), mst.tiles.len());
+    }
+    let mut ret = sheet.clone();
+    for (n, (tile, sprite)) in mst.tiles.iter().zip(sprites).enumerate() {
+        let Some(sprite) = sprite else {
+            continue;
+        };
+        let (x, y, w, h) = rect(mst, tile, sheet.dimensions());
+        if sprite.dimensions() != (w, h) {
+            bail!(
+                "Sprite {n} is {}x{}, tile is {w}x{h}",
+                sprite.width(),
+                sprite.height()
+            );
+        }
+        imageops::replace(&mut ret, sprite, x.into(), y.into());
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn slice_and_build() {
+        let mst = MST {
+            version: 100,
+            image_size: [4, 4],
+            tiles: vec![
+                Tile {
+                    pos: [0, 0],
+                    size: [2, 2],
+                },
+                Tile {
+                    pos: [2, 1],
+                    size: [2, 3],
+                },
+            ],
+        };
+        // Texture stored at twice the size the MST was made for
+        let sheet = RgbaImage::from_fn(8, 8, |x, y| Rgba([x as u8, y as u8, 0, 255]));
+        let sprites = slice(&mst, &sheet);
+        assert_eq!(sprites[1].dimensions(), (4, 6));
+        assert_eq!(sprites[1].get_pixel(0, 0), &Rgba([4, 2, 0, 255]));
+
+        let edited = RgbaImage::from_pixel(4, 6, Rgba([255; 4]));
+        let rebuilt = build(&mst, &sheet, &[None, Some(edited)]).unwrap();
+        assert_eq!(rebuilt.get_pixel(4, 2), &Rgba([255; 4]));
+        assert_eq!(rebuilt.get_pixel(1, 1), sheet.get_pixel(1, 1));
+        assert!(build(&mst, &sheet, &[Some(RgbaImage::new(1, 1))]).is_err());
+    }
+}
@@ -0,0 +1,9 @@
+//! Helpers shared by the unit tests
+
+/// Chunk with `magic` and `body`, as [`crate::chunks::walk`] expects it
+pub(crate) fn chunk(magic: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut data = magic.to_vec();
+    data.extend(u32::try_from(body.len()).unwrap().to_le_bytes());
+    data.extend(body);
+    data
+}
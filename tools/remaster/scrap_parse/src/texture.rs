@@ -0,0 +1,431 @@
+//! Texture decoding to RGBA
+//!
+//! DDS is decoded here instead of by the `image` crate since it ignores the 1-bit alpha of
+//! DXT1 and doesn't support uncompressed surfaces, everything else goes through `image`.
+//! Textures can have their alpha channel in a separate grayscale `<name>.alpha.dds`
+//! (or `.alpha.tga`) next to them which is merged by [`load`].
+use anyhow::{anyhow, bail, Context, Result};
+use binrw::binread;
+use binrw::io::Cursor;
+use binrw::prelude::*;
+use image::{imageops, DynamicImage, ImageFormat, RgbaImage};
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use vfs::VfsPath;
+
+const ALPHA_EXTS: &[&str] = &["alpha.dds", "alpha.tga"];
+
+const DDPF_ALPHAPIXELS: u32 = 0x1;
+const DDPF_ALPHA: u32 = 0x2;
+const DDPF_FOURCC: u32 = 0x4;
+const DDPF_LUMINANCE: u32 = 0x20000;
+
+#[binread]
+#[derive(Debug)]
+struct PixelFormat {
+    #[br(temp, assert(size == 32, "Invalid DDS pixel format size"))]
+    size: u32,
+    flags: u32,
+    fourcc: [u8; 4],
+    bit_count: u32,
+    /// R, G, B and A bit masks
+    masks: [u32; 4],
+}
+
+#[binread]
+#[br(magic = b"DDS ")]
+#[derive(Debug)]
+struct DdsHeader {
+    #[br(temp, assert(size == 124, "Invalid DDS header size"))]
+    size: u32,
+    _flags: u32,
+    height: u32,
+    width: u32,
+    _pitch: u32,
+    _depth: u32,
+    _mip_count: u32,
+    _reserved: [u32; 11],
+    pixel_format: PixelFormat,
+    _caps: [u32; 4],
+    _reserved_2: u32,
+}
+
+fn rgb565(value: u16) -> [u8; 3] {
+    let expand =
+        |v: u16, bits: u32| ((u32::from(v) * 255 + (1 << (bits - 1))) / ((1 << bits) - 1)) as u8;
+    [
+        expand(value >> 11, 5),
+        expand((value >> 5) & 0x3f, 6),
+        expand(value & 0x1f, 5),
+    ]
+}
+
+/// Decodes the color part of a DXT block into `block` (4x4 RGBA, row major)
+fn color_block(data: &[u8], allow_transparent: bool, block: &mut [[u8; 4]; 16]) {
+    let c0 = u16::from_le_bytes([data[0], data[1]]);
+    let c1 = u16::from_le_bytes([data[2], data[3]]);
+    let indices = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
+    let (a, b) = (rgb565(c0), rgb565(c1));
+    let mix = |wa: u16, wb: u16| -> [u8; 4] {
+        let ch = |i: usize| ((u16::from(a[i]) * wa + u16::from(b[i]) * wb) / (wa + wb)) as u8;
+        [ch(0), ch(1), ch(2), 255]
+    };
+    let palette = if c0 > c1 || !allow_transparent {
+        [
+            [a[0], a[1], a[2], 255],
+            [b[0], b[1], b[2], 255],
+            mix(2, 1),
+            mix(1, 2),
+        ]
+    } else {
+        [
+            [a[0], a[1], a[2], 255],
+            [b[0], b[1], b[2], 255],
+            mix(1, 1),
+            [0; 4],
+        ]
+    };
+    for (i, pixel) in block.iter_mut().enumerate() {
+        *pixel = palette[(indices >> (i * 2)) as usize & 3];
+    }
+}
+
+fn dxt3_alpha(data: &[u8], block: &mut [[u8; 4]; 16]) {
+    let alpha = u64::from_le_bytes(data[..8].try_into().unwrap());
+    for (i, pixel) in block.iter_mut().enumerate() {
+        pixel[3] = ((alpha >> (i * 4)) & 0xf) as u8 * 0x11;
+    }
+}
+
+fn dxt5_alpha(data: &[u8], block: &mut [[u8; 4]; 16]) {
+    let (a0, a1) = (u16::from(data[0]), u16::from(data[1]));
+    let mut palette = [0u8; 8];
+    palette[0] = a0 as u8;
+    palette[1] = a1 as u8;
+    if a0 > a1 {
+        for i in 1..7 {
+            palette[i + 1] = ((a0 * (7 - i as u16) + a1 * i as u16) / 7) as u8;
+        }
+    } else {
+        for i in 1..5 {
+            palette[i + 1] = ((a0 * (5 - i as u16) + a1 * i as u16) / 5) as u8;
+        }
+        palette[7] = 255;
+    }
+    let mut indices = [0u8; 8];
+    indices[..6].copy_from_slice(&data[2..8]);
+    let indices = u64::from_le_bytes(indices);
+    for (i, pixel) in block.iter_mut().enumerate() {
+        pixel[3] = palette[(indices >> (i * 3)) as usize & 7];
+    }
+}
+
+fn decode_dxt(header: &DdsHeader, data: &[u8]) -> Result<RgbaImage> {
+    let block_size = match &header.pixel_format.fourcc {
+        b"DXT1" => 8,
+        b"DXT2" | b"DXT3" | b"DXT4" | b"DXT5" => 16,
+        fourcc => bail!(
+            "Unsupported DDS FourCC {:?}",
+            String::from_utf8_lossy(fourcc)
+        ),
+    };
+    let (width, height) = (header.width, header.height);
+    let blocks_x = width.div_ceil(4) as usize;
+    let blocks_y = height.div_ceil(4) as usize;
+    if data.len() < blocks_x * blocks_y * block_size {
+        bail!("DDS data truncated");
+    }
+    let mut image = RgbaImage::new(width, height);
+    let mut block = [[0u8; 4]; 16];
+    for (n, data) in data
+        .chunks_exact(block_size)
+        .take(blocks_x * blocks_y)
+        .enumerate()
+    {
+        match &header.pixel_format.fourcc {
+            b"DXT1" => color_block(data, true, &mut block),
+            b"DXT2" | b"DXT3" => {
+                color_block(&data[8..], false, &mut block);
+                dxt3_alpha(data, &mut block);
+            }
+            _ => {
+                color_block(&data[8..], false, &mut block);
+                dxt5_alpha(data, &mut block);
+            }
+        }
+        let (bx, by) = ((n % blocks_x) as u32 * 4, (n / blocks_x) as u32 * 4);
+        for (i, pixel) in block.iter().enumerate() {
+            let (x, y) = (bx + (i % 4) as u32, by + (i / 4) as u32);
+            if x < width && y < height {
+                image.put_pixel(x, y, image::Rgba(*pixel));
+            }
+        }
+    }
+    Ok(image)
+}
+
+fn decode_uncompressed(header: &DdsHeader, data: &[u8]) -> Result<RgbaImage> {
+    let format = &header.pixel_format;
+    let bytes = match format.bit_count {
+        8 | 16 | 24 | 32 => (format.bit_count / 8) as usize,
+        bits => bail!("Unsupported DDS bit count: {bits}"),
+    };
+    let (width, height) = (header.width, header.height);
+    if data.len() < width as usize * height as usize * bytes {
+        bail!("DDS data truncated");
+    }
+    let channel = |value: u32, mask: u32| -> Option<u8> {
+        if mask == 0 {
+            return None;
+        }
+        let shift = mask.trailing_zeros();
+        let max = u64::from(mask >> shift);
+        Some((u64::from((value & mask) >> shift) * 255 / max) as u8)
+    };
+    let [r, g, b, a] = format.masks;
+    let has_alpha = format.flags & (DDPF_ALPHAPIXELS | DDPF_ALPHA) != 0;
+    let mut pixels = data.chunks_exact(bytes);
+    Ok(RgbaImage::from_fn(width, height, |_, _| {
+        let mut value = [0u8; 4];
+        value[..bytes].copy_from_slice(pixels.next().unwrap_or(&[0; 4][..bytes]));
+        let value = u32::from_le_bytes(value);
+        let alpha = has_alpha
+            .then(|| channel(value, a))
+            .flatten()
+            .unwrap_or(255);
+        let pixel = if format.flags & DDPF_LUMINANCE != 0 {
+            let l = channel(value, r).unwrap_or(255);
+            [l, l, l, alpha]
+        } else {
+            [
+                channel(value, r).unwrap_or(255),
+                channel(value, g).unwrap_or(255),
+                channel(value, b).unwrap_or(255),
+                alpha,
+            ]
+        };
+        image::Rgba(pixel)
+    }))
+}
+
+/// Decodes the top mip level of a DDS file
+pub(crate) fn decode_dds(data: &[u8]) -> Result<RgbaImage> {
+    let mut cursor = Cursor::new(data);
+    let header: DdsHeader = cursor.read_le()?;
+    let data = &data[cursor.position() as usize..];
+    if header.pixel_format.flags & DDPF_FOURCC != 0 {
+        decode_dxt(&header, data)
+    } else {
+        decode_uncompressed(&header, data)
+    }
+}
+
+/// Decodes a texture, `ext` is the file extension of the original file
+pub(crate) fn decode(data: &[u8], ext: &str) -> Result<RgbaImage> {
+    if ext.eq_ignore_ascii_case("dds") {
+        return decode_dds(data);
+    }
+    let format =
+        ImageFormat::from_extension(ext).ok_or_else(|| anyhow!("Unknown texture format: {ext}"))?;
+    Ok(image::load_from_memory_with_format(data, format)?.into_rgba8())
+}
+
+fn decode_file(path: &VfsPath) -> Result<RgbaImage> {
+    let ext = path.extension().unwrap_or_default();
+    let mut data = vec![];
+    path.open_file()?.read_to_end(&mut data)?;
+    decode(&data, &ext).with_context(|| path.as_str().to_owned())
+}
+
+fn is_alpha_map(path: &str) -> bool {
+    let path = path.to_ascii_lowercase();
+    ALPHA_EXTS
+        .iter()
+        .any(|ext| path.ends_with(&format!(".{ext}")))
+}
+
+/// Separate alpha map belonging to the texture at `path`, if there is one
+pub(crate) fn alpha_path(path: &VfsPath) -> Option<VfsPath> {
+    if is_alpha_map(path.as_str()) {
+        return None;
+    }
+    let filename = path.filename();
+    let stem = filename
+        .rsplit_once('.')
+        .map_or(filename.as_str(), |(stem, _)| stem);
+    let parent = path.parent();
+    ALPHA_EXTS
+        .iter()
+        .filter_map(|ext| parent.join(format!("{stem}.{ext}")).ok())
+        .find(|path| path.exists().unwrap_or(false))
+}
+
+/// Replaces the alpha channel of `image` with the brightness of `alpha`, which is scaled
+/// if the sizes differ
+pub(crate) fn merge_alpha(image: &mut RgbaImage, alpha: &RgbaImage) {
+    let mut alpha = DynamicImage::ImageRgba8(alpha.clone()).into_luma8();
+    if alpha.dimensions() != image.dimensions() {
+        alpha = imageops::resize(
+            &alpha,
+            image.width(),
+            image.height(),
+            imageops::FilterType::Triangle,
+        );
+    }
+    for (pixel, alpha) in image.pixels_mut().zip(alpha.pixels()) {
+        pixel[3] = alpha[0];
+    }
+}
+
+/// Decodes the texture at `path` with its separate alpha map merged in
+pub(crate) fn load(path: &VfsPath) -> Result<RgbaImage> {
+    let mut image = decode_file(path)?;
+    if let Some(alpha) = alpha_path(path) {
+        merge_alpha(&mut image, &decode_file(&alpha)?);
+    }
+    Ok(image)
+}
+
+pub(crate) fn is_texture(path: &str) -> bool {
+    let path = path.to_ascii_lowercase();
+    ["png", "bmp", "dds", "tga"]
+        .iter()
+        .any(|ext| path.ends_with(&format!(".{ext}")))
+}
+
+/// Textures that failed to decode with their error
+pub(crate) type DecodeFailures = Vec<(String, anyhow::Error)>;
+
+/// Converts all textures among resolved dependencies (as returned by `resolve_deps`) to
+/// PNG files in `out_dir`, keeping their path in the archive, returns the written file
+/// for each dependency and the textures that failed to decode
+pub(crate) fn export_png(
+    dependencies: &HashMap<String, String>,
+    root: &VfsPath,
+    out_dir: &Path,
+) -> Result<(HashMap<String, PathBuf>, DecodeFailures)> {
+    let mut ret = HashMap::new();
+    let mut failed = vec![];
+    let mut written: HashMap<&str, PathBuf> = HashMap::new();
+    for (dep, path) in dependencies {
+        if !is_texture(path) {
+            continue;
+        }
+        if let Some(out_path) = written.get(path.as_str()) {
+            ret.insert(dep.clone(), out_path.clone());
+            continue;
+        }
+        let out_path = out_dir
+            .join(path.trim_start_matches('/'))
+            .with_extension("png");
+        let image = match load(&root.join(path)?) {
+            Ok(image) => image,
+            Err(e) => {
+                failed.push((path.clone(), e));
+                continue;
+            }
+        };
+        if let Some(parent) = out_path.parent() {
+            fs_err::create_dir_all(parent)?;
+        }
+        image.save(&out_path)?;
+        written.insert(path, out_path.clone());
+        ret.insert(dep.clone(), out_path);
+    }
+    Ok((ret, failed))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn dds(fourcc: &[u8; 4], flags: u32, bit_count: u32, masks: [u32; 4], size: u32) -> Vec<u8> {
+        let mut data = b"DDS ".to_vec();
+        let header = [124, 0x1007, size, size, 0, 0, 1]
+            .into_iter()
+            .chain([0; 11])
+            .chain([32, flags, u32::from_le_bytes(*fourcc), bit_count])
+            .chain(masks)
+            .chain([0x1000, 0, 0, 0, 0]);
+        header.for_each(|v: u32| data.extend(v.to_le_bytes()));
+        data
+    }
+
+    #[test]
+    fn dds_formats() {
+        // DXT1 3-color mode: red, blue, mix and transparent
+        let mut data = dds(b"DXT1", DDPF_FOURCC, 0, [0; 4], 4);
+        data.extend(0x001fu16.to_le_bytes());
+        data.extend(0xf800u16.to_le_bytes());
+        data.extend(0b11_10_01_00u32.to_le_bytes());
+        let image = decode_dds(&data).unwrap();
+        assert_eq!(image.get_pixel(0, 0).0, [0, 0, 255, 255]);
+        assert_eq!(image.get_pixel(1, 0).0, [255, 0, 0, 255]);
+        assert_eq!(image.get_pixel(2, 0).0, [127, 0, 127, 255]);
+        assert_eq!(image.get_pixel(3, 0).0, [0; 4]);
+        assert_eq!(image.get_pixel(0, 1).0, [0, 0, 255, 255]);
+
+        // DXT5 with interpolated alpha over a 1x1 image
+        let mut data = dds(b"DXT5", DDPF_FOURCC, 0, [0; 4], 1);
+        data.extend([255, 0, 0b010, 0, 0, 0, 0, 0]);
+        data.extend([0xff; 4]);
+        data.extend([0; 4]);
+        let image = decode_dds(&data).unwrap();
+        assert_eq!(image.dimensions(), (1, 1));
+        assert_eq!(image.get_pixel(0, 0).0, [255, 255, 255, 218]);
+
+        // A1R5G5B5
+        let masks = [0x7c00, 0x3e0, 0x1f, 0x8000];
+        let mut data = dds(&[0; 4], 0x40 | DDPF_ALPHAPIXELS, 16, masks, 1);
+        data.extend(0x7c00u16.to_le_bytes());
+        assert_eq!(decode_dds(&data).unwrap().get_pixel(0, 0).0, [255, 0, 0, 0]);
+    }
+
+    #[test]
+    fn alpha_map() {
+        let fs: VfsPath = vfs::MemoryFS::new().into();
+        let tga = |color: [u8; 4]| {
+            let mut data = std::io::Cursor::new(vec![]);
+            RgbaImage::from_pixel(2, 2, image::Rgba(color))
+                .write_to(&mut data, ImageFormat::Tga)
+                .unwrap();
+            data.into_inner()
+        };
+        fs.join("tex.tga")
+            .unwrap()
+            .create_file()
+            .unwrap()
+            .write_all(&tga([10, 20, 30, 255]))
+            .unwrap();
+        fs.join("tex.alpha.tga")
+            .unwrap()
+            .create_file()
+            .unwrap()
+            .write_all(&tga([128, 128, 128, 255]))
+            .unwrap();
+        let tex = fs.join("tex.tga").unwrap();
+        assert_eq!(alpha_path(&tex).unwrap().filename(), "tex.alpha.tga");
+        assert!(alpha_path(&alpha_path(&tex).unwrap()).is_none());
+        assert_eq!(load(&tex).unwrap().get_pixel(1, 1).0, [10, 20, 30, 128]);
+    }
+
+    #[test]
+    fn export_failures() {
+        let fs: VfsPath = vfs::MemoryFS::new().into();
+        fs.join("broken.dds")
+            .unwrap()
+            .create_file()
+            .unwrap()
+            .write_all(b"DDS garbage")
+            .unwrap();
+        let deps = HashMap::from([
+            ("broken.dds".to_owned(), "/broken.dds".to_owned()),
+            ("model.sm3".to_owned(), "/model.sm3".to_owned()),
+        ]);
+        let (written, failed) = export_png(&deps, &fs, Path::new("unused")).unwrap();
+        assert!(written.is_empty());
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].0, "/broken.dds");
+    }
+}